@@ -272,7 +272,7 @@ impl Cli {
                         Self::fill_with(&mut fixture, &chunker, fill_paths)?;
 
                         let measurement = fixture.measure(&dataset, chunker.clone())?;
-                        distributions.push(fixture.size_distribution(adjustment));
+                        distributions.push(fixture.size_distribution(adjustment)?);
 
                         measurements.push(measurement)
                     }
@@ -281,7 +281,7 @@ impl Cli {
 
                     for _ in 0..*count {
                         let measurement = fixture.measure(&dataset, chunker.clone())?;
-                        distributions.push(fixture.size_distribution(adjustment));
+                        distributions.push(fixture.size_distribution(adjustment)?);
                         measurements.push(measurement)
                     }
                 };
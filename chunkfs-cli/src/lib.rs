@@ -22,7 +22,7 @@ pub fn measure_datasets(datasets: &[Dataset], chunkers: &[ChunkerRef]) -> io::Re
         measurement.write_to_csv("measurements.csv")?;
 
         for adjustment in [100, 500, 1000] {
-            let map = fixture.size_distribution(adjustment);
+            let map = fixture.size_distribution(adjustment)?;
 
             save_distribution(&dataset.name, chunker, adjustment, map)?;
         }
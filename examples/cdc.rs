@@ -21,7 +21,7 @@ fn main() -> io::Result<()> {
     let measurements = fs.close_file(file)?;
     println!("{:?}", measurements);
 
-    println!("{}", fs.cdc_dedup_ratio());
+    println!("{}", fs.cdc_dedup_ratio()?);
 
     let file = fs.open_file_readonly("file")?;
     let read = fs.read_file_complete(&file)?;
@@ -20,6 +20,6 @@ fn main() -> io::Result<()> {
     let dataset = Dataset::new("../new", "dataset")?;
     let measurement = fixture.measure(&dataset, SuperChunker::default())?;
     println!("measurement: {:?}", measurement);
-    println!("dedup ratio: {}", fixture.fs.cdc_dedup_ratio());
+    println!("dedup ratio: {}", fixture.fs.cdc_dedup_ratio()?);
     Ok(())
 }
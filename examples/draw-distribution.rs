@@ -17,7 +17,7 @@ fn main() -> io::Result<()> {
     fixture.measure(&dataset, SuperChunker::default())?;
 
     for adjustment in [100, 500, 1000] {
-        let map = fixture.size_distribution(adjustment);
+        let map = fixture.size_distribution(adjustment)?;
 
         let pairs = map.into_iter().collect::<Vec<(usize, u32)>>();
 
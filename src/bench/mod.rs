@@ -6,12 +6,12 @@ use std::fmt::Debug;
 use std::fs::File;
 use std::io;
 use std::io::Read as _;
-use std::time::{Duration, Instant};
+use std::time::Instant;
 use uuid::Uuid;
 
 use crate::system::file_layer::FileHandle;
 use crate::{
-    create_cdc_filesystem, ChunkHash, ChunkerRef, DataContainer, FileSystem, Hasher,
+    create_cdc_filesystem, CacheStats, ChunkHash, ChunkerRef, DataContainer, FileSystem, Hasher,
     IterableDatabase, WriteMeasurements, MB,
 };
 
@@ -109,16 +109,21 @@ where
             save_time,
             chunk_time,
             hash_time,
+            compress_time,
+            encrypt_time,
+            ..
         } = self.fs.close_file(file)?;
 
-        let read_time = self.verify(dataset, &uuid)?;
+        let (read, cache_stats) = self.verify(dataset, &uuid)?;
 
         let measurement = TimeMeasurement {
             write_time,
-            read_time,
             save_time,
             chunk_time,
             hash_time,
+            compress_time,
+            encrypt_time,
+            ..read
         };
 
         let throughput = Throughput::new(dataset.size, measurement);
@@ -130,9 +135,11 @@ where
             chunker: chunker_name,
             measurement,
             throughput,
-            dedup_ratio: self.fs.cdc_dedup_ratio(),
-            full_dedup_ratio: self.fs.full_cdc_dedup_ratio(),
-            avg_chunk_size: self.fs.average_chunk_size(),
+            cache_stats,
+            dedup_ratio: self.fs.cdc_dedup_ratio()?,
+            full_dedup_ratio: self.fs.full_cdc_dedup_ratio()?,
+            compression_ratio: self.fs.compression_ratio()?,
+            avg_chunk_size: self.fs.average_chunk_size()?,
             size: dataset.size,
             path: dataset.path.clone(),
             chunk_count: self.chunk_count(),
@@ -207,7 +214,7 @@ where
 
         Ok(DedupMeasurement {
             name: dataset.name.to_string(),
-            dedup_ratio: self.fs.cdc_dedup_ratio(),
+            dedup_ratio: self.fs.cdc_dedup_ratio()?,
         })
     }
 
@@ -217,32 +224,38 @@ where
     /// i.e. the size step in the distribution.
     ///
     /// Does not modify the database, i.e. does not clear it.
-    pub fn size_distribution(&self, adjustment: usize) -> HashMap<usize, u32> {
+    pub fn size_distribution(&self, adjustment: usize) -> io::Result<HashMap<usize, u32>> {
         let mut chunk_map = HashMap::new();
-        for chunk_len in self
-            .fs
-            .storage_iterator()
-            .map(|(_, container)| container.unwrap_chunk().len())
-        {
+        for pair in self.fs.storage_iterator() {
+            let (_, container) = pair?;
+            let chunk_len = container.unwrap_chunk().len();
             chunk_map
                 .entry(chunk_len / adjustment * adjustment)
                 .and_modify(|count| *count += 1)
                 .or_insert(1);
         }
 
-        chunk_map
+        Ok(chunk_map)
     }
 
     pub fn chunk_count(&self) -> usize {
-        self.fs.storage_iterator().count()
+        self.fs.database().keys().count()
     }
 
     /// Verifies that the written dataset contents are valid.
     ///
-    /// Returns read time for the file.
-    fn verify(&self, dataset: &Dataset, uuid: &str) -> io::Result<Duration> {
+    /// Returns read time for the file, along with the parts of it spent on decompressing and decrypting chunks,
+    /// and hits and misses of the database cache during the timed read.
+    fn verify(
+        &self,
+        dataset: &Dataset,
+        uuid: &str,
+    ) -> io::Result<(TimeMeasurement, Option<CacheStats>)> {
         let mut file = self.fs.open_file_readonly(uuid)?;
 
+        let decompressed_before = self.fs.decompress_time();
+        let decrypted_before = self.fs.decrypt_time();
+        let cache_before = self.fs.database().cache_stats();
         let now = Instant::now();
         let mut read_size = 0;
         loop {
@@ -253,6 +266,14 @@ where
             read_size += segment_len;
         }
         let read_time = now.elapsed();
+        let decompress_time = self.fs.decompress_time() - decompressed_before;
+        let decrypt_time = self.fs.decrypt_time() - decrypted_before;
+        let cache_stats = self
+            .fs
+            .database()
+            .cache_stats()
+            .zip(cache_before)
+            .map(|(after, before)| after - before);
 
         if read_size != dataset.size {
             let msg = "dataset size and size of written file are different";
@@ -278,7 +299,13 @@ where
             }
         }
 
-        Ok(read_time)
+        let measurement = TimeMeasurement {
+            read_time,
+            decompress_time,
+            decrypt_time,
+            ..Default::default()
+        };
+        Ok((measurement, cache_stats))
     }
 
     /// Creates a file with a random name and a given chunker, then returns it and its name.
@@ -1,4 +1,4 @@
-use crate::MB;
+use crate::{CacheStats, MB};
 use chrono::{DateTime, Utc};
 use std::fmt::{Debug, Display, Formatter};
 use std::fs::File;
@@ -16,10 +16,13 @@ pub struct MeasureResult {
     pub size: usize,
     pub dedup_ratio: f64,
     pub full_dedup_ratio: f64,
+    pub compression_ratio: f64,
     pub avg_chunk_size: usize,
     pub chunk_count: usize,
     pub measurement: TimeMeasurement,
     pub throughput: Throughput,
+    /// Hits and misses of the database cache while reading the dataset back, `None` if the database is not cached.
+    pub cache_stats: Option<CacheStats>,
     pub file_name: String,
     pub path: String,
 }
@@ -53,9 +56,19 @@ impl Debug for MeasureResult {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "Dataset: {}\n{:?}\nDedup ratio: {:.3}",
-            self.name, self.measurement, self.dedup_ratio
-        )
+            "Dataset: {}\n{:?}\nDedup ratio: {:.3}\nCompression ratio: {:.3}",
+            self.name, self.measurement, self.dedup_ratio, self.compression_ratio
+        )?;
+        if let Some(stats) = self.cache_stats {
+            write!(
+                f,
+                "\nCache hits: {}\nCache misses: {}\nCache hit ratio: {:.3}",
+                stats.hits,
+                stats.misses,
+                stats.hit_ratio()
+            )?;
+        }
+        Ok(())
     }
 }
 
@@ -69,6 +82,7 @@ struct SerializableResult {
     pub size: usize,
     pub dedup_ratio: f64,
     pub full_dedup_ratio: f64,
+    pub compression_ratio: f64,
     pub avg_chunk_size: usize,
     pub chunk_count: usize,
     #[serde_as(as = "serde_with::DurationSecondsWithFrac<f64>")]
@@ -81,11 +95,21 @@ struct SerializableResult {
     pub chunk_time: Duration,
     #[serde_as(as = "serde_with::DurationSecondsWithFrac<f64>")]
     pub hash_time: Duration,
+    #[serde_as(as = "serde_with::DurationSecondsWithFrac<f64>")]
+    pub compress_time: Duration,
+    #[serde_as(as = "serde_with::DurationSecondsWithFrac<f64>")]
+    pub decompress_time: Duration,
+    #[serde_as(as = "serde_with::DurationSecondsWithFrac<f64>")]
+    pub encrypt_time: Duration,
+    #[serde_as(as = "serde_with::DurationSecondsWithFrac<f64>")]
+    pub decrypt_time: Duration,
     pub chunk_throughput: f64,
     pub hash_throughput: f64,
     pub save_throughput: f64,
     pub write_throughput: f64,
     pub read_throughput: f64,
+    pub cache_hits: Option<usize>,
+    pub cache_misses: Option<usize>,
     pub path: String,
 }
 
@@ -98,6 +122,7 @@ impl SerializableResult {
             size: result.size,
             dedup_ratio: result.dedup_ratio,
             full_dedup_ratio: result.full_dedup_ratio,
+            compression_ratio: result.compression_ratio,
             avg_chunk_size: result.avg_chunk_size,
             chunk_count: result.chunk_count,
             write_time: result.measurement.write_time,
@@ -105,11 +130,17 @@ impl SerializableResult {
             save_time: result.measurement.save_time,
             chunk_time: result.measurement.chunk_time,
             hash_time: result.measurement.hash_time,
+            compress_time: result.measurement.compress_time,
+            decompress_time: result.measurement.decompress_time,
+            encrypt_time: result.measurement.encrypt_time,
+            decrypt_time: result.measurement.decrypt_time,
             chunk_throughput: result.throughput.chunk,
             hash_throughput: result.throughput.hash,
             save_throughput: result.throughput.save,
             write_throughput: result.throughput.write,
             read_throughput: result.throughput.read,
+            cache_hits: result.cache_stats.map(|stats| stats.hits),
+            cache_misses: result.cache_stats.map(|stats| stats.misses),
             path: result.path.clone(),
         }
     }
@@ -122,6 +153,10 @@ pub struct TimeMeasurement {
     pub save_time: Duration,
     pub chunk_time: Duration,
     pub hash_time: Duration,
+    pub compress_time: Duration,
+    pub decompress_time: Duration,
+    pub encrypt_time: Duration,
+    pub decrypt_time: Duration,
 }
 
 impl Add for TimeMeasurement {
@@ -134,6 +169,10 @@ impl Add for TimeMeasurement {
         measurement.save_time += rhs.save_time;
         measurement.chunk_time += rhs.chunk_time;
         measurement.hash_time += rhs.hash_time;
+        measurement.compress_time += rhs.compress_time;
+        measurement.decompress_time += rhs.decompress_time;
+        measurement.encrypt_time += rhs.encrypt_time;
+        measurement.decrypt_time += rhs.decrypt_time;
         measurement
     }
 }
@@ -145,6 +184,10 @@ impl AddAssign for TimeMeasurement {
         self.save_time += rhs.save_time;
         self.chunk_time += rhs.chunk_time;
         self.hash_time += rhs.hash_time;
+        self.compress_time += rhs.compress_time;
+        self.decompress_time += rhs.decompress_time;
+        self.encrypt_time += rhs.encrypt_time;
+        self.decrypt_time += rhs.decrypt_time;
     }
 }
 
@@ -152,8 +195,17 @@ impl Debug for TimeMeasurement {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "Read time: {:?}\nWrite time: {:?}\nSave time: {:?}\nChunk time: {:?}\nHash time: {:?}",
-            self.read_time, self.write_time, self.save_time, self.chunk_time, self.hash_time,
+            "Read time: {:?}\nWrite time: {:?}\nSave time: {:?}\nChunk time: {:?}\nHash time: {:?}\n\
+            Compress time: {:?}\nDecompress time: {:?}\nEncrypt time: {:?}\nDecrypt time: {:?}",
+            self.read_time,
+            self.write_time,
+            self.save_time,
+            self.chunk_time,
+            self.hash_time,
+            self.compress_time,
+            self.decompress_time,
+            self.encrypt_time,
+            self.decrypt_time,
         )
     }
 }
@@ -2,15 +2,30 @@ use std::fmt::{Debug, Formatter};
 
 use cdc_chunkers::SizeParams;
 
-use crate::{Chunk, Chunker, KB};
+use super::stream::{cut_before_end, CutChunker, StreamTail};
+use crate::{Chunk, Chunker, StreamingChunker, KB};
 
 pub struct FastChunker {
     sizes: SizeParams,
+    stream: StreamTail,
 }
 
 impl FastChunker {
     pub fn new(sizes: SizeParams) -> Self {
-        FastChunker { sizes }
+        FastChunker {
+            sizes,
+            stream: StreamTail::default(),
+        }
+    }
+
+    fn fastcdc<'a>(&self, data: &'a [u8]) -> fastcdc::v2020::FastCDC<'a> {
+        let (min, avg, max) = (
+            self.sizes.min as u32,
+            self.sizes.avg as u32,
+            self.sizes.max as u32,
+        );
+
+        fastcdc::v2020::FastCDC::new(data, min, avg, max)
     }
 }
 
@@ -28,13 +43,7 @@ impl Default for FastChunker {
 
 impl Chunker for FastChunker {
     fn chunk_data(&mut self, data: &[u8], empty: Vec<Chunk>) -> Vec<Chunk> {
-        let (min, avg, max) = (
-            self.sizes.min as u32,
-            self.sizes.avg as u32,
-            self.sizes.max as u32,
-        );
-
-        let chunker = fastcdc::v2020::FastCDC::new(data, min, avg, max);
+        let chunker = self.fastcdc(data);
         let mut chunks = empty;
 
         for chunk in chunker {
@@ -47,6 +56,25 @@ impl Chunker for FastChunker {
     fn estimate_chunk_count(&self, data: &[u8]) -> usize {
         data.len() / self.sizes.min
     }
+
+    fn as_streaming(&mut self) -> Option<&mut dyn StreamingChunker> {
+        Some(self)
+    }
+}
+
+impl CutChunker for FastChunker {
+    fn horizon(&self) -> usize {
+        self.sizes.max + 1
+    }
+
+    fn cut(&mut self, data: &[u8], last: bool) -> Option<usize> {
+        let chunk = self.fastcdc(data).next()?;
+        cut_before_end(chunk.length, data, last)
+    }
+
+    fn tail(&mut self) -> &mut StreamTail {
+        &mut self.stream
+    }
 }
 
 impl Debug for FastChunker {
@@ -1,7 +1,7 @@
 use std::cmp::min;
 use std::fmt::{Debug, Formatter};
 
-use crate::{Chunk, Chunker};
+use crate::{Chunk, Chunker, StreamingChunker};
 
 /// Chunker that utilizes Fixed Sized Chunking (FSC) algorithm,
 /// splitting file into even-sized chunks.
@@ -9,11 +9,19 @@ use crate::{Chunk, Chunker};
 /// Default chunk size is 4096 bytes.
 pub struct FSChunker {
     chunk_size: usize,
+    /// Amount of data fed to the chunker since the start of the stream.
+    position: usize,
+    /// Length of the unfinished chunk at the end of the stream.
+    unfinished: usize,
 }
 
 impl FSChunker {
     pub fn new(chunk_size: usize) -> Self {
-        Self { chunk_size }
+        Self {
+            chunk_size,
+            position: 0,
+            unfinished: 0,
+        }
     }
 }
 
@@ -45,4 +53,36 @@ impl Chunker for FSChunker {
     fn estimate_chunk_count(&self, data: &[u8]) -> usize {
         data.len() / self.chunk_size + 1
     }
+
+    fn as_streaming(&mut self) -> Option<&mut dyn StreamingChunker> {
+        Some(self)
+    }
+}
+
+impl StreamingChunker for FSChunker {
+    fn feed(&mut self, data: &[u8], empty: Vec<Chunk>) -> Vec<Chunk> {
+        let mut offset = self.position - self.unfinished;
+        self.position += data.len();
+
+        let mut chunks = empty;
+        while offset + self.chunk_size <= self.position {
+            chunks.push(Chunk::new(offset, self.chunk_size));
+            offset += self.chunk_size;
+        }
+
+        self.unfinished = self.position - offset;
+        chunks
+    }
+
+    fn finish(&mut self, empty: Vec<Chunk>) -> Vec<Chunk> {
+        let chunk = Chunk::new(self.position - self.unfinished, self.unfinished);
+        self.position = 0;
+        self.unfinished = 0;
+
+        let mut chunks = empty;
+        if chunk.length() > 0 {
+            chunks.push(chunk);
+        }
+        chunks
+    }
 }
@@ -2,16 +2,21 @@ use std::fmt::{Debug, Formatter};
 
 use cdc_chunkers::SizeParams;
 
-use crate::{Chunk, Chunker};
+use super::stream::{cut_before_end, CutChunker, StreamTail};
+use crate::{Chunk, Chunker, StreamingChunker};
 
 /// Chunker that utilizes Leap-based CDC algorithm.
 pub struct LeapChunker {
     sizes: SizeParams,
+    stream: StreamTail,
 }
 
 impl LeapChunker {
     pub fn new(sizes: SizeParams) -> Self {
-        Self { sizes }
+        Self {
+            sizes,
+            stream: StreamTail::default(),
+        }
     }
 }
 
@@ -41,4 +46,24 @@ impl Chunker for LeapChunker {
     fn estimate_chunk_count(&self, data: &[u8]) -> usize {
         data.len() / self.sizes.min
     }
+
+    fn as_streaming(&mut self) -> Option<&mut dyn StreamingChunker> {
+        Some(self)
+    }
+}
+
+impl CutChunker for LeapChunker {
+    fn horizon(&self) -> usize {
+        // leaps can step a few bytes over the maximal chunk size
+        2 * self.sizes.max
+    }
+
+    fn cut(&mut self, data: &[u8], last: bool) -> Option<usize> {
+        let chunk = cdc_chunkers::leap_based::Chunker::new(data, self.sizes).next()?;
+        cut_before_end(chunk.len, data, last)
+    }
+
+    fn tail(&mut self) -> &mut StreamTail {
+        &mut self.stream
+    }
 }
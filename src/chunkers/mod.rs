@@ -13,6 +13,7 @@ mod fixed_size;
 mod leap;
 mod rabin;
 pub mod seq;
+mod stream;
 mod supercdc;
 mod ultra;
 
@@ -22,8 +23,64 @@ mod tests {
 
     use sha3::{Digest, Sha3_256};
 
-    use crate::chunkers::RabinChunker;
-    use crate::Chunker;
+    use crate::chunkers::{
+        FSChunker, FastChunker, LeapChunker, RabinChunker, SeqChunker, SuperChunker, UltraChunker,
+    };
+    use crate::{Chunk, Chunker, KB, MB};
+
+    /// Feeds the data to the chunker in segments of the given size and returns all chunks of the stream.
+    fn stream_chunks(chunker: &mut dyn Chunker, data: &[u8], segment_size: usize) -> Vec<Chunk> {
+        let streaming = chunker.as_streaming().unwrap();
+        let mut chunks = vec![];
+        for segment in data.chunks(segment_size) {
+            chunks = streaming.feed(segment, chunks);
+        }
+        streaming.finish(chunks)
+    }
+
+    #[test]
+    fn streamed_cut_points_match_one_shot_chunking() {
+        let mut state = 1u64;
+        let mut random = |len: usize| {
+            (0..len)
+                .map(|_| {
+                    state = state.wrapping_mul(6364136223846793005).wrapping_add(1);
+                    (state >> 56) as u8
+                })
+                .collect::<Vec<_>>()
+        };
+        // zeros and repeated data exercise the rules that depend on previous chunks
+        let repeated = random(MB);
+        let data = [
+            random(2 * MB),
+            vec![0; 300 * KB],
+            repeated.clone(),
+            repeated,
+        ]
+        .concat();
+
+        let chunkers: [fn() -> Box<dyn Chunker>; 7] = [
+            || Box::new(FSChunker::new(5000)),
+            || Box::new(FastChunker::default()),
+            || Box::new(LeapChunker::default()),
+            || Box::new(RabinChunker::default()),
+            || Box::new(SeqChunker::default()),
+            || Box::new(SuperChunker::default()),
+            || Box::new(UltraChunker::default()),
+        ];
+        for chunker in chunkers {
+            let expected = chunker().chunk_data(&data, vec![]);
+            for segment_size in [1000, 4 * KB + 7, 100 * KB, MB] {
+                // Super CDC keeps its records between streams, so every stream needs a new chunker
+                let mut streaming = chunker();
+                let streamed = stream_chunks(&mut *streaming, &data, segment_size);
+                assert_eq!(
+                    streamed, expected,
+                    "{streaming:?}, segment size {segment_size}"
+                );
+            }
+        }
+    }
 
     #[test]
     #[ignore]
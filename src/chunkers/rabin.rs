@@ -2,12 +2,14 @@ use std::fmt::{Debug, Formatter};
 
 use cdc_chunkers::SizeParams;
 
-use crate::{Chunk, Chunker};
+use super::stream::{cut_before_end, CutChunker, StreamTail};
+use crate::{Chunk, Chunker, StreamingChunker};
 
 /// Chunker that utilizes Rabin CDC algorithm.
 pub struct RabinChunker {
     params: Option<cdc_chunkers::rabin::ChunkerParams>,
     sizes: SizeParams,
+    stream: StreamTail,
 }
 
 impl RabinChunker {
@@ -15,6 +17,7 @@ impl RabinChunker {
         Self {
             params: Some(cdc_chunkers::rabin::ChunkerParams::new()),
             sizes,
+            stream: StreamTail::default(),
         }
     }
 }
@@ -53,4 +56,30 @@ impl Chunker for RabinChunker {
     fn estimate_chunk_count(&self, data: &[u8]) -> usize {
         data.len() / self.sizes.min
     }
+
+    fn as_streaming(&mut self) -> Option<&mut dyn StreamingChunker> {
+        Some(self)
+    }
+}
+
+impl CutChunker for RabinChunker {
+    fn horizon(&self) -> usize {
+        self.sizes.max + 1
+    }
+
+    fn cut(&mut self, data: &[u8], last: bool) -> Option<usize> {
+        let mut chunker = cdc_chunkers::rabin::Chunker::with_params(
+            data,
+            self.params.take().unwrap(),
+            self.sizes,
+        );
+        let chunk = chunker.next();
+        self.params = Some(chunker.give_params());
+
+        cut_before_end(chunk?.len, data, last)
+    }
+
+    fn tail(&mut self) -> &mut StreamTail {
+        &mut self.stream
+    }
 }
@@ -3,12 +3,14 @@ use std::fmt::{Debug, Formatter};
 pub use cdc_chunkers::seq::{Config, OperationMode};
 use cdc_chunkers::{seq, SizeParams};
 
-use crate::{Chunk, Chunker};
+use super::stream::{cut_before_end, CutChunker, StreamTail};
+use crate::{Chunk, Chunker, StreamingChunker};
 
 pub struct SeqChunker {
     mode: OperationMode,
     sizes: SizeParams,
     config: Config,
+    stream: StreamTail,
 }
 
 impl SeqChunker {
@@ -17,6 +19,7 @@ impl SeqChunker {
             mode,
             sizes,
             config,
+            stream: StreamTail::default(),
         }
     }
 }
@@ -52,4 +55,24 @@ impl Chunker for SeqChunker {
     fn estimate_chunk_count(&self, data: &[u8]) -> usize {
         data.len() / self.sizes.avg
     }
+
+    fn as_streaming(&mut self) -> Option<&mut dyn StreamingChunker> {
+        Some(self)
+    }
+}
+
+impl CutChunker for SeqChunker {
+    fn horizon(&self) -> usize {
+        // skips can step over the maximal chunk size
+        2 * self.sizes.max
+    }
+
+    fn cut(&mut self, data: &[u8], last: bool) -> Option<usize> {
+        let chunk = seq::Chunker::new(data, self.sizes, self.mode, self.config).next()?;
+        cut_before_end(chunk.len, data, last)
+    }
+
+    fn tail(&mut self) -> &mut StreamTail {
+        &mut self.stream
+    }
 }
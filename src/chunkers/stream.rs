@@ -0,0 +1,116 @@
+use std::mem;
+
+use crate::{Chunk, Chunker, StreamingChunker};
+
+/// Content-defined chunker that finds chunks one at a time, each starting where the previous one has ended.
+///
+/// Such chunkers implement [`StreamingChunker`] by keeping the data after their last chunk in a [`StreamTail`].
+/// A chunk is cut only when enough data after its start is fed to place its end exactly where
+/// [`chunk_data`][Chunker::chunk_data] places it, so every chunk is searched for once.
+pub(crate) trait CutChunker: Chunker {
+    /// Returns the number of bytes after the start of a chunk that is usually enough to find its end.
+    fn horizon(&self) -> usize;
+
+    /// Returns the length of the first chunk of `data`, which starts at a chunk boundary,
+    /// or `None` if more data is needed to find its end.
+    ///
+    /// If `last` is `true`, `data` ends the stream and the length is always returned.
+    /// The state carried by the chunker from one chunk to the next is changed only if the length is returned.
+    fn cut(&mut self, data: &[u8], last: bool) -> Option<usize>;
+
+    /// Returns the data fed after the end of the last chunk.
+    fn tail(&mut self) -> &mut StreamTail;
+
+    /// Resets the state carried from one chunk to the next, so that a new stream can be started.
+    fn reset(&mut self) {}
+}
+
+/// Returns the length of the first chunk found by an algorithm that doesn't look past the end of the chunk
+/// and stops at the end of the data, if the chunk can't continue after `data`.
+///
+/// A chunk that ends before the data does is cut the same way whatever follows the data.
+pub(crate) fn cut_before_end(length: usize, data: &[u8], last: bool) -> Option<usize> {
+    (last || length < data.len()).then_some(length)
+}
+
+/// Data fed to a [`CutChunker`] after the end of its last chunk.
+#[derive(Default)]
+pub(crate) struct StreamTail {
+    data: Vec<u8>,
+    /// Offset of the data from the start of the stream.
+    offset: usize,
+    /// Length of the data needed to cut the next chunk, if the horizon of the chunker was not enough.
+    wanted: usize,
+}
+
+impl StreamTail {
+    fn feed<C>(&mut self, chunker: &mut C, data: &[u8], mut chunks: Vec<Chunk>) -> Vec<Chunk>
+    where
+        C: CutChunker + ?Sized,
+    {
+        let mut rest = data;
+
+        // chunks that start in the kept data are cut over it, joined with as much of the segment as needed
+        while !self.data.is_empty() {
+            let wanted = self.wanted.max(chunker.horizon());
+            let taken = wanted.saturating_sub(self.data.len()).min(rest.len());
+            self.data.extend_from_slice(&rest[..taken]);
+            rest = &rest[taken..];
+            if self.data.len() < wanted {
+                return chunks;
+            }
+
+            match chunker.cut(&self.data, false) {
+                Some(length) => {
+                    chunks.push(Chunk::new(self.offset, length));
+                    self.offset += length;
+                    self.data.drain(..length);
+                    self.wanted = 0;
+                }
+                None => self.wanted = 2 * self.data.len(),
+            }
+        }
+
+        // the other chunks are cut right in the segment
+        while rest.len() >= self.wanted.max(chunker.horizon()) {
+            match chunker.cut(rest, false) {
+                Some(length) => {
+                    chunks.push(Chunk::new(self.offset, length));
+                    self.offset += length;
+                    rest = &rest[length..];
+                    self.wanted = 0;
+                }
+                None => self.wanted = 2 * rest.len(),
+            }
+        }
+
+        self.data.extend_from_slice(rest);
+        chunks
+    }
+}
+
+impl<C: CutChunker> StreamingChunker for C {
+    fn feed(&mut self, data: &[u8], empty: Vec<Chunk>) -> Vec<Chunk> {
+        let mut tail = mem::take(self.tail());
+        let chunks = tail.feed(self, data, empty);
+        *self.tail() = tail;
+        chunks
+    }
+
+    fn finish(&mut self, empty: Vec<Chunk>) -> Vec<Chunk> {
+        let tail = mem::take(self.tail());
+
+        let mut chunks = empty;
+        let mut start = 0;
+        while start < tail.data.len() {
+            let length = self
+                .cut(&tail.data[start..], true)
+                .expect("chunk is always cut at the end of the stream");
+            chunks.push(Chunk::new(tail.offset + start, length));
+            start += length;
+        }
+
+        self.reset();
+        chunks
+    }
+}
@@ -1,23 +1,126 @@
+use std::cmp::min;
 use std::collections::HashMap;
 use std::fmt::{Debug, Formatter};
 
 use cdc_chunkers::SizeParams;
 
-use crate::{Chunk, Chunker};
+use super::stream::{CutChunker, StreamTail};
+use crate::{Chunk, Chunker, StreamingChunker};
+
+// masks of the streaming cut, same as in `cdc_chunkers::supercdc`
+const MASK_S: u64 = 0b1111_1111_1111;
+const MASK_L: u64 = 0b111_1111_1111;
+const MASK_B: u64 = 0b11_1111_1111;
+
+const MASK_S_LS: u64 = MASK_B << 1;
+const MASK_L_LS: u64 = MASK_L << 1;
+const MASK_B_LS: u64 = MASK_B << 1;
 
 /// Chunker that utilizes Super CDC algorithm.
 pub struct SuperChunker {
     records: Option<HashMap<u64, usize>>,
     sizes: SizeParams,
+    stream: StreamTail,
+    state: CutState,
+}
+
+/// State of the Super CDC algorithm that is carried from one chunk to the next within a stream.
+struct CutState {
+    gear: Box<[u64; 256]>,
+    gear_ls: Box<[u64; 256]>,
+    last_hash: u64,
+    record_last_hash: bool,
+    /// Length of the next chunk, predicted by the records.
+    shelved: Option<usize>,
 }
 
 impl SuperChunker {
     pub fn new(sizes: SizeParams) -> Self {
+        // Super CDC uses the gear tables of FastCDC
+        let (gear, gear_ls) = fastcdc::v2020::get_gear_with_seed(0);
         Self {
             records: Some(HashMap::new()),
             sizes,
+            stream: StreamTail::default(),
+            state: CutState {
+                gear,
+                gear_ls,
+                last_hash: 0,
+                record_last_hash: false,
+                shelved: None,
+            },
         }
     }
+
+    /// Finds the end of the chunk at the start of `buf` and returns it with the gear value of its last byte.
+    ///
+    /// Matches `find_border` of `cdc_chunkers::supercdc`, which is not public.
+    fn find_border(&self, buf: &[u8]) -> (u64, usize) {
+        let (gear_table, gear_ls_table) = (&self.state.gear, &self.state.gear_ls);
+
+        let len = buf.len();
+        if len < self.sizes.min {
+            return (0, len);
+        }
+
+        let remaining = min(self.sizes.max, len);
+        let center = min(self.sizes.avg, len);
+
+        let mut breakpoint = remaining;
+        let mut breakpoint_flag = false;
+        let mut breakpoint_gear = 0;
+
+        let mut fingerprint: u64 = 0;
+        let mut pos = self.sizes.min / 2;
+        for index in 1..16 {
+            fingerprint =
+                fingerprint.wrapping_add(gear_table[buf[self.sizes.min - index] as usize] << index);
+            pos += 1;
+        }
+
+        while pos < center / 2 {
+            let a = pos * 2;
+            let gear = gear_ls_table[buf[a] as usize];
+            fingerprint = (fingerprint << 2).wrapping_add(gear);
+            if fingerprint & MASK_S_LS == 0 {
+                return (gear, a);
+            }
+            let gear = gear_table[buf[a + 1] as usize];
+            fingerprint = fingerprint.wrapping_add(gear);
+            if fingerprint & MASK_S == 0 {
+                return (gear, a + 1);
+            }
+            pos += 1;
+        }
+
+        while pos < remaining / 2 {
+            let a = pos * 2;
+            let gear = gear_ls_table[buf[a] as usize];
+            fingerprint = (fingerprint << 2).wrapping_add(gear);
+            if fingerprint & MASK_L_LS == 0 {
+                return (gear, a);
+            }
+            if !breakpoint_flag && fingerprint & MASK_B_LS == 0 {
+                breakpoint_flag = true;
+                breakpoint = a;
+                breakpoint_gear = gear;
+            }
+
+            let gear = gear_table[buf[a + 1] as usize];
+            fingerprint = fingerprint.wrapping_add(gear);
+            if fingerprint & MASK_L == 0 {
+                return (gear, a + 1);
+            }
+            if !breakpoint_flag && fingerprint & MASK_B == 0 {
+                breakpoint_flag = true;
+                breakpoint = a + 1;
+                breakpoint_gear = gear;
+            }
+            pos += 1;
+        }
+
+        (breakpoint_gear, breakpoint)
+    }
 }
 
 impl Default for SuperChunker {
@@ -54,4 +157,57 @@ impl Chunker for SuperChunker {
     fn estimate_chunk_count(&self, data: &[u8]) -> usize {
         data.len() / self.sizes.min
     }
+
+    fn as_streaming(&mut self) -> Option<&mut dyn StreamingChunker> {
+        Some(self)
+    }
+}
+
+impl CutChunker for SuperChunker {
+    fn horizon(&self) -> usize {
+        // the chunk and the one predicted after it must both fit
+        2 * self.sizes.max + 1
+    }
+
+    fn cut(&mut self, data: &[u8], last: bool) -> Option<usize> {
+        if !last && data.len() < self.horizon() {
+            return None;
+        }
+
+        if let Some(length) = self.state.shelved.take() {
+            return Some(length);
+        }
+
+        let (hash, length) = self.find_border(data);
+
+        let records = self.records.as_mut().unwrap();
+        let state = &mut self.state;
+        if state.record_last_hash {
+            records.insert(state.last_hash, length);
+        }
+
+        match records.get(&hash) {
+            Some(&found_length) => {
+                state.record_last_hash = false;
+                if length + found_length < data.len() {
+                    state.shelved = Some(found_length);
+                    return Some(length);
+                }
+            }
+            None => state.record_last_hash = true,
+        }
+        state.last_hash = hash;
+
+        Some(length)
+    }
+
+    fn tail(&mut self) -> &mut StreamTail {
+        &mut self.stream
+    }
+
+    fn reset(&mut self) {
+        self.state.last_hash = 0;
+        self.state.record_last_hash = false;
+        self.state.shelved = None;
+    }
 }
@@ -2,19 +2,39 @@ use std::fmt::{Debug, Formatter};
 
 use cdc_chunkers::SizeParams;
 
-use crate::{Chunk, Chunker};
+use super::stream::{CutChunker, StreamTail};
+use crate::{Chunk, Chunker, StreamingChunker};
+
+// constants of the streaming cut, same as in `cdc_chunkers::ultra`
+const WINDOW_SIZE: usize = 8;
+const MASK_S: usize = 0x2F;
+const MASK_L: usize = 0x2C;
+/// Number of equal consecutive windows after which a chunk is cut.
+const LEST: usize = 64;
 
 /// Chunker that utilizes Ultra CDC algorithm.
 pub struct UltraChunker {
     sizes: SizeParams,
+    stream: StreamTail,
+    /// Number of equal consecutive windows seen so far, carried from one chunk to the next within a stream.
+    equal_window_count: usize,
 }
 
 impl UltraChunker {
     pub fn new(sizes: SizeParams) -> Self {
-        Self { sizes }
+        Self {
+            sizes,
+            stream: StreamTail::default(),
+            equal_window_count: 0,
+        }
     }
 }
 
+/// Returns the Hamming distance between the byte and `0xAA`, which is used as the hash of the byte.
+fn distance(byte: u8) -> usize {
+    (byte ^ 0xAA).count_ones() as usize
+}
+
 impl Default for UltraChunker {
     fn default() -> Self {
         Self::new(SizeParams::ultra_default())
@@ -41,4 +61,70 @@ impl Chunker for UltraChunker {
     fn estimate_chunk_count(&self, data: &[u8]) -> usize {
         data.len() / self.sizes.min
     }
+
+    fn as_streaming(&mut self) -> Option<&mut dyn StreamingChunker> {
+        Some(self)
+    }
+}
+
+impl CutChunker for UltraChunker {
+    fn horizon(&self) -> usize {
+        self.sizes.max + WINDOW_SIZE + 1
+    }
+
+    /// Matches `generate_chunk` of `cdc_chunkers::ultra`, which is not public.
+    fn cut(&mut self, data: &[u8], last: bool) -> Option<usize> {
+        if !last && data.len() < self.horizon() {
+            return None;
+        }
+
+        let mut length = self.sizes.min;
+        if length + WINDOW_SIZE >= data.len() {
+            return Some(data.len());
+        }
+
+        let mut out_window = &data[..WINDOW_SIZE];
+        length += WINDOW_SIZE;
+        let mut distance_sum: usize = out_window.iter().map(|&byte| distance(byte)).sum();
+
+        for (size_limit, mask) in [(self.sizes.avg, MASK_S), (self.sizes.max, MASK_L)] {
+            while length < size_limit {
+                if length + WINDOW_SIZE >= data.len() {
+                    return Some(data.len());
+                }
+
+                let in_window = &data[length..length + WINDOW_SIZE];
+                if in_window == out_window {
+                    self.equal_window_count += 1;
+                    if self.equal_window_count == LEST {
+                        return Some(length + WINDOW_SIZE);
+                    }
+                    length += WINDOW_SIZE;
+                    continue;
+                }
+
+                self.equal_window_count = 0;
+                for (&old, &new) in out_window.iter().zip(in_window) {
+                    if distance_sum & mask == 0 {
+                        return Some(length + WINDOW_SIZE);
+                    }
+                    distance_sum += distance(new);
+                    distance_sum -= distance(old);
+                }
+
+                out_window = in_window;
+                length += WINDOW_SIZE;
+            }
+        }
+
+        Some(length)
+    }
+
+    fn tail(&mut self) -> &mut StreamTail {
+        &mut self.stream
+    }
+
+    fn reset(&mut self) {
+        self.equal_window_count = 0;
+    }
 }
@@ -0,0 +1,65 @@
+use std::fmt::{Debug, Formatter};
+use std::io;
+
+use crate::Compressor;
+
+/// Compressor that uses LZ4 block format. Fast, but compresses worse than zstd.
+#[derive(Debug, Default)]
+pub struct Lz4Compressor;
+
+impl Compressor for Lz4Compressor {
+    fn compress(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+        Ok(lz4_flex::compress_prepend_size(data))
+    }
+
+    fn decompress(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+        lz4_flex::decompress_size_prepended(data)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    fn decompressed_len(&self, data: &[u8]) -> usize {
+        lz4_flex::block::uncompressed_size(data).map_or(0, |(size, _)| size)
+    }
+}
+
+/// Compressor that uses zstd with the given compression level.
+///
+/// Default level is 3, same as zstd's default.
+pub struct ZstdCompressor {
+    level: i32,
+}
+
+impl ZstdCompressor {
+    pub fn new(level: i32) -> Self {
+        Self { level }
+    }
+}
+
+impl Default for ZstdCompressor {
+    fn default() -> Self {
+        Self::new(zstd::DEFAULT_COMPRESSION_LEVEL)
+    }
+}
+
+impl Debug for ZstdCompressor {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "zstd, level: {}", self.level)
+    }
+}
+
+impl Compressor for ZstdCompressor {
+    fn compress(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+        zstd::bulk::compress(data, self.level)
+    }
+
+    fn decompress(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+        zstd::bulk::decompress(data, self.decompressed_len(data))
+    }
+
+    fn decompressed_len(&self, data: &[u8]) -> usize {
+        match zstd::zstd_safe::get_frame_content_size(data) {
+            Ok(Some(size)) => size as usize,
+            _ => 0,
+        }
+    }
+}
@@ -0,0 +1,48 @@
+use sha2::{Digest, Sha256};
+
+use crate::{ChunkKey, ConvergentCipher};
+
+/// Convergent cipher built on SHA-256 alone.
+///
+/// The key of a chunk is its SHA-256 digest. The chunk is XORed with a keystream,
+/// whose blocks are SHA-256 digests of the key followed by the block index, as in the counter mode.
+/// Every key encrypts exactly one plaintext, so the keystream is never reused for different data.
+/// Ciphertext has the same length as the chunk.
+#[derive(Debug, Default)]
+pub struct Sha256Cipher;
+
+impl Sha256Cipher {
+    fn apply_keystream(key: &ChunkKey, data: &[u8]) -> Vec<u8> {
+        let mut hasher = Sha256::new();
+        let mut output = Vec::with_capacity(data.len());
+
+        for (index, block) in data.chunks(32).enumerate() {
+            Digest::update(&mut hasher, key);
+            Digest::update(&mut hasher, (index as u64).to_le_bytes());
+            let keystream: [u8; 32] = hasher.finalize_reset().into();
+
+            output.extend(
+                block
+                    .iter()
+                    .zip(keystream)
+                    .map(|(byte, key_byte)| byte ^ key_byte),
+            );
+        }
+
+        output
+    }
+}
+
+impl ConvergentCipher for Sha256Cipher {
+    fn derive_key(&self, chunk: &[u8]) -> ChunkKey {
+        Sha256::digest(chunk).into()
+    }
+
+    fn encrypt(&self, key: &ChunkKey, chunk: &[u8]) -> Vec<u8> {
+        Self::apply_keystream(key, chunk)
+    }
+
+    fn decrypt(&self, key: &ChunkKey, data: &[u8]) -> Vec<u8> {
+        Self::apply_keystream(key, data)
+    }
+}
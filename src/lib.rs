@@ -1,19 +1,32 @@
+use std::collections::HashMap;
 use std::fmt::{Debug, Formatter};
 use std::hash;
+use std::io;
 use std::ops::{Add, AddAssign, Deref, DerefMut};
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
-pub use system::database::{Database, IterableDatabase};
-pub use system::disk_database::DiskDatabase;
-pub use system::scrub::{CopyScrubber, Scrub, ScrubMeasurements};
-pub use system::storage::{Data, DataContainer};
+pub use system::cached_database::{ByteSize, CacheStats, CachedDatabase};
+pub use system::database::{ConcurrentDatabase, Database, IterableDatabase, PersistentDatabase};
+pub use system::disk_database::{CompactionMeasurements, DiskDatabase};
+pub use system::file_io::{FileReader, FileWriter};
+pub use system::file_layer::{DirEntry, FileMetadata};
+pub use system::filtered_database::FilteredDatabase;
+pub use system::pipeline::Pipeline;
+pub use system::scrub::{CopyScrubber, DumbScrubber, Scrub, ScrubMeasurements};
+pub use system::sharded_database::ShardedDatabase;
+pub use system::shared::SharedFileSystem;
+pub use system::storage::{CollisionPolicy, Data, DataContainer};
 pub use system::{create_cdc_filesystem, FileSystem};
 
 #[cfg(feature = "bench")]
 pub mod bench;
 #[cfg(feature = "chunkers")]
 pub mod chunkers;
+#[cfg(feature = "compressors")]
+pub mod compressors;
+#[cfg(feature = "encryption")]
+pub mod encryption;
 #[cfg(feature = "hashers")]
 pub mod hashers;
 mod system;
@@ -84,6 +97,32 @@ pub trait Chunker: Debug {
     /// data buffer. Used to pre-allocate the buffer with the required size so that allocation times are not counted
     /// towards total chunking time.
     fn estimate_chunk_count(&self, data: &[u8]) -> usize;
+
+    /// Returns the chunker as a [`StreamingChunker`], if it can continue chunking where the previous segment of the data ended.
+    ///
+    /// Chunkers that return `None` are run over the unfinished chunk of the previous segment together with the next segment,
+    /// so the data of that chunk is chunked twice.
+    fn as_streaming(&mut self) -> Option<&mut dyn StreamingChunker> {
+        None
+    }
+}
+
+/// Chunker that keeps its state between consecutive segments of the data, so that every byte is processed only once.
+///
+/// Offsets of the chunks are counted from the start of the stream, so a chunk found in one segment
+/// may begin in one of the previous ones.
+pub trait StreamingChunker {
+    /// Continues the stream with the next segment of `data` and returns the chunks that end in it.
+    ///
+    /// Data after the end of the last returned chunk belongs to the unfinished chunk, which is continued by the next call.
+    /// `empty` is the same as in [`chunk_data`][Chunker::chunk_data].
+    fn feed(&mut self, data: &[u8], empty: Vec<Chunk>) -> Vec<Chunk>;
+
+    /// Ends the stream and returns the chunks of the data after the end of the last returned chunk.
+    /// The chunker is then ready for a new stream.
+    ///
+    /// `empty` is the same as in [`chunk_data`][Chunker::chunk_data].
+    fn finish(&mut self, empty: Vec<Chunk>) -> Vec<Chunk>;
 }
 
 /// Reference to a chunker that can be re-used.
@@ -125,6 +164,40 @@ impl Debug for ChunkerRef {
     }
 }
 
+/// Chunkers by the names that file metadata records for them, so that files of a
+/// [`loaded`][crate::FileSystem::load] file system can be written with their chunkers again.
+///
+/// The name of a chunker is its `Debug` representation, same as in [`FileMetadata::chunker`].
+#[derive(Default)]
+pub struct ChunkerRegistry {
+    factories: HashMap<String, Box<dyn Fn() -> ChunkerRef + Send + Sync>>,
+}
+
+impl ChunkerRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers chunkers made by `factory` under the name of the chunker it makes.
+    ///
+    /// Every file gets a new chunker from the factory, so that files don't share the chunking state.
+    pub fn register<F, C>(mut self, factory: F) -> Self
+    where
+        F: Fn() -> C + Send + Sync + 'static,
+        C: Chunker + 'static,
+    {
+        let name = format!("{:?}", factory());
+        self.factories
+            .insert(name, Box::new(move || factory().into()));
+        self
+    }
+
+    /// Returns a new chunker registered under the given name, or `None` if there is no such chunker.
+    pub fn get(&self, name: &str) -> Option<ChunkerRef> {
+        self.factories.get(name).map(|factory| factory())
+    }
+}
+
 /// Functionality for an object that hashes the input.
 pub trait Hasher {
     /// Hash type that would be returned by the hasher.
@@ -146,6 +219,61 @@ where
     }
 }
 
+/// Functionality for an object that compresses chunks before they are stored.
+///
+/// Only the chunks that are stored for the first time are compressed, i.e. compression is applied after deduplication.
+/// Chunks are decompressed when they are retrieved.
+pub trait Compressor: Debug + Send + Sync {
+    /// Compresses the chunk.
+    fn compress(&self, data: &[u8]) -> io::Result<Vec<u8>>;
+
+    /// Restores the chunk compressed by [`compress`][Compressor::compress].
+    fn decompress(&self, data: &[u8]) -> io::Result<Vec<u8>>;
+
+    /// Returns the length of the chunk compressed by [`compress`][Compressor::compress], without decompressing it,
+    /// or 0 if `data` is not a compressed chunk.
+    fn decompressed_len(&self, data: &[u8]) -> usize;
+}
+
+impl<C> From<C> for Box<dyn Compressor>
+where
+    C: Compressor + 'static,
+{
+    fn from(compressor: C) -> Self {
+        Box::new(compressor)
+    }
+}
+
+/// Key that a chunk is encrypted with by a [`ConvergentCipher`].
+pub type ChunkKey = [u8; 32];
+
+/// Functionality for an object that encrypts chunks with convergent (message-locked) encryption.
+///
+/// The key of each chunk is derived from its contents, and the chunk is stored under the hash of its ciphertext,
+/// so that equal chunks are encrypted into equal ciphertexts and are still deduplicated.
+/// Encryption must therefore be deterministic: no random nonces or salts.
+///
+/// Keys are kept by the storage along with the file metadata, not in the database.
+pub trait ConvergentCipher: Debug + Send + Sync {
+    /// Derives the key of the chunk from its contents.
+    fn derive_key(&self, chunk: &[u8]) -> ChunkKey;
+
+    /// Encrypts the chunk with the given key.
+    fn encrypt(&self, key: &ChunkKey, chunk: &[u8]) -> Vec<u8>;
+
+    /// Restores the chunk encrypted by [`encrypt`][ConvergentCipher::encrypt] with the same key.
+    fn decrypt(&self, key: &ChunkKey, data: &[u8]) -> Vec<u8>;
+}
+
+impl<C> From<C> for Box<dyn ConvergentCipher>
+where
+    C: ConvergentCipher + 'static,
+{
+    fn from(cipher: C) -> Self {
+        Box::new(cipher)
+    }
+}
+
 /// Measurements that are received after writing data to a file.
 /// Contain time spent for chunking and for hashing.
 #[derive(Debug, PartialEq, Default, Clone, Copy)]
@@ -153,6 +281,13 @@ pub struct WriteMeasurements {
     save_time: Duration,
     chunk_time: Duration,
     hash_time: Duration,
+    /// Time spent on compressing the stored chunks. Always 0 unless a compressor is set.
+    compress_time: Duration,
+    /// Time spent on deriving keys and encrypting chunks. Always 0 unless a cipher is set.
+    encrypt_time: Duration,
+    /// Number of chunks whose hash matched a stored chunk with different contents.
+    /// Always 0 unless collision checks are enabled.
+    collisions: usize,
 }
 
 impl WriteMeasurements {
@@ -161,6 +296,9 @@ impl WriteMeasurements {
             save_time,
             chunk_time,
             hash_time,
+            compress_time: Duration::default(),
+            encrypt_time: Duration::default(),
+            collisions: 0,
         }
     }
 
@@ -175,6 +313,24 @@ impl WriteMeasurements {
     pub fn hash_time(&self) -> Duration {
         self.hash_time
     }
+
+    /// Returns the time spent on compressing chunks,
+    /// see [`FileSystem::set_compressor`][crate::FileSystem::set_compressor].
+    pub fn compress_time(&self) -> Duration {
+        self.compress_time
+    }
+
+    /// Returns the time spent on encrypting chunks,
+    /// see [`FileSystem::set_cipher`][crate::FileSystem::set_cipher].
+    pub fn encrypt_time(&self) -> Duration {
+        self.encrypt_time
+    }
+
+    /// Returns the number of hash collisions found during the write,
+    /// see [`FileSystem::set_collision_policy`][crate::FileSystem::set_collision_policy].
+    pub fn collisions(&self) -> usize {
+        self.collisions
+    }
 }
 
 impl Add for WriteMeasurements {
@@ -185,6 +341,9 @@ impl Add for WriteMeasurements {
             save_time: self.save_time + rhs.save_time,
             chunk_time: self.chunk_time + rhs.chunk_time,
             hash_time: self.hash_time + rhs.hash_time,
+            compress_time: self.compress_time + rhs.compress_time,
+            encrypt_time: self.encrypt_time + rhs.encrypt_time,
+            collisions: self.collisions + rhs.collisions,
         }
     }
 }
@@ -194,5 +353,8 @@ impl AddAssign for WriteMeasurements {
         self.save_time += rhs.save_time;
         self.chunk_time += rhs.chunk_time;
         self.hash_time += rhs.hash_time;
+        self.compress_time += rhs.compress_time;
+        self.encrypt_time += rhs.encrypt_time;
+        self.collisions += rhs.collisions;
     }
 }
@@ -0,0 +1,196 @@
+use std::alloc::{alloc_zeroed, dealloc, handle_alloc_error, Layout};
+use std::collections::HashMap;
+use std::fmt::{Debug, Formatter};
+use std::ops::{Deref, DerefMut};
+use std::ptr::NonNull;
+use std::slice;
+use std::sync::{Arc, Mutex};
+
+/// Zero-initialized byte buffer, whose memory is aligned as required for I/O with the O_DIRECT flag.
+///
+/// Taken from a [`BufferPool`], and returned to it on drop.
+pub(crate) struct AlignedBuffer {
+    ptr: NonNull<u8>,
+    len: usize,
+    capacity: usize,
+    pool: Arc<BufferPool>,
+}
+
+// SAFETY: the buffer exclusively owns its memory, like `Vec<u8>` does.
+unsafe impl Send for AlignedBuffer {}
+// SAFETY: shared references only allow reading the memory.
+unsafe impl Sync for AlignedBuffer {}
+
+impl Deref for AlignedBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        // SAFETY: `ptr` points to an allocation of `capacity >= len` initialized bytes owned by the buffer.
+        unsafe { slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl DerefMut for AlignedBuffer {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        // SAFETY: same as in `deref`, and `&mut self` guarantees exclusive access.
+        unsafe { slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl Debug for AlignedBuffer {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AlignedBuffer")
+            .field("len", &self.len)
+            .field("alignment", &self.pool.alignment)
+            .finish()
+    }
+}
+
+impl Drop for AlignedBuffer {
+    fn drop(&mut self) {
+        self.pool.release(self.ptr, self.capacity);
+    }
+}
+
+/// Released memory kept for reuse. Wrapped so that it can be moved between threads.
+struct FreeMemory(NonNull<u8>);
+
+// SAFETY: the memory is not accessed until it's taken out of the pool by a single buffer.
+unsafe impl Send for FreeMemory {}
+
+/// Pool of [aligned buffers][AlignedBuffer], which keeps the memory of dropped buffers for reuse.
+///
+/// Buffer capacities are rounded up to powers of two, so that buffers of similar sizes share the memory.
+/// At most `capacity` bytes are kept, the rest is freed on release.
+pub(crate) struct BufferPool {
+    alignment: usize,
+    capacity: usize,
+    free: Mutex<FreeBuffers>,
+}
+
+#[derive(Default)]
+struct FreeBuffers {
+    /// Released memory by its capacity.
+    buffers: HashMap<usize, Vec<FreeMemory>>,
+    /// Total capacity of the released memory.
+    size: usize,
+}
+
+impl BufferPool {
+    /// Creates a pool of buffers aligned by `alignment`, which keeps at most `capacity` bytes for reuse.
+    ///
+    /// # Panics
+    /// Panics if `alignment` is not a power of two.
+    pub fn new(alignment: usize, capacity: usize) -> Arc<Self> {
+        assert!(
+            alignment.is_power_of_two(),
+            "alignment must be a power of two"
+        );
+
+        Arc::new(Self {
+            alignment,
+            capacity,
+            free: Mutex::default(),
+        })
+    }
+
+    /// Returns a zeroed buffer of the given length, reusing released memory if possible.
+    pub fn take(self: &Arc<Self>, len: usize) -> AlignedBuffer {
+        let capacity = len.next_power_of_two().max(self.alignment);
+
+        let reused = {
+            let mut free = self.free.lock().unwrap();
+            let reused = free.buffers.get_mut(&capacity).and_then(Vec::pop);
+            if reused.is_some() {
+                free.size -= capacity;
+            }
+            reused
+        };
+        let ptr = match reused {
+            Some(FreeMemory(ptr)) => {
+                // SAFETY: the memory was allocated with `capacity >= len` bytes and is not used by other buffers.
+                unsafe { ptr.as_ptr().write_bytes(0, len) };
+                ptr
+            }
+            None => {
+                let layout = self.layout(capacity);
+                // SAFETY: `layout` has a non-zero size, as `capacity >= alignment >= 1`.
+                let ptr = unsafe { alloc_zeroed(layout) };
+                NonNull::new(ptr).unwrap_or_else(|| handle_alloc_error(layout))
+            }
+        };
+
+        AlignedBuffer {
+            ptr,
+            len,
+            capacity,
+            pool: Arc::clone(self),
+        }
+    }
+
+    /// Keeps the memory of a dropped buffer for reuse, or frees it if the pool is full.
+    fn release(&self, ptr: NonNull<u8>, capacity: usize) {
+        let mut free = self.free.lock().unwrap();
+        if free.size + capacity <= self.capacity {
+            free.buffers
+                .entry(capacity)
+                .or_default()
+                .push(FreeMemory(ptr));
+            free.size += capacity;
+            return;
+        }
+        drop(free);
+
+        // SAFETY: the memory was allocated by this pool with the same layout.
+        unsafe { dealloc(ptr.as_ptr(), self.layout(capacity)) }
+    }
+
+    fn layout(&self, capacity: usize) -> Layout {
+        Layout::from_size_align(capacity, self.alignment).unwrap()
+    }
+}
+
+impl Drop for BufferPool {
+    fn drop(&mut self) {
+        let free = std::mem::take(self.free.get_mut().unwrap());
+        for (capacity, buffers) in free.buffers {
+            for FreeMemory(ptr) in buffers {
+                // SAFETY: the memory was allocated by this pool with the same layout.
+                unsafe { dealloc(ptr.as_ptr(), self.layout(capacity)) }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BufferPool;
+
+    fn pooled_size(pool: &BufferPool) -> usize {
+        pool.free.lock().unwrap().size
+    }
+
+    #[test]
+    fn buffers_are_aligned_zeroed_and_reused() {
+        let pool = BufferPool::new(4096, 64 * 1024);
+
+        let mut buffer = pool.take(5000);
+        assert_eq!(buffer.len(), 5000);
+        assert_eq!(buffer.as_ptr() as usize % 4096, 0);
+        buffer.fill(1);
+        let ptr = buffer.as_ptr();
+        drop(buffer);
+        assert_eq!(pooled_size(&pool), 8192);
+
+        // memory of the same capacity is reused and zeroed again
+        let buffer = pool.take(6000);
+        assert_eq!(buffer.as_ptr(), ptr);
+        assert!(buffer.iter().all(|&byte| byte == 0));
+        assert_eq!(pooled_size(&pool), 0);
+
+        // buffers that don't fit into the pool are freed
+        drop(pool.take(128 * 1024));
+        assert_eq!(pooled_size(&pool), 0);
+        assert_eq!(pool.take(0).len(), 0);
+    }
+}
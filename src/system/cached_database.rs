@@ -0,0 +1,365 @@
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+use std::io;
+use std::ops::Sub;
+use std::sync::Mutex;
+
+use super::database::{Database, IterableDatabase};
+use super::storage::{Data, DataContainer};
+
+/// Size of a value in bytes, as accounted by [`CachedDatabase`].
+pub trait ByteSize {
+    fn byte_size(&self) -> usize;
+}
+
+impl ByteSize for Vec<u8> {
+    fn byte_size(&self) -> usize {
+        self.len()
+    }
+}
+
+impl<K> ByteSize for DataContainer<K> {
+    fn byte_size(&self) -> usize {
+        match self.extract() {
+            Data::Chunk(chunk) => chunk.len(),
+            Data::TargetChunk(keys) => keys.len() * size_of::<K>(),
+        }
+    }
+}
+
+/// Hit and miss counters of a [`CachedDatabase`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Number of values returned from the cache.
+    pub hits: usize,
+    /// Number of values fetched from the underlying database.
+    pub misses: usize,
+    /// Number of values evicted from the cache to free space for others.
+    pub evictions: usize,
+}
+
+impl CacheStats {
+    /// Returns the share of lookups served from the cache, or 0 if there were none.
+    pub fn hit_ratio(&self) -> f64 {
+        if self.hits + self.misses == 0 {
+            return 0.0;
+        }
+        self.hits as f64 / (self.hits + self.misses) as f64
+    }
+}
+
+impl Sub for CacheStats {
+    type Output = CacheStats;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        CacheStats {
+            hits: self.hits - rhs.hits,
+            misses: self.misses - rhs.misses,
+            evictions: self.evictions - rhs.evictions,
+        }
+    }
+}
+
+/// Database decorator that keeps recently read values in a least-recently-used cache bounded by their total size.
+///
+/// Values are cached on reads only, so that written chunks don't push out the ones being read.
+/// Written keys are dropped from the cache, so a value replaced in the underlying database is not read stale.
+/// Reading a file with many repeated chunks fetches each of them from the underlying database once,
+/// as long as the cache is big enough to hold the chunks between repetitions.
+pub struct CachedDatabase<D, K, V> {
+    database: D,
+    cache: Mutex<LruCache<K, V>>,
+}
+
+impl<K, V, D> CachedDatabase<D, K, V>
+where
+    K: Hash + Eq + Clone,
+    V: Clone + ByteSize,
+{
+    /// Wraps the database into a cache that holds at most `capacity` bytes of values.
+    pub fn new(database: D, capacity: usize) -> Self {
+        Self {
+            database,
+            cache: Mutex::new(LruCache::new(capacity)),
+        }
+    }
+
+    /// Returns hit and miss counters of the cache.
+    pub fn stats(&self) -> CacheStats {
+        self.cache.lock().unwrap().stats
+    }
+
+    /// Resets hit and miss counters, keeping the cached values.
+    pub fn reset_stats(&self) {
+        self.cache.lock().unwrap().stats = CacheStats::default();
+    }
+
+    /// Returns the total size of the cached values in bytes.
+    pub fn cached_size(&self) -> usize {
+        self.cache.lock().unwrap().size
+    }
+
+    /// Consumes the decorator and returns the underlying database.
+    pub fn into_inner(self) -> D {
+        self.database
+    }
+}
+
+impl<K, V, D> Database<K, V> for CachedDatabase<D, K, V>
+where
+    K: Hash + Eq + Clone,
+    V: Clone + ByteSize,
+    D: Database<K, V>,
+{
+    fn insert(&mut self, key: K, value: V) -> io::Result<()> {
+        self.cache.get_mut().unwrap().remove(&key);
+        self.database.insert(key, value)
+    }
+
+    fn get(&self, key: &K) -> io::Result<V> {
+        if let Some(value) = self.cache.lock().unwrap().get(key) {
+            return Ok(value);
+        }
+
+        let value = self.database.get(key)?;
+        self.cache.lock().unwrap().put(key.clone(), value.clone());
+        Ok(value)
+    }
+
+    fn insert_multi(&mut self, pairs: Vec<(K, V)>) -> io::Result<()> {
+        let cache = self.cache.get_mut().unwrap();
+        for (key, _) in &pairs {
+            cache.remove(key);
+        }
+        self.database.insert_multi(pairs)
+    }
+
+    /// Fetches the values that are not cached with a single request to the underlying database.
+    ///
+    /// Every missing key is fetched once, its repetitions in `keys` are counted as hits.
+    fn get_multi(&self, keys: &[K]) -> io::Result<Vec<V>> {
+        let mut values = Vec::with_capacity(keys.len());
+        // keys to fetch, each of them once, and indices of the values waiting for them
+        let mut missing = vec![];
+        let mut positions = HashMap::new();
+        let mut waiting = vec![];
+
+        let mut cache = self.cache.lock().unwrap();
+        for (index, key) in keys.iter().enumerate() {
+            if let Some(&position) = positions.get(key) {
+                cache.stats.hits += 1;
+                waiting.push((index, position));
+                values.push(None);
+                continue;
+            }
+
+            let value = cache.get(key);
+            if value.is_none() {
+                positions.insert(key, missing.len());
+                waiting.push((index, missing.len()));
+                missing.push(key.clone());
+            }
+            values.push(value);
+        }
+        drop(cache);
+
+        if !missing.is_empty() {
+            let fetched = self.database.get_multi(&missing)?;
+
+            let mut cache = self.cache.lock().unwrap();
+            for (key, value) in missing.into_iter().zip(&fetched) {
+                cache.put(key, value.clone());
+            }
+            for (index, position) in waiting {
+                values[index] = Some(fetched[position].clone());
+            }
+        }
+
+        Ok(values.into_iter().map(Option::unwrap).collect())
+    }
+
+    fn contains(&self, key: &K) -> bool {
+        self.cache.lock().unwrap().contains(key) || self.database.contains(key)
+    }
+
+    fn remove(&mut self, key: &K) -> io::Result<()> {
+        self.cache.get_mut().unwrap().remove(key);
+        self.database.remove(key)
+    }
+
+    fn cache_stats(&self) -> Option<CacheStats> {
+        Some(self.stats())
+    }
+}
+
+impl<K, V, D> IterableDatabase<K, V> for CachedDatabase<D, K, V>
+where
+    K: Hash + Eq + Clone,
+    V: Clone + ByteSize,
+    D: IterableDatabase<K, V>,
+{
+    fn iterator(&self) -> Box<dyn Iterator<Item = io::Result<(&K, V)>> + '_> {
+        self.database.iterator()
+    }
+
+    /// Drops the cached values, as they may be changed by `f`.
+    fn for_each_mut(&mut self, f: &mut dyn FnMut(&K, &mut V) -> io::Result<()>) -> io::Result<()> {
+        self.cache.get_mut().unwrap().clear();
+        self.database.for_each_mut(f)
+    }
+
+    fn keys<'a>(&'a self) -> Box<dyn Iterator<Item = &'a K> + 'a>
+    where
+        V: 'a,
+    {
+        self.database.keys()
+    }
+
+    fn values(&self) -> Box<dyn Iterator<Item = io::Result<V>> + '_> {
+        self.database.values()
+    }
+
+    fn clear(&mut self) -> io::Result<()> {
+        self.cache.get_mut().unwrap().clear();
+        self.database.clear()
+    }
+}
+
+/// Least-recently-used cache, bounded by the total size of the values.
+///
+/// Recency is tracked with a counter of accesses, the least recent entry has the smallest one.
+struct LruCache<K, V> {
+    entries: HashMap<K, (V, u64)>,
+    recency: BTreeMap<u64, K>,
+    clock: u64,
+    size: usize,
+    capacity: usize,
+    stats: CacheStats,
+}
+
+impl<K: Hash + Eq + Clone, V: Clone + ByteSize> LruCache<K, V> {
+    fn new(capacity: usize) -> Self {
+        Self {
+            entries: HashMap::new(),
+            recency: BTreeMap::new(),
+            clock: 0,
+            size: 0,
+            capacity,
+            stats: CacheStats::default(),
+        }
+    }
+
+    /// Returns a copy of the cached value and marks it as the most recently used, counting a hit or a miss.
+    fn get(&mut self, key: &K) -> Option<V> {
+        let Some((value, last_used)) = self.entries.get_mut(key) else {
+            self.stats.misses += 1;
+            return None;
+        };
+        self.stats.hits += 1;
+
+        self.clock += 1;
+        let key = self.recency.remove(last_used).unwrap();
+        self.recency.insert(self.clock, key);
+        *last_used = self.clock;
+
+        Some(value.clone())
+    }
+
+    fn contains(&self, key: &K) -> bool {
+        self.entries.contains_key(key)
+    }
+
+    /// Caches the value, evicting the least recently used ones until it fits.
+    /// Values larger than the whole cache are not cached.
+    fn put(&mut self, key: K, value: V) {
+        let size = value.byte_size();
+        if size > self.capacity || self.entries.contains_key(&key) {
+            return;
+        }
+
+        while self.size + size > self.capacity {
+            let (_, evicted) = self.recency.pop_first().unwrap();
+            let (value, _) = self.entries.remove(&evicted).unwrap();
+            self.size -= value.byte_size();
+            self.stats.evictions += 1;
+        }
+
+        self.clock += 1;
+        self.recency.insert(self.clock, key.clone());
+        self.entries.insert(key, (value, self.clock));
+        self.size += size;
+    }
+
+    fn remove(&mut self, key: &K) {
+        if let Some((value, last_used)) = self.entries.remove(key) {
+            self.recency.remove(&last_used);
+            self.size -= value.byte_size();
+        }
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.recency.clear();
+        self.size = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::{CacheStats, CachedDatabase};
+    use crate::system::database::Database;
+
+    #[test]
+    fn least_recently_used_values_are_evicted() {
+        let mut db = CachedDatabase::new(HashMap::<u64, Vec<u8>>::new(), 3 * 100);
+        let pairs = (0..4).map(|i| (i, vec![i as u8; 100])).collect();
+        db.insert_multi(pairs).unwrap();
+
+        assert_eq!(db.get_multi(&[0, 1, 2]).unwrap()[2], vec![2; 100]);
+        assert_eq!(db.get(&0).unwrap(), vec![0; 100]);
+        assert_eq!(
+            db.stats(),
+            CacheStats {
+                hits: 1,
+                misses: 3,
+                evictions: 0
+            }
+        );
+
+        // 1 is the least recently used
+        db.get(&3).unwrap();
+        assert_eq!(db.stats().evictions, 1);
+        assert_eq!(db.cached_size(), 300);
+
+        db.reset_stats();
+        db.get_multi(&[0, 2, 3]).unwrap();
+        db.get(&1).unwrap();
+        assert_eq!(db.stats().hits, 3);
+        assert_eq!(db.stats().misses, 1);
+
+        db.remove(&1).unwrap();
+        assert!(!db.contains(&1));
+        assert_eq!(db.cached_size(), 200);
+    }
+
+    #[test]
+    fn written_values_are_not_read_stale() {
+        let mut db = CachedDatabase::new(HashMap::<u64, Vec<u8>>::new(), 1000);
+        db.insert(0, vec![0; 100]).unwrap();
+        db.insert_multi(vec![(1, vec![1; 100])]).unwrap();
+        db.get_multi(&[0, 1]).unwrap();
+        assert_eq!(db.cached_size(), 200);
+
+        // written keys are fetched from the database again, whatever it keeps for them
+        db.insert(0, vec![2; 100]).unwrap();
+        db.insert_multi(vec![(1, vec![3; 100])]).unwrap();
+        assert_eq!(db.cached_size(), 0);
+        assert_eq!(
+            db.get_multi(&[0, 1]).unwrap(),
+            db.database.get_multi(&[0, 1]).unwrap()
+        );
+        assert_eq!(db.cache_stats().unwrap().misses, 4);
+    }
+}
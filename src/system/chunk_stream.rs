@@ -0,0 +1,266 @@
+use std::sync::MutexGuard;
+use std::time::{Duration, Instant};
+
+use crate::{Chunk, Chunker, ChunkerRef};
+
+/// Splits a stream of data into chunks, one segment at a time.
+///
+/// Chunkers that implement [`StreamingChunker`][crate::StreamingChunker] keep their state between segments,
+/// so every byte is chunked once and segments are not copied. Other chunkers can't continue where they stopped,
+/// so they are run over the unfinished chunk of the previous segment together with the next segment.
+///
+/// The chunker is locked while the stream exists, so that streams sharing a chunker don't mix their states.
+pub(crate) struct ChunkStream<'a> {
+    chunker: MutexGuard<'a, dyn Chunker + 'static>,
+    /// Whether the chunker implements streaming.
+    streaming: bool,
+    /// Data of the unfinished chunk, which started in one of the previous segments.
+    tail: Vec<u8>,
+    /// Offset of the next segment from the start of the stream.
+    position: usize,
+}
+
+/// Segment of the data split into chunks by [`ChunkStream`].
+#[derive(Default)]
+pub(crate) struct ChunkedSegment {
+    /// Chunks that started in the previous segments and ended in this one, assembled from their parts.
+    heads: Vec<Vec<u8>>,
+    /// Chunks that lie within the segment, relative to its start.
+    chunks: Vec<Chunk>,
+    /// Time spent on chunking the segment.
+    pub chunk_time: Duration,
+}
+
+impl ChunkedSegment {
+    /// Creates a segment that consists of the given chunks only.
+    pub fn from_chunks(chunks: Vec<Vec<u8>>) -> Self {
+        Self {
+            heads: chunks,
+            ..Default::default()
+        }
+    }
+
+    /// Returns `true` if no chunks were finished in the segment.
+    pub fn is_empty(&self) -> bool {
+        self.heads.is_empty() && self.chunks.is_empty()
+    }
+
+    /// Returns contents of the chunks in the order of the data, `data` must be the segment that was chunked.
+    pub fn chunk_data<'d>(&'d self, data: &'d [u8]) -> Vec<&'d [u8]> {
+        self.heads
+            .iter()
+            .map(Vec::as_slice)
+            .chain(self.chunks.iter().map(|chunk| &data[chunk.range()]))
+            .collect()
+    }
+}
+
+impl<'a> ChunkStream<'a> {
+    pub fn new(chunker: &'a ChunkerRef) -> Self {
+        let mut chunker = chunker.lock().unwrap();
+        let streaming = match chunker.as_streaming() {
+            Some(streaming) => {
+                // discards the state left by a stream that was interrupted by an error
+                streaming.finish(vec![]);
+                true
+            }
+            None => false,
+        };
+
+        Self {
+            chunker,
+            streaming,
+            tail: vec![],
+            position: 0,
+        }
+    }
+
+    /// Chunks the next segment of the stream.
+    ///
+    /// Data after the last finished chunk is kept until the chunk is finished by the next segments or by [`finish`][Self::finish].
+    pub fn next(&mut self, data: &[u8]) -> ChunkedSegment {
+        if !self.streaming {
+            return self.rechunk(data);
+        }
+
+        let empty = Vec::with_capacity(self.chunker.estimate_chunk_count(data));
+        let streaming = self.chunker.as_streaming().unwrap();
+
+        let start = Instant::now();
+        let found = streaming.feed(data, empty);
+        let chunk_time = start.elapsed();
+
+        let position = self.position;
+        self.position += data.len();
+        let tail_start = position - self.tail.len();
+
+        let mut segment = ChunkedSegment {
+            chunk_time,
+            ..Default::default()
+        };
+        // end of the last found chunk, from the start of the stream
+        let mut end = tail_start;
+        for chunk in found {
+            end = chunk.offset() + chunk.length();
+            if chunk.offset() < position {
+                // the chunk starts in the tail
+                let tail_end = end.min(position) - tail_start;
+                let mut head = self.tail[chunk.offset() - tail_start..tail_end].to_vec();
+                head.extend_from_slice(&data[..end.saturating_sub(position)]);
+                segment.heads.push(head);
+            } else {
+                let chunk = Chunk::new(chunk.offset() - position, chunk.length());
+                segment.chunks.push(chunk);
+            }
+        }
+
+        if end < position {
+            self.tail.drain(..end - tail_start);
+            self.tail.extend_from_slice(data);
+        } else {
+            self.tail = data[end - position..].to_vec();
+        }
+        segment
+    }
+
+    /// Ends the stream and returns the chunks of the data after the last finished chunk.
+    pub fn finish(&mut self) -> ChunkedSegment {
+        let tail = std::mem::take(&mut self.tail);
+
+        let Some(streaming) = self.chunker.as_streaming() else {
+            let heads = if tail.is_empty() { vec![] } else { vec![tail] };
+            return ChunkedSegment::from_chunks(heads);
+        };
+        let tail_start = self.position - tail.len();
+        self.position = 0;
+
+        let start = Instant::now();
+        let found = streaming.finish(vec![]);
+        let chunk_time = start.elapsed();
+
+        let heads = found
+            .iter()
+            .map(|chunk| {
+                let start = chunk.offset() - tail_start;
+                tail[start..start + chunk.length()].to_vec()
+            })
+            .collect();
+        ChunkedSegment {
+            chunk_time,
+            ..ChunkedSegment::from_chunks(heads)
+        }
+    }
+
+    /// Chunks the segment prepended with the tail, for chunkers that can't keep their state between segments.
+    /// The last chunk becomes the new tail, as it may continue in the next segment.
+    fn rechunk(&mut self, data: &[u8]) -> ChunkedSegment {
+        let tail_length = self.tail.len();
+        let mut buffer = std::mem::take(&mut self.tail);
+        buffer.extend_from_slice(data);
+
+        let empty = Vec::with_capacity(self.chunker.estimate_chunk_count(&buffer));
+
+        let start = Instant::now();
+        let mut found = self.chunker.chunk_data(&buffer, empty);
+        let chunk_time = start.elapsed();
+
+        if let Some(last) = found.pop() {
+            self.tail = buffer[last.range()].to_vec();
+        }
+
+        let mut segment = ChunkedSegment {
+            chunk_time,
+            ..Default::default()
+        };
+        for chunk in found {
+            if chunk.offset() < tail_length {
+                segment.heads.push(buffer[chunk.range()].to_vec());
+            } else {
+                let chunk = Chunk::new(chunk.offset() - tail_length, chunk.length());
+                segment.chunks.push(chunk);
+            }
+        }
+        segment
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fmt::{Debug, Formatter};
+
+    use super::ChunkStream;
+    use crate::chunkers::{FSChunker, FastChunker};
+    use crate::{Chunk, Chunker, ChunkerRef, MB};
+
+    /// Fixed size chunker that doesn't implement streaming.
+    struct Restarting(FSChunker);
+
+    impl Debug for Restarting {
+        fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+            self.0.fmt(f)
+        }
+    }
+
+    impl Chunker for Restarting {
+        fn chunk_data(&mut self, data: &[u8], empty: Vec<Chunk>) -> Vec<Chunk> {
+            self.0.chunk_data(data, empty)
+        }
+
+        fn estimate_chunk_count(&self, data: &[u8]) -> usize {
+            self.0.estimate_chunk_count(data)
+        }
+    }
+
+    fn chunk_stream(chunker: ChunkerRef, data: &[u8], segment_size: usize) -> Vec<Vec<u8>> {
+        let mut stream = ChunkStream::new(&chunker);
+
+        let mut chunks = vec![];
+        for segment in data.chunks(segment_size) {
+            let chunked = stream.next(segment);
+            chunks.extend(chunked.chunk_data(segment).into_iter().map(<[u8]>::to_vec));
+        }
+        let last = stream.finish();
+        chunks.extend(last.chunk_data(&[]).into_iter().map(<[u8]>::to_vec));
+        chunks
+    }
+
+    #[test]
+    fn streaming_and_restarting_chunkers_find_same_chunks() {
+        let data = (0..3 * MB + 100)
+            .map(|i| (i % 251) as u8)
+            .collect::<Vec<_>>();
+
+        for segment_size in [1000, 4096, MB] {
+            let streamed = chunk_stream(FSChunker::new(4000).into(), &data, segment_size);
+            let restarted =
+                chunk_stream(Restarting(FSChunker::new(4000)).into(), &data, segment_size);
+
+            assert_eq!(streamed, restarted);
+            assert_eq!(streamed.concat(), data);
+            assert!(streamed[..streamed.len() - 1]
+                .iter()
+                .all(|chunk| chunk.len() == 4000));
+        }
+    }
+
+    #[test]
+    fn cdc_chunks_are_assembled_across_segments() {
+        let mut state = 1u64;
+        let data = (0..3 * MB + 100)
+            .map(|_| {
+                state = state.wrapping_mul(6364136223846793005).wrapping_add(1);
+                (state >> 56) as u8
+            })
+            .collect::<Vec<_>>();
+        let expected = FastChunker::default()
+            .chunk_data(&data, vec![])
+            .into_iter()
+            .map(|chunk| data[chunk.range()].to_vec())
+            .collect::<Vec<_>>();
+
+        for segment_size in [1000, 4096, MB] {
+            let streamed = chunk_stream(FastChunker::default().into(), &data, segment_size);
+            assert_eq!(streamed, expected);
+        }
+    }
+}
@@ -0,0 +1,335 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io;
+use std::os::unix::fs::FileExt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::system::aligned_buffer::{AlignedBuffer, BufferPool};
+use crate::system::data_block::{Alignment, DataInfo};
+
+/// Size of the number of values and of each value's offset and length in the metadata section.
+const METADATA_ENTRY_SIZE: u64 = 4;
+
+/// Layout of fixed-size containers, into which [`DiskDatabase`][crate::DiskDatabase] groups its values.
+/// Containers follow each other starting from the base offset.
+///
+/// Each container starts with a metadata section, followed by the data section with values in the order they were written.
+/// Metadata holds the number of values, then the offset and length of each value relative to the container start,
+/// all as little-endian `u32`. The rest of the metadata section is zeroed.
+#[derive(Clone, Copy)]
+pub(crate) struct ContainerLayout {
+    base: u64,
+    size: u64,
+    metadata_size: u64,
+}
+
+impl ContainerLayout {
+    /// Creates a layout of containers of the given size, the first of which starts at `base`.
+    /// The metadata section takes 1/64 of the container.
+    ///
+    /// # Errors
+    /// * `io::ErrorKind::InvalidInput` - if the size is not a multiple of the block size or doesn't fit into `u32`.
+    pub fn new(base: u64, size: u64, alignment: &Alignment) -> io::Result<Self> {
+        let block_size = match alignment {
+            Alignment::ByBlockSize(block_size) => *block_size,
+            Alignment::None => 1,
+        };
+        if size == 0 || !size.is_multiple_of(block_size) || size > u32::MAX as u64 {
+            let msg =
+                "container size must be a non-zero multiple of the block size and fit into u32";
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+        }
+
+        let metadata_size = (size / 64).next_multiple_of(block_size).max(block_size);
+        Ok(Self {
+            base,
+            size,
+            metadata_size: metadata_size.max(METADATA_ENTRY_SIZE * 3),
+        })
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Returns index of the container that contains the given offset.
+    pub fn index(&self, offset: u64) -> u64 {
+        (offset - self.base) / self.size
+    }
+
+    /// Returns the offset of the container with the given index.
+    pub fn start(&self, index: u64) -> u64 {
+        self.base + index * self.size
+    }
+
+    /// Returns the maximal number of values in a container.
+    fn max_values(&self) -> usize {
+        ((self.metadata_size - METADATA_ENTRY_SIZE) / (2 * METADATA_ENTRY_SIZE)) as usize
+    }
+}
+
+/// Container that values are being added to. It's kept in memory until it's full.
+struct OpenContainer {
+    index: u64,
+    buffer: AlignedBuffer,
+    /// Offsets and lengths of the values relative to the container start.
+    entries: Vec<(u32, u32)>,
+    /// End of the last value relative to the container start.
+    end: u64,
+}
+
+impl OpenContainer {
+    fn new(layout: &ContainerLayout, index: u64, buffers: &Arc<BufferPool>) -> Self {
+        Self {
+            index,
+            buffer: buffers.take(layout.size as usize),
+            entries: vec![],
+            end: layout.metadata_size,
+        }
+    }
+
+    /// Adds the value to the container and returns its location, or `None` if it doesn't fit.
+    fn push(&mut self, layout: &ContainerLayout, value: &[u8]) -> Option<DataInfo> {
+        let length = value.len() as u64;
+        if self.end + length > layout.size || self.entries.len() == layout.max_values() {
+            return None;
+        }
+
+        let start = self.end as usize;
+        self.buffer[start..start + value.len()].copy_from_slice(value);
+        self.entries.push((self.end as u32, length as u32));
+        self.end += length;
+
+        Some(DataInfo::new(
+            layout.start(self.index) + start as u64,
+            length,
+        ))
+    }
+
+    /// Returns the metadata section of the container.
+    fn metadata(&self, layout: &ContainerLayout, buffers: &Arc<BufferPool>) -> AlignedBuffer {
+        let mut metadata = buffers.take(layout.metadata_size as usize);
+        let fields = [self.entries.len() as u32].into_iter().chain(
+            self.entries
+                .iter()
+                .flat_map(|&(offset, length)| [offset, length]),
+        );
+        for (chunk, field) in metadata.chunks_exact_mut(4).zip(fields) {
+            chunk.copy_from_slice(&field.to_le_bytes());
+        }
+        metadata
+    }
+
+    /// Writes the container with its metadata section to the device.
+    fn write(
+        &self,
+        device: &File,
+        layout: &ContainerLayout,
+        buffers: &Arc<BufferPool>,
+    ) -> io::Result<()> {
+        let start = layout.start(self.index);
+        device.write_all_at(&self.metadata(layout, buffers), start)?;
+        device.write_all_at(
+            &self.buffer[layout.metadata_size as usize..],
+            start + layout.metadata_size,
+        )
+    }
+
+    fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+/// Containers of a [`DiskDatabase`][crate::DiskDatabase]: the one being filled,
+/// and the recently read ones, which are kept in memory to serve the following reads.
+pub(crate) struct Containers {
+    layout: ContainerLayout,
+    open: OpenContainer,
+    /// Recently read containers, the most recent one is at the back.
+    prefetched: Mutex<VecDeque<(u64, Arc<AlignedBuffer>)>>,
+    prefetch_capacity: usize,
+    /// Number of containers read from the device.
+    reads: AtomicUsize,
+    buffers: Arc<BufferPool>,
+}
+
+impl Containers {
+    /// Creates containers, the first of which starts at `offset`. The offset must be aligned by the container size.
+    pub fn new(
+        layout: ContainerLayout,
+        offset: u64,
+        prefetch_capacity: usize,
+        buffers: Arc<BufferPool>,
+    ) -> Self {
+        Self {
+            layout,
+            open: OpenContainer::new(&layout, layout.index(offset), &buffers),
+            prefetched: Mutex::default(),
+            prefetch_capacity,
+            reads: AtomicUsize::new(0),
+            buffers,
+        }
+    }
+
+    pub fn layout(&self) -> &ContainerLayout {
+        &self.layout
+    }
+
+    /// Returns the offset where the containers end, including the open one.
+    pub fn end(&self) -> u64 {
+        self.layout.start(self.open.index + 1)
+    }
+
+    /// Returns the number of recently read containers kept in memory.
+    pub fn cached(&self) -> usize {
+        self.prefetch_capacity
+    }
+
+    /// Returns the number of containers read from the device.
+    pub fn reads(&self) -> usize {
+        self.reads.load(Ordering::Relaxed)
+    }
+
+    /// Adds the values to the open container, writing it to the device and opening the next one when it's full.
+    /// Containers are not placed beyond `limit`.
+    ///
+    /// # Errors
+    /// * `io::ErrorKind::InvalidInput` - if a value doesn't fit into an empty container.
+    /// * `io::ErrorKind::OutOfMemory` - if there is no space for the next container on the device.
+    pub fn append(
+        &mut self,
+        device: &File,
+        limit: u64,
+        values: &[Vec<u8>],
+    ) -> io::Result<Vec<DataInfo>> {
+        let mut data_infos = Vec::with_capacity(values.len());
+
+        for value in values {
+            if let Some(data_info) = self.open.push(&self.layout, value) {
+                data_infos.push(data_info);
+                continue;
+            }
+            if self.open.is_empty() {
+                let msg = "value doesn't fit into a container";
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+            }
+
+            let next = self.open.index + 1;
+            if self.layout.start(next + 1) > limit {
+                return Err(io::Error::from(io::ErrorKind::OutOfMemory));
+            }
+            self.write_open(device)?;
+            self.open = OpenContainer::new(&self.layout, next, &self.buffers);
+
+            match self.open.push(&self.layout, value) {
+                Some(data_info) => data_infos.push(data_info),
+                None => {
+                    let msg = "value doesn't fit into a container";
+                    return Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+                }
+            }
+        }
+
+        Ok(data_infos)
+    }
+
+    /// Writes the open container to the device, so that it's not lost if the database is dropped.
+    /// The container stays open.
+    pub fn write_open(&self, device: &File) -> io::Result<()> {
+        self.open.write(device, &self.layout, &self.buffers)
+    }
+
+    /// Reads the values from their containers, every container is read from the device at most once.
+    pub fn read(&self, device: &File, data_infos: &[&DataInfo]) -> io::Result<Vec<Vec<u8>>> {
+        let mut current: Option<(u64, Arc<AlignedBuffer>)> = None;
+
+        data_infos
+            .iter()
+            .map(|data_info| {
+                let index = self.layout.index(data_info.offset());
+                let start = (data_info.offset() - self.layout.start(index)) as usize;
+                let end = start + data_info.data_length() as usize;
+
+                if index == self.open.index {
+                    return Ok(self.open.buffer[start..end].to_vec());
+                }
+
+                let container = match &current {
+                    Some((current_index, container)) if *current_index == index => container,
+                    _ => &current.insert((index, self.fetch(device, index)?)).1,
+                };
+                Ok(container[start..end].to_vec())
+            })
+            .collect()
+    }
+
+    /// Returns the container from the prefetched ones, or reads it from the device.
+    fn fetch(&self, device: &File, index: u64) -> io::Result<Arc<AlignedBuffer>> {
+        {
+            let mut prefetched = self.prefetched.lock().unwrap();
+            if let Some(position) = prefetched.iter().position(|(i, _)| *i == index) {
+                let entry = prefetched.remove(position).unwrap();
+                let container = Arc::clone(&entry.1);
+                prefetched.push_back(entry);
+                return Ok(container);
+            }
+        }
+
+        let mut container = self.buffers.take(self.layout.size as usize);
+        device.read_exact_at(&mut container, self.layout.start(index))?;
+        self.reads.fetch_add(1, Ordering::Relaxed);
+        let container = Arc::new(container);
+
+        if self.prefetch_capacity > 0 {
+            let mut prefetched = self.prefetched.lock().unwrap();
+            if prefetched.len() == self.prefetch_capacity {
+                prefetched.pop_front();
+            }
+            prefetched.push_back((index, Arc::clone(&container)));
+        }
+        Ok(container)
+    }
+
+    /// Drops all containers and starts filling a new one at `offset`.
+    pub fn reset(&mut self, offset: u64) {
+        self.open = OpenContainer::new(&self.layout, self.layout.index(offset), &self.buffers);
+        self.prefetched.lock().unwrap().clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ContainerLayout, OpenContainer};
+    use crate::system::aligned_buffer::BufferPool;
+    use crate::system::data_block::Alignment;
+
+    /// Parses the metadata section of a container and returns offsets and lengths of its values.
+    fn parse_metadata(container: &[u8]) -> Vec<(u32, u32)> {
+        let read_u32 = |at: usize| u32::from_le_bytes(container[at..at + 4].try_into().unwrap());
+
+        let count = read_u32(0) as usize;
+        (0..count)
+            .map(|i| (read_u32(4 + i * 8), read_u32(8 + i * 8)))
+            .collect()
+    }
+
+    #[test]
+    fn container_metadata_lists_values() {
+        let layout = ContainerLayout::new(0, 64 * 1024, &Alignment::ByBlockSize(512)).unwrap();
+        assert!(ContainerLayout::new(0, 1000, &Alignment::ByBlockSize(512)).is_err());
+
+        let buffers = BufferPool::new(512, 0);
+        let mut container = OpenContainer::new(&layout, 2, &buffers);
+        let first = container.push(&layout, &[1; 1000]).unwrap();
+        let second = container.push(&layout, &[2; 3000]).unwrap();
+        assert_eq!(first.offset(), 2 * 64 * 1024 + 1024);
+        assert_eq!(second.offset(), first.offset() + 1000);
+        assert!(container.push(&layout, &[3; 62 * 1024]).is_none());
+
+        let metadata = container.metadata(&layout, &buffers);
+        assert_eq!(metadata.len(), 1024);
+        assert_eq!(parse_metadata(&metadata), [(1024, 1000), (2024, 3000)]);
+        assert_eq!(container.buffer[2024..5024], [2; 3000]);
+    }
+}
@@ -1,8 +1,11 @@
-use bincode::{decode_from_slice, Decode};
+use bincode::{decode_from_slice, Decode, Encode};
 use std::io;
+use std::sync::Arc;
+
+use crate::system::aligned_buffer::{AlignedBuffer, BufferPool};
 
 /// Information about the location of the data on the disk.
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Encode, Decode)]
 pub struct DataInfo {
     /// Offset of the data on the block device.
     offset: u64,
@@ -11,12 +14,20 @@ pub struct DataInfo {
 }
 
 impl DataInfo {
-    fn new(offset: u64, data_length: u64) -> Self {
+    pub(crate) fn new(offset: u64, data_length: u64) -> Self {
         Self {
             offset,
             data_length,
         }
     }
+
+    pub(crate) fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    pub(crate) fn data_length(&self) -> u64 {
+        self.data_length
+    }
 }
 
 /// Type of the data alignment.
@@ -28,20 +39,23 @@ pub enum Alignment {
     ByBlockSize(u64),
 }
 
-/// Continuous data interval with information about internal values. Offsets of the internal values must be sequential and continuous.
+/// Continuous data interval with information about internal values. Internal values must be sorted by their offsets,
+/// and may be separated by gaps, which are read along with them.
 /// Need for more convenient large aggregated read requests.
 ///
 /// `Is not written to disk`, only used when processing read operations.
 ///
+/// The data is held in an [`AlignedBuffer`] taken from a [`BufferPool`], so that it can be read and written with O_DIRECT.
+///
 /// DataBlock is either [`without alignment`][Alignment::None] when the first value starts at the DataBlock's offset,
 /// or [`with alignment`][Alignment::ByBlockSize] by the block size, with padding at the beginning and end.
 #[derive(Debug)]
 pub struct DataBlock {
     /// Actual data of the DataBlock.
-    data: Vec<u8>,
+    data: AlignedBuffer,
     /// DataBlock offset. The first value may not be at this offset but after the padding if the DataBlock is aligned by some block size.
     offset: u64,
-    /// Internal values info. Must be sequential and continuous, so that each successive offset is equal to the previous offset plus the previous size.
+    /// Internal values info. Must be sorted by offsets, values written by [`Self::from_values`] are also continuous.
     data_infos: Vec<DataInfo>,
 }
 
@@ -62,31 +76,34 @@ impl DataBlock {
         self.data_infos
     }
 
-    /// Constructs a [`DataBlock`] from a vector of sequential and continuous [`DataInfo`].
+    /// Constructs a [`DataBlock`] from a vector of [`DataInfo`] sorted by offsets, which spans from the first value to the furthest end.
     ///
     /// Padded at the start and end by the block size if the corresponding alignment is passed.
-    /// If data_infos is empty or not sequential and continuous, then [`io::ErrorKind::InvalidData`] is returned.
-    fn from_data_infos(alignment: Alignment, data_infos: Vec<DataInfo>) -> io::Result<Self> {
+    /// If data_infos is empty or not sorted, then [`io::ErrorKind::InvalidData`] is returned.
+    fn from_data_infos(
+        buffers: &Arc<BufferPool>,
+        alignment: Alignment,
+        data_infos: Vec<DataInfo>,
+    ) -> io::Result<Self> {
         if data_infos.is_empty() {
             return Err(io::Error::from(io::ErrorKind::InvalidData));
         }
-        for (i, data_info) in data_infos.iter().enumerate().skip(1) {
-            if data_info.offset != data_infos[i - 1].offset + data_infos[i - 1].data_length {
-                return Err(io::Error::from(io::ErrorKind::InvalidData));
-            }
+        if !data_infos.is_sorted_by_key(|data_info| data_info.offset) {
+            return Err(io::Error::from(io::ErrorKind::InvalidData));
         }
 
         let first = data_infos.first().unwrap();
-        let last = data_infos.last().unwrap();
-        let (start_padding, end_padding) = start_and_end_padding_of_datablock(
-            first.offset,
-            last.offset + last.data_length,
-            alignment,
-        );
-        let total_len = last.offset + last.data_length - first.offset + start_padding + end_padding;
+        let end = data_infos
+            .iter()
+            .map(|data_info| data_info.offset + data_info.data_length)
+            .max()
+            .unwrap();
+        let (start_padding, end_padding) =
+            start_and_end_padding_of_datablock(first.offset, end, alignment);
+        let total_len = end - first.offset + start_padding + end_padding;
 
         Ok(Self {
-            data: vec![0; total_len as usize],
+            data: buffers.take(total_len as usize),
             offset: first.offset - start_padding,
             data_infos,
         })
@@ -99,8 +116,9 @@ impl DataBlock {
     ///
     /// Returns [`io::ErrorKind::InvalidData`] if data_infos is empty.
     pub fn from_values(
+        buffers: &Arc<BufferPool>,
         alignment: Alignment,
-        mut values: Vec<Vec<u8>>,
+        values: Vec<Vec<u8>>,
         mut offset: u64,
     ) -> io::Result<Self> {
         if values.is_empty() {
@@ -120,9 +138,13 @@ impl DataBlock {
             last_data_info.offset + last_data_info.data_length,
             alignment.clone(),
         );
-        values.push(vec![0; end_padding as usize]);
-        values.insert(0, vec![0; start_padding as usize]);
-        let data = values.concat();
+        let total_len = offset - given_offset + start_padding + end_padding;
+        let mut data = buffers.take(total_len as usize);
+        let mut start = start_padding as usize;
+        for value in &values {
+            data[start..start + value.len()].copy_from_slice(value);
+            start += value.len();
+        }
 
         Ok(Self {
             data,
@@ -131,43 +153,77 @@ impl DataBlock {
         })
     }
 
-    /// Split [`DataInfo`] vector into continuous intervals ([`DataBlock`]'s).
+    /// Split [`DataInfo`] vector into intervals ([`DataBlock`]'s), each read with a single request.
     ///
-    /// If some intervals follow each other by offsets but don't follow each other in the given vector, they are split into different intervals.
-    pub fn split_to_datablocks(alignment: Alignment, data_infos: Vec<&DataInfo>) -> Vec<Self> {
-        if data_infos.is_empty() {
-            return vec![];
-        }
-
-        let mut sequential_data_infos = vec![vec![data_infos[0].clone()]];
-        for &data_info in data_infos[1..].iter() {
-            let last_seq = sequential_data_infos.last_mut().unwrap();
-            let last = last_seq.last().unwrap();
-
-            if data_info.offset == last.offset + last.data_length {
-                last_seq.push(data_info.clone());
+    /// Values are sorted by their offsets, repeated values are read once.
+    /// Values separated by gaps of at most `max_gap` bytes are merged into one interval, and the gaps are read along with them.
+    /// An interval is closed once merging the next value would make it longer than `max_read_size` bytes, not counting
+    /// the alignment padding. A value that is longer by itself is read alone.
+    /// The order of the given vector is kept by the returned [`CoalescedRead`].
+    pub fn split_to_datablocks(
+        buffers: &Arc<BufferPool>,
+        alignment: Alignment,
+        data_infos: Vec<&DataInfo>,
+        max_gap: u64,
+        max_read_size: u64,
+    ) -> CoalescedRead {
+        let mut sorted = (0..data_infos.len()).collect::<Vec<_>>();
+        sorted.sort_by_key(|&i| (data_infos[i].offset, data_infos[i].data_length));
+
+        let mut order = vec![0; data_infos.len()];
+        let mut intervals: Vec<Vec<DataInfo>> = vec![];
+        let mut unique_values = 0;
+        let mut interval_start = 0u64;
+        let mut interval_end = 0u64;
+        for (position, &i) in sorted.iter().enumerate() {
+            let data_info = data_infos[i];
+            if position > 0 && data_info == data_infos[sorted[position - 1]] {
+                order[i] = unique_values - 1;
                 continue;
             }
-            sequential_data_infos.push(vec![data_info.clone()]);
+
+            let end = data_info.offset + data_info.data_length;
+            match intervals.last_mut() {
+                Some(interval)
+                    if data_info.offset <= interval_end.saturating_add(max_gap)
+                        && interval_end.max(end) - interval_start <= max_read_size =>
+                {
+                    interval.push(data_info.clone());
+                    interval_end = interval_end.max(end);
+                }
+                _ => {
+                    intervals.push(vec![data_info.clone()]);
+                    interval_start = data_info.offset;
+                    interval_end = end;
+                }
+            }
+            order[i] = unique_values;
+            unique_values += 1;
         }
 
-        sequential_data_infos
+        let datablocks = intervals
             .into_iter()
-            .map(|seq| DataBlock::from_data_infos(alignment.clone(), seq))
+            .map(|interval| DataBlock::from_data_infos(buffers, alignment.clone(), interval))
             .collect::<io::Result<Vec<DataBlock>>>()
-            .unwrap()
+            .unwrap();
+        CoalescedRead { datablocks, order }
+    }
+
+    /// Returns the internal values of the datablock, without padding.
+    pub fn values(&self) -> impl Iterator<Item = &[u8]> {
+        self.data_infos.iter().map(|data_info| {
+            let start = (data_info.offset - self.offset) as usize;
+            &self.data[start..start + data_info.data_length as usize]
+        })
     }
 
     /// Decode each internal value of each datablock and concat them into a vector of decoded values.
     pub fn decode_datablocks<T: Decode<()>>(datablocks: Vec<&Self>) -> io::Result<Vec<T>> {
         let mut decoded = vec![];
         datablocks.iter().try_for_each(|&datablock| {
-            datablock.data_infos.iter().try_for_each(|data_info| {
-                let start = (data_info.offset - datablock.offset) as usize;
-                let end = start + data_info.data_length as usize;
-                let (value, _) =
-                    decode_from_slice(&datablock.data[start..end], bincode::config::standard())
-                        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            datablock.values().try_for_each(|value| {
+                let (value, _) = decode_from_slice(value, bincode::config::standard())
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
                 decoded.push(value);
                 Ok::<(), io::Error>(())
             })
@@ -176,6 +232,56 @@ impl DataBlock {
     }
 }
 
+/// Values requested by a single read, merged into datablocks by [`DataBlock::split_to_datablocks`].
+#[derive(Debug)]
+pub struct CoalescedRead {
+    /// Datablocks sorted by offsets.
+    datablocks: Vec<DataBlock>,
+    /// Index of each requested value, in the order of the request, among the values of the datablocks.
+    order: Vec<usize>,
+}
+
+impl CoalescedRead {
+    pub fn datablocks_mut(&mut self) -> Vec<&mut DataBlock> {
+        self.datablocks.iter_mut().collect()
+    }
+
+    /// Returns the requested values in the order of the request.
+    pub fn values(&self) -> Vec<&[u8]> {
+        let values = self
+            .datablocks
+            .iter()
+            .flat_map(DataBlock::values)
+            .collect::<Vec<_>>();
+        self.order.iter().map(|&i| values[i]).collect()
+    }
+
+    /// Decodes the requested values and returns them in the order of the request.
+    ///
+    /// Each value is decoded once, and cloned if it was requested several times.
+    pub fn decode<T: Clone + Decode<()>>(&self) -> io::Result<Vec<T>> {
+        let mut uses = vec![0; self.order.len()];
+        self.order.iter().for_each(|&i| uses[i] += 1);
+
+        let mut decoded = DataBlock::decode_datablocks::<T>(self.datablocks.iter().collect())?
+            .into_iter()
+            .map(Some)
+            .collect::<Vec<_>>();
+        Ok(self
+            .order
+            .iter()
+            .map(|&i| {
+                uses[i] -= 1;
+                if uses[i] == 0 {
+                    decoded[i].take().unwrap()
+                } else {
+                    decoded[i].clone().unwrap()
+                }
+            })
+            .collect())
+    }
+}
+
 /// Looks for the complement of a number up to a multiple of the block size.
 ///
 /// For example, the result for 1000 with a block size of 512 would be 24.
@@ -208,6 +314,10 @@ mod tests {
     use crate::MB;
     use bincode::encode_to_vec;
 
+    fn buffers() -> Arc<BufferPool> {
+        BufferPool::new(512, 0)
+    }
+
     #[test]
     fn padding_to_multiple_block_ok() {
         assert_eq!(20, padding_to_multiple_block_size(490, 510));
@@ -236,29 +346,33 @@ mod tests {
 
     #[test]
     fn from_data_infos_empty_fails() {
-        let res = DataBlock::from_data_infos(Alignment::None, vec![]);
+        let res = DataBlock::from_data_infos(&buffers(), Alignment::None, vec![]);
         assert_eq!(res.unwrap_err().kind(), io::ErrorKind::InvalidData);
     }
 
     #[test]
     fn from_data_infos_not_sequential_fails() {
         let data_infos = vec![DataInfo::new(100, 100), DataInfo::new(0, 100)];
-        let res = DataBlock::from_data_infos(Alignment::None, data_infos);
+        let res = DataBlock::from_data_infos(&buffers(), Alignment::None, data_infos);
         assert_eq!(res.unwrap_err().kind(), io::ErrorKind::InvalidData);
     }
 
     #[test]
-    fn from_data_infos_not_continuous_fails() {
-        let data_infos = vec![DataInfo::new(0, 100), DataInfo::new(101, 100)];
-        let res = DataBlock::from_data_infos(Alignment::None, data_infos);
-        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::InvalidData);
+    fn from_data_infos_with_gap_ok() {
+        let data_infos = vec![DataInfo::new(0, 100), DataInfo::new(150, 100)];
+        let datablock =
+            DataBlock::from_data_infos(&buffers(), Alignment::None, data_infos.clone()).unwrap();
+        assert_eq!(datablock.data.len(), 250);
+        assert_eq!(datablock.offset, 0);
+        assert_eq!(datablock.data_infos, data_infos);
     }
 
     #[test]
     fn from_single_data_info_without_alignment_ok() {
         let data_info = DataInfo::new(50, 150);
         let data_infos = vec![data_info.clone()];
-        let datablock = DataBlock::from_data_infos(Alignment::None, data_infos).unwrap();
+        let datablock =
+            DataBlock::from_data_infos(&buffers(), Alignment::None, data_infos).unwrap();
         assert_eq!(datablock.data.len(), 150);
         assert_eq!(datablock.offset, 50);
         assert_eq!(datablock.data_infos, vec![data_info.clone()]);
@@ -269,7 +383,8 @@ mod tests {
         let data_info = DataInfo::new(50, 150);
         let data_infos = vec![data_info.clone()];
         let datablock =
-            DataBlock::from_data_infos(Alignment::ByBlockSize(512), data_infos).unwrap();
+            DataBlock::from_data_infos(&buffers(), Alignment::ByBlockSize(512), data_infos)
+                .unwrap();
         assert_eq!(datablock.data.len(), 512);
         assert_eq!(datablock.offset, 0);
         assert_eq!(datablock.data_infos, vec![data_info.clone()]);
@@ -280,7 +395,8 @@ mod tests {
         let data_info = DataInfo::new(400, 150);
         let data_infos = vec![data_info.clone()];
         let datablock =
-            DataBlock::from_data_infos(Alignment::ByBlockSize(512), data_infos).unwrap();
+            DataBlock::from_data_infos(&buffers(), Alignment::ByBlockSize(512), data_infos)
+                .unwrap();
         assert_eq!(datablock.data.len(), 512 * 2);
         assert_eq!(datablock.offset, 0);
         assert_eq!(datablock.data_infos, vec![data_info.clone()]);
@@ -293,7 +409,8 @@ mod tests {
             DataInfo::new(200, 500),
             DataInfo::new(700, 1024),
         ];
-        let datablock = DataBlock::from_data_infos(Alignment::None, data_infos.clone()).unwrap();
+        let datablock =
+            DataBlock::from_data_infos(&buffers(), Alignment::None, data_infos.clone()).unwrap();
         assert_eq!(datablock.data.len(), 150 + 500 + 1024);
         assert_eq!(datablock.offset, 50);
         assert_eq!(datablock.data_infos, data_infos.clone());
@@ -307,7 +424,8 @@ mod tests {
             DataInfo::new(1200, 1024),
         ];
         let datablock =
-            DataBlock::from_data_infos(Alignment::ByBlockSize(512), data_infos.clone()).unwrap();
+            DataBlock::from_data_infos(&buffers(), Alignment::ByBlockSize(512), data_infos.clone())
+                .unwrap();
         assert_eq!(datablock.data.len(), 512 * 4);
         assert_eq!(datablock.offset, 512);
         assert_eq!(datablock.data_infos, data_infos.clone());
@@ -315,7 +433,7 @@ mod tests {
 
     #[test]
     fn from_values_empty_fails() {
-        let res = DataBlock::from_values(Alignment::None, vec![], 10);
+        let res = DataBlock::from_values(&buffers(), Alignment::None, vec![], 10);
         assert_eq!(res.unwrap_err().kind(), io::ErrorKind::InvalidData);
     }
 
@@ -323,10 +441,10 @@ mod tests {
     fn from_single_value_without_alignment_ok() {
         let data = vec![1; 1500];
         let values = vec![data.clone()];
-        let datablock = DataBlock::from_values(Alignment::None, values, 150).unwrap();
+        let datablock = DataBlock::from_values(&buffers(), Alignment::None, values, 150).unwrap();
         assert_eq!(datablock.data.len(), 1500);
         assert_eq!(datablock.offset, 150);
-        assert_eq!(datablock.data, data);
+        assert_eq!(*datablock.data, data);
         assert_eq!(datablock.data_infos, vec![DataInfo::new(150, 1500)]);
     }
 
@@ -334,11 +452,12 @@ mod tests {
     fn from_single_value_with_alignment_ok() {
         let data = vec![1; 1500];
         let values = vec![data.clone()];
-        let datablock = DataBlock::from_values(Alignment::ByBlockSize(512), values, 150).unwrap();
+        let datablock =
+            DataBlock::from_values(&buffers(), Alignment::ByBlockSize(512), values, 150).unwrap();
         assert_eq!(datablock.data.len(), 512 * 4);
         assert_eq!(datablock.offset, 0);
         assert_eq!(
-            datablock.data,
+            *datablock.data,
             [vec![0; 150], data, vec![0; 512 * 4 - 1500 - 150]].concat()
         );
         assert_eq!(datablock.data_infos, vec![DataInfo::new(150, 1500)]);
@@ -347,10 +466,11 @@ mod tests {
     #[test]
     fn from_multi_values_without_alignment_ok() {
         let values = vec![vec![1; 150], vec![2; 500], vec![3; 1024]];
-        let datablock = DataBlock::from_values(Alignment::None, values.clone(), 50).unwrap();
+        let datablock =
+            DataBlock::from_values(&buffers(), Alignment::None, values.clone(), 50).unwrap();
         assert_eq!(datablock.data.len(), 150 + 500 + 1024);
         assert_eq!(datablock.offset, 50);
-        assert_eq!(datablock.data, values.concat());
+        assert_eq!(*datablock.data, values.concat());
         assert_eq!(
             datablock.data_infos,
             vec![
@@ -365,7 +485,8 @@ mod tests {
     fn from_multi_values_with_alignment_ok() {
         let values = vec![vec![1; 150], vec![2; 500], vec![3; 1024]];
         let datablock =
-            DataBlock::from_values(Alignment::ByBlockSize(512), values.clone(), 50).unwrap();
+            DataBlock::from_values(&buffers(), Alignment::ByBlockSize(512), values.clone(), 50)
+                .unwrap();
         assert_eq!(datablock.data.len(), 512 * 4);
         assert_eq!(datablock.offset, 0);
         let datablock_expected_data = [
@@ -374,7 +495,7 @@ mod tests {
             vec![0; 512 * 4 - 50 - 150 - 500 - 1024],
         ]
         .concat();
-        assert_eq!(datablock.data, datablock_expected_data);
+        assert_eq!(*datablock.data, datablock_expected_data);
         assert_eq!(
             datablock.data_infos,
             vec![
@@ -387,8 +508,9 @@ mod tests {
 
     #[test]
     fn split_to_datablocks_empty_ok() {
-        let datablocks = DataBlock::split_to_datablocks(Alignment::None, vec![]);
-        assert!(datablocks.is_empty());
+        let read = DataBlock::split_to_datablocks(&buffers(), Alignment::None, vec![], 0, u64::MAX);
+        assert!(read.datablocks.is_empty());
+        assert!(read.values().is_empty());
     }
 
     #[test]
@@ -400,10 +522,14 @@ mod tests {
             DataInfo::new(5000, 1024),
             DataInfo::new(6024, 1024),
         ];
-        let datablocks = DataBlock::split_to_datablocks(
+        let read = DataBlock::split_to_datablocks(
+            &buffers(),
             Alignment::ByBlockSize(512),
             data_infos.iter().collect(),
+            0,
+            u64::MAX,
         );
+        let datablocks = &read.datablocks;
         assert_eq!(datablocks.len(), 2);
 
         assert_eq!(datablocks[0].offset, 512 * 3);
@@ -435,14 +561,32 @@ mod tests {
             DataInfo::new(900, 4500),
             DataInfo::new(4000, 30),
         ];
-        let datablocks =
-            DataBlock::split_to_datablocks(Alignment::None, data_infos.iter().collect());
-        assert_eq!(datablocks.len(), 3);
+        let read = DataBlock::split_to_datablocks(
+            &buffers(),
+            Alignment::None,
+            data_infos.iter().collect(),
+            0,
+            u64::MAX,
+        );
+        let datablocks = &read.datablocks;
+        assert_eq!(datablocks.len(), 2);
 
-        assert_eq!(datablocks[0].offset, 10200);
-        assert_eq!(datablocks[0].data.len(), 1500 + 550 + 5000);
+        // the value at 4000 lies inside the one at 900, so it's read with it
+        assert_eq!(datablocks[0].offset, 200);
+        assert_eq!(datablocks[0].data.len(), 700 + 4500);
         assert_eq!(
             datablocks[0].data_infos,
+            vec![
+                DataInfo::new(200, 700),
+                DataInfo::new(900, 4500),
+                DataInfo::new(4000, 30),
+            ]
+        );
+
+        assert_eq!(datablocks[1].offset, 10200);
+        assert_eq!(datablocks[1].data.len(), 1500 + 550 + 5000);
+        assert_eq!(
+            datablocks[1].data_infos,
             vec![
                 DataInfo::new(10200, 1500),
                 DataInfo::new(11700, 550),
@@ -450,16 +594,103 @@ mod tests {
             ]
         );
 
-        assert_eq!(datablocks[1].offset, 200);
-        assert_eq!(datablocks[1].data.len(), 700 + 4500);
+        let lengths = read
+            .values()
+            .iter()
+            .map(|value| value.len() as u64)
+            .collect::<Vec<_>>();
+        assert_eq!(lengths, [1500, 550, 5000, 700, 4500, 30]);
+    }
+
+    #[test]
+    fn split_to_datablocks_merges_gaps_and_keeps_request_order() {
+        let values = (0..6u8)
+            .map(|i| vec![i; 100 * (i as usize + 1)])
+            .collect::<Vec<_>>();
+        let encoded = values
+            .iter()
+            .map(|value| encode_to_vec(value, bincode::config::standard()).unwrap())
+            .collect::<Vec<_>>();
+        let written =
+            DataBlock::from_values(&buffers(), Alignment::None, encoded.clone(), 1000).unwrap();
+        let data_infos = written.data_infos.clone();
+
+        // values 1 and 3 are not requested, so 0 and 2 are separated by a gap of 201 bytes,
+        // and 2 and 4 by a gap of 403 bytes
+        let request = [4, 0, 2, 4, 5, 0];
+        let requested = request.iter().map(|&i| &data_infos[i]).collect();
+        let mut read = DataBlock::split_to_datablocks(
+            &buffers(),
+            Alignment::ByBlockSize(512),
+            requested,
+            300,
+            u64::MAX,
+        );
+        assert_eq!(read.datablocks.len(), 2);
         assert_eq!(
-            datablocks[1].data_infos,
-            vec![DataInfo::new(200, 700), DataInfo::new(900, 4500),]
+            read.datablocks[0].data_infos,
+            vec![data_infos[0].clone(), data_infos[2].clone()]
+        );
+        assert_eq!(
+            read.datablocks[1].data_infos,
+            vec![data_infos[4].clone(), data_infos[5].clone()]
+        );
+
+        let mut device = vec![0; 8 * KB];
+        device[1000..1000 + written.data.len()].copy_from_slice(&written.data);
+        for datablock in read.datablocks_mut() {
+            let offset = datablock.offset as usize;
+            let length = datablock.data.len();
+            datablock
+                .data_mut()
+                .copy_from_slice(&device[offset..offset + length]);
+        }
+
+        let expected_encoded = request.iter().map(|&i| &encoded[i][..]).collect::<Vec<_>>();
+        assert_eq!(read.values(), expected_encoded);
+        let decoded: Vec<Vec<u8>> = read.decode().unwrap();
+        let expected = request
+            .iter()
+            .map(|&i| values[i].clone())
+            .collect::<Vec<_>>();
+        assert_eq!(decoded, expected);
+    }
+
+    #[test]
+    fn split_to_datablocks_closes_intervals_at_max_read_size() {
+        let data_infos = (0..10)
+            .map(|i| DataInfo::new(1000 * i, 1000))
+            .chain([DataInfo::new(9500, 200), DataInfo::new(12_000, 5000)])
+            .collect::<Vec<_>>();
+        let read = DataBlock::split_to_datablocks(
+            &buffers(),
+            Alignment::None,
+            data_infos.iter().collect(),
+            0,
+            3000,
         );
 
-        assert_eq!(datablocks[2].offset, 4000);
-        assert_eq!(datablocks[2].data.len(), 30);
-        assert_eq!(datablocks[2].data_infos, vec![DataInfo::new(4000, 30),]);
+        let intervals = read
+            .datablocks
+            .iter()
+            .map(|datablock| (datablock.offset, datablock.data.len()))
+            .collect::<Vec<_>>();
+        // the value at 9500 lies inside the one at 9000, the last one is read alone
+        assert_eq!(
+            intervals,
+            [
+                (0, 3000),
+                (3000, 3000),
+                (6000, 3000),
+                (9000, 1000),
+                (12_000, 5000)
+            ]
+        );
+        assert!(read
+            .values()
+            .iter()
+            .zip(&data_infos)
+            .all(|(value, data_info)| value.len() == data_info.data_length() as usize));
     }
 
     #[test]
@@ -468,13 +699,18 @@ mod tests {
         let data_vectors2 = vec![vec![4; 500 * KB], vec![2; 2 * MB], vec![10; 10]];
         let encoded1 = encode_to_vec(data_vectors1.clone(), bincode::config::standard()).unwrap();
         let encoded2 = encode_to_vec(data_vectors2.clone(), bincode::config::standard()).unwrap();
-        let datablock1 =
-            DataBlock::from_values(Alignment::ByBlockSize(2300), vec![encoded1, encoded2], 5000)
-                .unwrap();
+        let datablock1 = DataBlock::from_values(
+            &buffers(),
+            Alignment::ByBlockSize(2300),
+            vec![encoded1, encoded2],
+            5000,
+        )
+        .unwrap();
 
         let data_vectors3 = vec![vec![9; 400 * KB], vec![150; MB + 1], vec![]];
         let encoded3 = encode_to_vec(data_vectors3.clone(), bincode::config::standard()).unwrap();
-        let datablock2 = DataBlock::from_values(Alignment::None, vec![encoded3], 200).unwrap();
+        let datablock2 =
+            DataBlock::from_values(&buffers(), Alignment::None, vec![encoded3], 200).unwrap();
 
         let mut decoded: Vec<Vec<Vec<i32>>> =
             DataBlock::decode_datablocks(vec![&datablock2, &datablock1]).unwrap();
@@ -1,6 +1,10 @@
-use crate::ChunkHash;
+use crate::{CacheStats, ChunkHash};
+use bincode::{Decode, Encode};
 use std::collections::HashMap;
+use std::fs::File;
 use std::io;
+use std::path::Path;
+use std::sync::RwLock;
 
 /// Serves as base functionality for storing the actual data as key-value pairs.
 ///
@@ -31,15 +35,46 @@ pub trait Database<K, V> {
 
     /// Returns `true` if the database contains a value for the specified key.
     fn contains(&self, key: &K) -> bool;
+
+    /// Removes a key-value pair from the storage.
+    ///
+    /// The default implementation doesn't support removal and always fails.
+    ///
+    /// # Errors
+    /// Should return [ErrorKind::NotFound], if the key-value pair
+    /// was not found in the storage.
+    /// Returns [ErrorKind::Unsupported], if the database doesn't support removal.
+    fn remove(&mut self, _key: &K) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "database doesn't support removal",
+        ))
+    }
+
+    /// Returns hit and miss counters of the cache in front of the database,
+    /// or `None` if the database is not [cached][crate::CachedDatabase].
+    fn cache_stats(&self) -> Option<CacheStats> {
+        None
+    }
 }
 
 /// Allows iteration over database contents.
+///
+/// Values are given out as copies, so that databases that don't keep values in memory,
+/// e.g. [`DiskDatabase`][crate::DiskDatabase], can be iterated too.
+/// Values are changed in place with [`for_each_mut`][IterableDatabase::for_each_mut].
+/// Reading a value may fail for such databases, so iterators yield a [`Result`][io::Result] for every value.
 pub trait IterableDatabase<K, V>: Database<K, V> {
-    /// Returns a simple immutable iterator over values.
-    fn iterator(&self) -> Box<dyn Iterator<Item = (&K, &V)> + '_>;
+    /// Returns an immutable iterator over keys and value copies.
+    fn iterator(&self) -> Box<dyn Iterator<Item = io::Result<(&K, V)>> + '_>;
 
-    /// Returns an iterator that can mutate values but not keys.
-    fn iterator_mut(&mut self) -> Box<dyn Iterator<Item = (&K, &mut V)> + '_>;
+    /// Calls `f` on every key-value pair, allowing it to mutate values but not keys.
+    /// Changed values are written back to the database.
+    ///
+    /// # Errors
+    /// Stops at the first error returned by `f` or by the database and returns it.
+    /// Values changed before the error stay changed.
+    fn for_each_mut(&mut self, f: &mut dyn FnMut(&K, &mut V) -> io::Result<()>) -> io::Result<()>;
 
     /// Returns an immutable iterator over keys.
     fn keys<'a>(&'a self) -> Box<dyn Iterator<Item = &'a K> + 'a>
@@ -47,20 +82,76 @@ pub trait IterableDatabase<K, V>: Database<K, V> {
         V: 'a;
 
     //// Returns an immutable iterator over value copies.
-    fn values(&self) -> Box<dyn Iterator<Item = V> + '_>;
-
-    /// Returns a mutable iterator over values.
-    fn values_mut<'a>(&'a mut self) -> Box<dyn Iterator<Item = &'a mut V> + 'a>
-    where
-        K: 'a,
-    {
-        Box::new(self.iterator_mut().map(|(_, v)| v))
-    }
+    fn values(&self) -> Box<dyn Iterator<Item = io::Result<V>> + '_>;
 
     /// Clears the database, removing all contained key-value pairs.
     fn clear(&mut self) -> io::Result<()>;
 }
 
+/// Allows saving database contents to disk and loading them back, so that the database can be reused between runs.
+pub trait PersistentDatabase<K, V>: Database<K, V> + Sized {
+    /// Saves the database to the given path, overwriting the existing file.
+    fn save(&self, path: &Path) -> io::Result<()>;
+
+    /// Loads the database previously saved to the given path by [`save`][PersistentDatabase::save].
+    fn load(path: &Path) -> io::Result<Self>;
+}
+
+/// Database that can be shared between threads and modified by several of them at once.
+///
+/// Unlike [`Database`], all methods take `&self`, synchronization is up to the implementation.
+/// Any database can be made concurrent by wrapping it into [`RwLock`],
+/// [`ShardedDatabase`][crate::ShardedDatabase] allows inserts into different shards to run in parallel.
+pub trait ConcurrentDatabase<K, V>: Send + Sync {
+    /// Inserts a key-value pair into the storage. If the key is already present, then nothing happens.
+    fn insert(&self, key: K, value: V) -> io::Result<()>;
+
+    /// Retrieves a value by a given key.
+    ///
+    /// # Errors
+    /// Should return [ErrorKind::NotFound], if the key-value pair
+    /// was not found in the storage.
+    fn get(&self, key: &K) -> io::Result<V>;
+
+    /// Try inserts multiple key-value pairs into the storage.
+    fn insert_multi(&self, pairs: Vec<(K, V)>) -> io::Result<()> {
+        for (key, value) in pairs.into_iter() {
+            self.insert(key, value)?;
+        }
+        Ok(())
+    }
+
+    /// Retrieves a multitude of values, corresponding to the keys, in the correct order.
+    fn get_multi(&self, keys: &[K]) -> io::Result<Vec<V>> {
+        keys.iter().map(|key| self.get(key)).collect()
+    }
+
+    /// Returns `true` if the database contains a value for the specified key.
+    fn contains(&self, key: &K) -> bool;
+
+    /// Removes a key-value pair from the storage.
+    ///
+    /// # Errors
+    /// Should return [ErrorKind::NotFound], if the key-value pair
+    /// was not found in the storage.
+    fn remove(&self, key: &K) -> io::Result<()>;
+}
+
+/// Encodes the value with bincode and writes it to a file by the given path, overwriting the existing file.
+pub(crate) fn encode_to_file<E: Encode>(value: &E, path: &Path) -> io::Result<()> {
+    let mut writer = io::BufWriter::new(File::create(path)?);
+    bincode::encode_into_std_write(value, &mut writer, bincode::config::standard())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    io::Write::flush(&mut writer)
+}
+
+/// Reads a file by the given path and decodes its contents with bincode.
+pub(crate) fn decode_from_file<D: Decode<()>>(path: &Path) -> io::Result<D> {
+    let mut reader = io::BufReader::new(File::open(path)?);
+    bincode::decode_from_std_read(&mut reader, bincode::config::standard())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
 impl<Hash: ChunkHash, V: Clone> Database<Hash, V> for HashMap<Hash, V> {
     fn insert(&mut self, key: Hash, value: V) -> io::Result<()> {
         self.entry(key).or_insert(value);
@@ -74,15 +165,24 @@ impl<Hash: ChunkHash, V: Clone> Database<Hash, V> for HashMap<Hash, V> {
     fn contains(&self, key: &Hash) -> bool {
         self.contains_key(key)
     }
+
+    fn remove(&mut self, key: &Hash) -> io::Result<()> {
+        HashMap::remove(self, key)
+            .map(|_| ())
+            .ok_or(io::ErrorKind::NotFound.into())
+    }
 }
 
 impl<Hash: ChunkHash, V: Clone> IterableDatabase<Hash, V> for HashMap<Hash, V> {
-    fn iterator(&self) -> Box<dyn Iterator<Item = (&Hash, &V)> + '_> {
-        Box::new(self.iter())
+    fn iterator(&self) -> Box<dyn Iterator<Item = io::Result<(&Hash, V)>> + '_> {
+        Box::new(self.iter().map(|(k, v)| Ok((k, v.clone()))))
     }
 
-    fn iterator_mut(&mut self) -> Box<dyn Iterator<Item = (&Hash, &mut V)> + '_> {
-        Box::new(self.iter_mut())
+    fn for_each_mut(
+        &mut self,
+        f: &mut dyn FnMut(&Hash, &mut V) -> io::Result<()>,
+    ) -> io::Result<()> {
+        self.iter_mut().try_for_each(|(k, v)| f(k, v))
     }
 
     fn keys<'a>(&'a self) -> Box<dyn Iterator<Item = &'a Hash> + 'a>
@@ -92,8 +192,8 @@ impl<Hash: ChunkHash, V: Clone> IterableDatabase<Hash, V> for HashMap<Hash, V> {
         Box::new(self.keys())
     }
 
-    fn values(&self) -> Box<dyn Iterator<Item = V> + '_> {
-        Box::new(self.values().cloned())
+    fn values(&self) -> Box<dyn Iterator<Item = io::Result<V>> + '_> {
+        Box::new(self.values().cloned().map(Ok))
     }
 
     fn clear(&mut self) -> io::Result<()> {
@@ -101,3 +201,84 @@ impl<Hash: ChunkHash, V: Clone> IterableDatabase<Hash, V> for HashMap<Hash, V> {
         Ok(())
     }
 }
+
+impl<Hash, V> PersistentDatabase<Hash, V> for HashMap<Hash, V>
+where
+    Hash: ChunkHash + Encode + Decode<()>,
+    V: Clone + Encode + Decode<()>,
+{
+    fn save(&self, path: &Path) -> io::Result<()> {
+        encode_to_file(self, path)
+    }
+
+    fn load(path: &Path) -> io::Result<Self> {
+        decode_from_file(path)
+    }
+}
+
+impl<K, V, D> ConcurrentDatabase<K, V> for RwLock<D>
+where
+    D: Database<K, V> + Send + Sync,
+{
+    fn insert(&self, key: K, value: V) -> io::Result<()> {
+        self.write().unwrap().insert(key, value)
+    }
+
+    fn get(&self, key: &K) -> io::Result<V> {
+        self.read().unwrap().get(key)
+    }
+
+    fn insert_multi(&self, pairs: Vec<(K, V)>) -> io::Result<()> {
+        self.write().unwrap().insert_multi(pairs)
+    }
+
+    fn get_multi(&self, keys: &[K]) -> io::Result<Vec<V>> {
+        self.read().unwrap().get_multi(keys)
+    }
+
+    fn contains(&self, key: &K) -> bool {
+        self.read().unwrap().contains(key)
+    }
+
+    fn remove(&self, key: &K) -> io::Result<()> {
+        self.write().unwrap().remove(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::Database;
+
+    /// Database written before removal was added to the trait.
+    struct AppendOnly(Vec<(u64, u64)>);
+
+    impl Database<u64, u64> for AppendOnly {
+        fn insert(&mut self, key: u64, value: u64) -> io::Result<()> {
+            self.0.push((key, value));
+            Ok(())
+        }
+
+        fn get(&self, key: &u64) -> io::Result<u64> {
+            self.0
+                .iter()
+                .find(|(k, _)| k == key)
+                .map(|(_, v)| *v)
+                .ok_or(io::Error::from(io::ErrorKind::NotFound))
+        }
+
+        fn contains(&self, key: &u64) -> bool {
+            self.0.iter().any(|(k, _)| k == key)
+        }
+    }
+
+    #[test]
+    fn remove_is_unsupported_by_default() {
+        let mut database = AppendOnly(vec![]);
+        database.insert(1, 10).unwrap();
+        let error = database.remove(&1).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::Unsupported);
+        assert_eq!(database.get(&1).unwrap(), 10);
+    }
+}
@@ -1,36 +1,71 @@
+use crate::system::aligned_buffer::BufferPool;
+use crate::system::container::{ContainerLayout, Containers};
 use crate::system::data_block::{Alignment, DataBlock, DataInfo};
-use crate::{ChunkHash, Database, IterableDatabase};
+use crate::system::database::{decode_from_file, encode_to_file};
+use crate::system::superblock::{Superblock, SUPERBLOCK_SIZE};
+use crate::{ChunkHash, Database, IterableDatabase, PersistentDatabase};
 use bincode::error::EncodeError;
-use bincode::{encode_to_vec, Decode, Encode};
+use bincode::{decode_from_slice, encode_to_vec, Decode, Encode};
 use libc::O_DIRECT;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fs::{File, OpenOptions};
 use std::io;
 use std::marker::PhantomData;
 use std::os::fd::AsRawFd;
-use std::os::unix::fs::{FileExt, OpenOptionsExt};
+use std::os::unix::fs::{FileExt, FileTypeExt, OpenOptionsExt};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 /// Constant for requesting the total size of the block device via ioctl
 const BLKGETSIZE64: u64 = 0x80081272;
 /// Constant for requesting size of the block in the block device via ioctl
 const BLKSSZGET: u64 = 0x1268;
+/// Number of values read at once by [`IterableDatabase::for_each_mut`] and [`DiskDatabase::compact`].
+const ITERATION_BATCH: usize = 1024;
+/// Maximal size of the I/O buffers kept for reuse by [`DiskDatabase`].
+const POOLED_BUFFERS_SIZE: usize = 64 * 1024 * 1024;
+/// Default maximal gap between values that are read with a single request, see [`DiskDatabase::with_read_gap`].
+const DEFAULT_READ_GAP: u64 = 16 * 1024;
+/// Default maximal size of a single read request, see [`DiskDatabase::with_max_read_size`].
+const DEFAULT_MAX_READ_SIZE: u64 = 1024 * 1024;
 
 enum InitType {
-    /// [`DiskDatabase`] is initialized on a block device.
-    BlockDevice,
-    /// [`DiskDatabase`] is initialized on a regular file. Contains a path to the file.
-    RegularFile(PathBuf),
+    /// [`DiskDatabase`] is initialized on a block device or opened with [`DiskDatabase::open`].
+    /// The index is saved to the device on drop.
+    Persistent,
+    /// [`DiskDatabase`] is initialized on a regular file, which is removed on drop.
+    RegularFile,
+}
+
+/// Measurements made by [`DiskDatabase::compact`].
+#[derive(Debug, Default, PartialEq, Eq, Copy, Clone)]
+pub struct CompactionMeasurements {
+    /// Time spent on compaction.
+    pub running_time: Duration,
+    /// How much data was copied to new locations (in bytes).
+    pub moved_data: u64,
+    /// How much space was freed (in bytes).
+    pub reclaimed_space: u64,
 }
 
 /// Database that stores data on a block device
+///
+/// The device starts with a superblock, followed by the values.
+/// The index of keys and locations of their values is kept in memory and saved to the end of the device by [`Self::flush`],
+/// so that the database can be reopened with [`Self::open`].
 pub struct DiskDatabase<K, V>
 where
-    K: ChunkHash,
+    K: ChunkHash + Encode + Decode<()>,
     V: Clone + Encode + Decode<()>,
 {
     /// Handle for an open block device (or regular file if initialized via `init_on_regular_file`).
     device: File,
+    /// Path to the block device (or regular file).
+    path: PathBuf,
+    /// Whether the device is opened with the O_DIRECT flag.
+    o_direct: bool,
     /// Type of the database initialization.
     init_type: InitType,
     /// A map that maps keys to the location of data on a disk.
@@ -39,15 +74,31 @@ where
     total_size: u64,
     /// Number of occupied blocks.
     used_size: u64,
-    /// Whether the device is opened with the O_DIRECT flag.
+    /// Offset of the index saved by the last [`Self::flush`]. Values are not written beyond it.
+    index_offset: AtomicU64,
+    /// Length of the index saved by the last [`Self::flush`], 0 if it was never saved.
+    index_length: AtomicU64,
+    /// Alignment of reads and writes, which depends on whether the device is opened with the O_DIRECT flag.
     alignment: Alignment,
+    /// Buffers for reads and writes, aligned in memory by the block size, as required by O_DIRECT.
+    buffers: Arc<BufferPool>,
+    /// Maximal gap between values that are read with a single request (in bytes).
+    read_gap: u64,
+    /// Maximal size of a single read request that merges several values (in bytes).
+    max_read_size: u64,
+    /// Containers that group values by their arrival, if the database is set up with [`Self::with_containers`].
+    containers: Option<Containers>,
+    /// Size of the values written by the user (in bytes).
+    written_data: u64,
+    /// Size of the values copied by [`Self::compact`] and [`Self::rewrite`] (in bytes).
+    copied_data: u64,
     /// Values data type. Database doesn't actually own them, so this field is necessary.
     _data_type: PhantomData<V>,
 }
 
 impl<K, V> DiskDatabase<K, V>
 where
-    K: ChunkHash,
+    K: ChunkHash + Encode + Decode<()>,
     V: Clone + Encode + Decode<()>,
 {
     /// Init database on a regular file.
@@ -56,25 +107,43 @@ where
     /// You can specify the ` o_direct ` flag for an open file in O_DIRECT mode. Consider the block size to be 512.
     /// The File is removed on a drop() call.
     ///
-    /// Intended for testing so that it does not require a block device.
+    /// Intended for testing so that it does not require a block device, use [`Self::create`] to keep the file.
     pub fn init_on_regular_file<P>(file_path: P, db_size: u64, o_direct: bool) -> io::Result<Self>
     where
         P: AsRef<Path>,
     {
         let file = Self::create_db_file(&file_path, db_size, o_direct)?;
+        let total_size = file.metadata()?.len();
+
+        Self::format(
+            file,
+            file_path.as_ref(),
+            o_direct,
+            InitType::RegularFile,
+            total_size,
+            Alignment::ByBlockSize(512),
+        )
+    }
 
-        let database_map = HashMap::new();
+    /// Creates a database on a regular file of the given size, with an O_DIRECT flag, if specified.
+    ///
+    /// Unlike [`Self::init_on_regular_file`], the file is kept and flushed on drop, like a block device,
+    /// so that the database can be reopened with [`Self::open`]. An existing file is truncated.
+    pub fn create<P>(file_path: P, db_size: u64, o_direct: bool) -> io::Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let file = Self::create_db_file(&file_path, db_size, o_direct)?;
         let total_size = file.metadata()?.len();
 
-        Ok(Self {
-            device: file,
-            init_type: InitType::RegularFile(file_path.as_ref().to_path_buf()),
-            database_map,
+        Self::format(
+            file,
+            file_path.as_ref(),
+            o_direct,
+            InitType::Persistent,
             total_size,
-            used_size: 0,
-            alignment: Alignment::ByBlockSize(512),
-            _data_type: PhantomData,
-        })
+            Alignment::ByBlockSize(512),
+        )
     }
 
     /// Creates a regular file in the specified path with the specified size and o_direct flag, if specified.
@@ -96,16 +165,101 @@ where
     /// Init database on a block device, with an O_DIRECT flag, if specified.
     ///
     /// Takes information about the block device via ioctl.
+    /// Data previously stored on the device is discarded, use [`Self::open`] to keep it.
     pub fn init<P>(blkdev_path: P, o_direct: bool) -> Result<Self, io::Error>
     where
         P: AsRef<Path>,
     {
+        let device = Self::open_device(&blkdev_path, o_direct)?;
+        let (total_size, alignment) = Self::block_device_geometry(&device, o_direct)?;
+
+        Self::format(
+            device,
+            blkdev_path.as_ref(),
+            o_direct,
+            InitType::Persistent,
+            total_size,
+            alignment,
+        )
+    }
+
+    /// Opens a database previously written to a block device or a regular file, with an O_DIRECT flag, if specified.
+    ///
+    /// Restores the index and the containers saved by the last [`Self::flush`].
+    /// Values written after it are lost. Regular files opened this way are not removed on drop.
+    ///
+    /// # Errors
+    /// * `io::ErrorKind::InvalidData` - if the device doesn't contain a database, or its index cannot be decoded.
+    pub fn open<P>(path: P, o_direct: bool) -> io::Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let device = Self::open_device(&path, o_direct)?;
+        let (total_size, alignment) = if device.metadata()?.file_type().is_block_device() {
+            Self::block_device_geometry(&device, o_direct)?
+        } else {
+            (device.metadata()?.len(), Alignment::ByBlockSize(512))
+        };
+
+        let buffers = buffer_pool(&alignment);
+        let superblock = Superblock::read(&device, &buffers)?;
+        let database_map = if superblock.index_length == 0 {
+            HashMap::new()
+        } else {
+            let mut index =
+                buffers.take(aligned_length(superblock.index_length, &alignment) as usize);
+            device.read_exact_at(&mut index, superblock.index_offset)?;
+            decode_from_slice(&index, bincode::config::standard())
+                .map(|(database_map, _)| database_map)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?
+        };
+        let containers = if superblock.container_size == 0 {
+            None
+        } else {
+            let layout =
+                ContainerLayout::new(SUPERBLOCK_SIZE, superblock.container_size, &alignment)?;
+            let cached_containers = superblock.cached_containers as usize;
+            Some(Containers::new(
+                layout,
+                superblock.used_size,
+                cached_containers,
+                Arc::clone(&buffers),
+            ))
+        };
+
+        Ok(Self {
+            device,
+            path: path.as_ref().to_path_buf(),
+            o_direct,
+            init_type: InitType::Persistent,
+            database_map,
+            total_size,
+            used_size: superblock.used_size,
+            index_offset: AtomicU64::new(superblock.index_offset),
+            index_length: AtomicU64::new(superblock.index_length),
+            alignment,
+            buffers,
+            read_gap: DEFAULT_READ_GAP,
+            max_read_size: DEFAULT_MAX_READ_SIZE,
+            containers,
+            written_data: 0,
+            copied_data: 0,
+            _data_type: PhantomData,
+        })
+    }
+
+    /// Opens the device for reading and writing, with an O_DIRECT flag, if specified.
+    fn open_device<P: AsRef<Path>>(path: P, o_direct: bool) -> io::Result<File> {
         let mut options = OpenOptions::new();
         options.read(true).write(true);
         if o_direct {
             options.custom_flags(O_DIRECT);
         }
-        let device = options.open(blkdev_path)?;
+        options.open(path)
+    }
+
+    /// Takes the size of the block device and the alignment for its reads and writes via ioctl.
+    fn block_device_geometry(device: &File, o_direct: bool) -> io::Result<(u64, Alignment)> {
         let fd = device.as_raw_fd();
 
         let mut total_size: u64 = 0;
@@ -128,19 +282,409 @@ where
             Alignment::None
         };
 
-        let database_map = HashMap::new();
+        Ok((total_size, alignment))
+    }
 
-        Ok(Self {
+    /// Creates an empty database on the device, writing a superblock without an index.
+    fn format(
+        device: File,
+        path: &Path,
+        o_direct: bool,
+        init_type: InitType,
+        total_size: u64,
+        alignment: Alignment,
+    ) -> io::Result<Self> {
+        if total_size <= SUPERBLOCK_SIZE {
+            let msg = "device is too small to hold a database";
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+        }
+
+        let database = Self {
             device,
-            init_type: InitType::BlockDevice,
-            database_map,
+            path: path.to_path_buf(),
+            o_direct,
+            init_type,
+            database_map: HashMap::new(),
             total_size,
-            used_size: 0,
+            used_size: SUPERBLOCK_SIZE,
+            index_offset: AtomicU64::new(total_size),
+            index_length: AtomicU64::new(0),
+            buffers: buffer_pool(&alignment),
             alignment,
-            _data_type: PhantomData {},
+            read_gap: DEFAULT_READ_GAP,
+            max_read_size: DEFAULT_MAX_READ_SIZE,
+            containers: None,
+            written_data: 0,
+            copied_data: 0,
+            _data_type: PhantomData,
+        };
+        database
+            .superblock(total_size, 0)
+            .write(&database.device, &database.buffers)?;
+        Ok(database)
+    }
+
+    /// Switches the database to a container-based layout.
+    ///
+    /// Values are grouped into fixed-size containers in the order they are written.
+    /// Each container has a metadata section with locations of its values, followed by the values themselves.
+    /// The container being filled is kept in memory and written to the device when it's full, or on [`Self::flush`].
+    ///
+    /// Reads fetch whole containers, the last `cached_containers` of them are kept in memory
+    /// and serve the following reads of values from the same containers.
+    ///
+    /// # Errors
+    /// * `io::ErrorKind::InvalidInput` - if the database is not empty, or the container size is not a multiple
+    ///   of the block size, or the device can't hold a single container.
+    pub fn with_containers(
+        mut self,
+        container_size: u64,
+        cached_containers: usize,
+    ) -> io::Result<Self> {
+        if !self.database_map.is_empty() || self.used_size != SUPERBLOCK_SIZE {
+            let msg = "containers can only be set up on an empty database";
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+        }
+        let layout = ContainerLayout::new(SUPERBLOCK_SIZE, container_size, &self.alignment)?;
+        if SUPERBLOCK_SIZE + layout.size() > self.index_offset.load(Ordering::Relaxed) {
+            let msg = "container size exceeds the size of the device";
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+        }
+
+        self.containers = Some(Containers::new(
+            layout,
+            SUPERBLOCK_SIZE,
+            cached_containers,
+            Arc::clone(&self.buffers),
+        ));
+        Ok(self)
+    }
+
+    /// Sets the maximal gap between values that are read from the device with a single request (in bytes).
+    ///
+    /// Values requested together are sorted by their offsets, and the ones separated by at most `read_gap` bytes
+    /// are read at once, along with the data between them. Larger gaps cut the number of reads, but read more unused data.
+    /// Defaults to 16 KiB. Doesn't affect databases that use containers.
+    pub fn with_read_gap(mut self, read_gap: u64) -> Self {
+        self.read_gap = read_gap;
+        self
+    }
+
+    /// Sets the maximal size of a single request that reads several values at once (in bytes).
+    ///
+    /// Values merged by [`Self::with_read_gap`] are split into several requests, so that one request doesn't hold up
+    /// the device and doesn't need a large buffer. A value larger than `max_read_size` is still read with one request.
+    /// Defaults to 1 MiB. Doesn't affect databases that use containers.
+    pub fn with_max_read_size(mut self, max_read_size: u64) -> Self {
+        self.max_read_size = max_read_size;
+        self
+    }
+
+    /// Returns the number of containers read from the device, or 0 if the database doesn't use containers.
+    pub fn container_reads(&self) -> usize {
+        self.containers.as_ref().map_or(0, Containers::reads)
+    }
+
+    /// Returns the number of distinct containers that hold the values of the given keys.
+    ///
+    /// Restoring a backup reads at least this many containers, so the number shows how fragmented the backup is.
+    ///
+    /// # Errors
+    /// * `io::ErrorKind::Unsupported` - if the database doesn't use containers.
+    /// * `io::ErrorKind::NotFound` - if some key is not in the database.
+    pub fn containers_referenced(&self, keys: &[K]) -> io::Result<usize> {
+        let containers = self.containers()?;
+        let indices = keys
+            .iter()
+            .map(|k| {
+                self.data_info(k)
+                    .map(|data_info| containers.layout().index(data_info.offset()))
+            })
+            .collect::<io::Result<HashSet<_>>>()?;
+        Ok(indices.len())
+    }
+
+    /// Writes the values of the given keys again, into the currently filled container.
+    ///
+    /// Used to reduce fragmentation of a backup by rewriting its chunks that are scattered over old containers.
+    /// The space taken by the old copies is not reclaimed.
+    ///
+    /// # Errors
+    /// * `io::ErrorKind::Unsupported` - if the database doesn't use containers.
+    /// * `io::ErrorKind::NotFound` - if some key is not in the database.
+    pub fn rewrite(&mut self, keys: &[K]) -> io::Result<()> {
+        let containers = self.containers()?;
+        let data_infos = keys
+            .iter()
+            .map(|k| self.data_info(k))
+            .collect::<io::Result<Vec<_>>>()?;
+        let values = containers.read(&self.device, &data_infos)?;
+
+        let limit = self.index_offset.load(Ordering::Relaxed);
+        let containers = self.containers.as_mut().unwrap();
+        let data_infos = containers.append(&self.device, limit, &values)?;
+        self.used_size = containers.end();
+        self.copied_data += values.iter().map(|value| value.len() as u64).sum::<u64>();
+        self.database_map
+            .extend(keys.iter().cloned().zip(data_infos));
+        Ok(())
+    }
+
+    /// Returns the space taken by removed and overwritten values, as well as by padding and unfilled parts of containers (in bytes).
+    ///
+    /// Removed and overwritten values are reclaimed by [`Self::compact`].
+    pub fn garbage_size(&self) -> u64 {
+        let live_size = self
+            .database_map
+            .values()
+            .map(DataInfo::data_length)
+            .sum::<u64>();
+        self.used_size - SUPERBLOCK_SIZE - live_size
+    }
+
+    /// Returns the ratio of all data written to the device to the data written by the user.
+    ///
+    /// Data copied by [`Self::compact`] and [`Self::rewrite`] is counted as written to the device.
+    /// Equals 1 if nothing was copied.
+    pub fn write_amplification(&self) -> f64 {
+        if self.written_data == 0 {
+            return 1.0;
+        }
+        (self.written_data + self.copied_data) as f64 / self.written_data as f64
+    }
+
+    /// Reclaims the space taken by removed and overwritten values.
+    ///
+    /// Live values are moved towards the start of the device in the order of their offsets, closing the gaps between them.
+    /// Values that are already in place are not copied. If the database uses containers,
+    /// live values of each container are packed into the containers at the start of the device instead.
+    /// The database is [flushed][Self::flush] afterward, so that the saved index points to the new locations.
+    ///
+    /// Compaction is not crash-safe: if it is interrupted, the database can't be reopened from the previously saved index.
+    pub fn compact(&mut self) -> io::Result<CompactionMeasurements> {
+        let now = Instant::now();
+        let used_size = self.used_size;
+
+        let mut live = self
+            .database_map
+            .iter()
+            .map(|(k, data_info)| (k.clone(), data_info.clone()))
+            .collect::<Vec<_>>();
+        live.sort_unstable_by_key(|(_, data_info)| data_info.offset());
+
+        let moved_data = match self.containers.is_some() {
+            true => self.compact_containers(&live)?,
+            false => self.compact_values(&live)?,
+        };
+        self.copied_data += moved_data;
+        self.flush()?;
+
+        Ok(CompactionMeasurements {
+            running_time: now.elapsed(),
+            moved_data,
+            reclaimed_space: used_size - self.used_size,
         })
     }
 
+    /// Moves the values sorted by their offsets to the start of the device. Returns the size of the moved values.
+    ///
+    /// Values never move past their old location, so every value is read before it can be overwritten.
+    fn compact_values(&mut self, live: &[(K, DataInfo)]) -> io::Result<u64> {
+        let mut destination = SUPERBLOCK_SIZE;
+        let mut moved_data = 0;
+
+        for batch in live.chunks(ITERATION_BATCH) {
+            let in_place = batch
+                .iter()
+                .take_while(|(_, data_info)| {
+                    let in_place = data_info.offset() == destination;
+                    if in_place {
+                        destination += data_info.data_length();
+                    }
+                    in_place
+                })
+                .count();
+            let batch = &batch[in_place..];
+            if batch.is_empty() {
+                continue;
+            }
+
+            let data_infos = batch.iter().map(|(_, data_info)| data_info).collect();
+            let mut read = DataBlock::split_to_datablocks(
+                &self.buffers,
+                self.alignment.clone(),
+                data_infos,
+                self.read_gap,
+                self.max_read_size,
+            );
+            self.fill_datablocks(read.datablocks_mut())?;
+            let values = read
+                .values()
+                .into_iter()
+                .map(<[u8]>::to_vec)
+                .collect::<Vec<_>>();
+            let values_size = values.iter().map(|value| value.len() as u64).sum::<u64>();
+
+            let mut datablock =
+                DataBlock::from_values(&self.buffers, self.alignment.clone(), values, destination)?;
+            self.fill_padding(&mut datablock, destination, destination + values_size)?;
+            self.device
+                .write_all_at(datablock.data(), datablock.offset())?;
+
+            let keys = batch.iter().map(|(k, _)| k.clone());
+            self.database_map.extend(keys.zip(datablock.data_infos()));
+            destination += values_size;
+            moved_data += values_size;
+        }
+
+        self.used_size = aligned_length(destination, &self.alignment);
+        Ok(moved_data)
+    }
+
+    /// Fills the padding of the datablock around its values, which span from `start` to `end`,
+    /// with the data currently stored on the device, so that writing the datablock doesn't change it.
+    fn fill_padding(&self, datablock: &mut DataBlock, start: u64, end: u64) -> io::Result<()> {
+        let Alignment::ByBlockSize(block_size) = self.alignment else {
+            return Ok(());
+        };
+        let offset = datablock.offset();
+        let length = datablock.data().len() as u64;
+        let mut block = self.buffers.take(block_size as usize);
+
+        if start > offset {
+            self.device.read_exact_at(&mut block, offset)?;
+            let padding = (start - offset) as usize;
+            datablock.data_mut()[..padding].copy_from_slice(&block[..padding]);
+        }
+        if offset + length > end {
+            let last_block = offset + length - block_size;
+            self.device.read_exact_at(&mut block, last_block)?;
+            let padding = (end - last_block) as usize;
+            let data = datablock.data_mut();
+            let data_end = (end - offset) as usize;
+            data[data_end..].copy_from_slice(&block[padding..]);
+        }
+        Ok(())
+    }
+
+    /// Packs the live values of each container, in the order of containers, into the containers at the start of the device.
+    /// Returns the size of the moved values.
+    ///
+    /// Live values of a container always fit into one container,
+    /// so they never move past their container, and every container is read before it can be overwritten.
+    fn compact_containers(&mut self, live: &[(K, DataInfo)]) -> io::Result<u64> {
+        let containers = self.containers.as_ref().unwrap();
+        let layout = *containers.layout();
+        let mut compacted = Containers::new(
+            layout,
+            SUPERBLOCK_SIZE,
+            containers.cached(),
+            Arc::clone(&self.buffers),
+        );
+        let limit = self.index_offset.load(Ordering::Relaxed);
+        let mut moved_data = 0;
+
+        let same_container = |(_, a): &(K, DataInfo), (_, b): &(K, DataInfo)| {
+            layout.index(a.offset()) == layout.index(b.offset())
+        };
+        for group in live.chunk_by(same_container) {
+            let data_infos = group
+                .iter()
+                .map(|(_, data_info)| data_info)
+                .collect::<Vec<_>>();
+            let values = containers.read(&self.device, &data_infos)?;
+            let new_data_infos = compacted.append(&self.device, limit, &values)?;
+
+            for ((k, data_info), new_data_info) in group.iter().zip(new_data_infos) {
+                if *data_info != new_data_info {
+                    moved_data += new_data_info.data_length();
+                }
+                self.database_map.insert(k.clone(), new_data_info);
+            }
+        }
+
+        self.used_size = compacted.end();
+        self.containers = Some(compacted);
+        Ok(moved_data)
+    }
+
+    /// Saves the database state to the device, so that it can be reopened with [`Self::open`].
+    ///
+    /// Writes the container being filled, if the database uses containers, then the index,
+    /// and finally the superblock that points to the index. The index is written to the end of the device,
+    /// or right below the previously saved one if they would overlap, so the saved state stays readable
+    /// until the superblock is replaced. The data is synced to the device before the superblock is written.
+    ///
+    /// Databases on block devices, or opened with [`Self::open`], are flushed on drop.
+    ///
+    /// # Errors
+    /// * `io::ErrorKind::OutOfMemory` - if there is no space for the index after the values.
+    pub fn flush(&self) -> io::Result<()> {
+        if let Some(containers) = &self.containers {
+            containers.write_open(&self.device)?;
+        }
+
+        let encoded = encode_to_vec(&self.database_map, bincode::config::standard())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let index_length = encoded.len() as u64;
+        let mut index = self
+            .buffers
+            .take(aligned_length(index_length, &self.alignment) as usize);
+        index[..encoded.len()].copy_from_slice(&encoded);
+
+        let end = self.total_size - self.total_size % block_size(&self.alignment);
+        let live_offset = self.index_offset.load(Ordering::Relaxed);
+        let live_length =
+            aligned_length(self.index_length.load(Ordering::Relaxed), &self.alignment);
+        let below_live = live_length > 0 && end < live_offset + live_length + index.len() as u64;
+        let Some(index_offset) = (if below_live { live_offset } else { end })
+            .checked_sub(index.len() as u64)
+            .filter(|&offset| offset >= self.used_size)
+        else {
+            return Err(io::Error::from(io::ErrorKind::OutOfMemory));
+        };
+        self.device.write_all_at(&index, index_offset)?;
+        self.device.sync_data()?;
+
+        self.superblock(index_offset, index_length)
+            .write(&self.device, &self.buffers)?;
+        self.device.sync_data()?;
+        self.index_offset.store(index_offset, Ordering::Relaxed);
+        self.index_length.store(index_length, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Returns the superblock that describes the current state of the database with the given index.
+    fn superblock(&self, index_offset: u64, index_length: u64) -> Superblock {
+        let (container_size, cached_containers) = match &self.containers {
+            Some(containers) => (containers.layout().size(), containers.cached() as u64),
+            None => (0, 0),
+        };
+
+        Superblock {
+            used_size: self.used_size,
+            index_offset,
+            index_length,
+            container_size,
+            cached_containers,
+        }
+    }
+
+    fn containers(&self) -> io::Result<&Containers> {
+        self.containers.as_ref().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::Unsupported,
+                "database doesn't use containers",
+            )
+        })
+    }
+
+    fn data_info(&self, key: &K) -> io::Result<&DataInfo> {
+        self.database_map
+            .get(key)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Key not found"))
+    }
+
     /// Read into datablocks from the block device based on their offsets.
     fn fill_datablocks(&self, datablocks: Vec<&mut DataBlock>) -> io::Result<()> {
         datablocks
@@ -153,36 +697,71 @@ where
         Ok(())
     }
 
-    /// Read and decode multiple data from the disk.
-    fn read_multi<T: Decode<()>>(&self, data_infos: Vec<&DataInfo>) -> io::Result<Vec<T>> {
+    /// Read and decode multiple data from the disk, in the order of the given data infos.
+    fn read_multi<T: Clone + Decode<()>>(&self, data_infos: Vec<&DataInfo>) -> io::Result<Vec<T>> {
         if data_infos.is_empty() {
             return Ok(Vec::new());
         }
 
-        let mut datablocks = DataBlock::split_to_datablocks(self.alignment.clone(), data_infos);
-        self.fill_datablocks(datablocks.iter_mut().collect())?;
-        DataBlock::decode_datablocks(datablocks.iter().collect())
+        if let Some(containers) = &self.containers {
+            return containers
+                .read(&self.device, &data_infos)?
+                .iter()
+                .map(|encoded| {
+                    decode_from_slice(encoded, bincode::config::standard())
+                        .map(|(value, _)| value)
+                        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+                })
+                .collect();
+        }
+
+        let mut read = DataBlock::split_to_datablocks(
+            &self.buffers,
+            self.alignment.clone(),
+            data_infos,
+            self.read_gap,
+            self.max_read_size,
+        );
+        self.fill_datablocks(read.datablocks_mut())?;
+        read.decode()
     }
 
     /// Serializes and writes multiple data to the disk. Returns `Vec<DataInfo>` with information about the allocated data.
     fn write_multi<T: Encode>(&mut self, values: &[&T]) -> io::Result<Vec<DataInfo>> {
-        if values.is_empty() {
-            return Ok(Vec::new());
-        }
-
         let encoded_values = values
             .iter()
             .map(|value| encode_to_vec(value, bincode::config::standard()))
             .collect::<Result<Vec<_>, EncodeError>>()
             .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        self.write_encoded(encoded_values)
+    }
 
-        let encoded_size: usize = encoded_values.iter().map(|vec| vec.len()).sum();
-        if self.used_size + encoded_size as u64 >= self.total_size {
-            return Err(io::Error::from(io::ErrorKind::OutOfMemory));
+    /// Writes multiple serialized data to the disk. Returns `Vec<DataInfo>` with information about the allocated data.
+    fn write_encoded(&mut self, encoded_values: Vec<Vec<u8>>) -> io::Result<Vec<DataInfo>> {
+        if encoded_values.is_empty() {
+            return Ok(Vec::new());
         }
+        self.written_data += encoded_values
+            .iter()
+            .map(|value| value.len() as u64)
+            .sum::<u64>();
 
-        let datablock =
-            DataBlock::from_values(self.alignment.clone(), encoded_values, self.used_size)?;
+        let limit = self.index_offset.load(Ordering::Relaxed);
+        if let Some(containers) = &mut self.containers {
+            let data_infos = containers.append(&self.device, limit, &encoded_values)?;
+            self.used_size = containers.end();
+            return Ok(data_infos);
+        }
+
+        let datablock = DataBlock::from_values(
+            &self.buffers,
+            self.alignment.clone(),
+            encoded_values,
+            self.used_size,
+        )?;
+        if self.used_size + datablock.data().len() as u64 > limit {
+            return Err(io::Error::from(io::ErrorKind::OutOfMemory));
+        }
         self.device.write_all_at(datablock.data(), self.used_size)?;
         self.used_size += datablock.data().len() as u64;
 
@@ -192,19 +771,40 @@ where
 
 impl<K, V> Drop for DiskDatabase<K, V>
 where
-    K: ChunkHash,
+    K: ChunkHash + Encode + Decode<()>,
     V: Clone + Encode + Decode<()>,
 {
     fn drop(&mut self) {
-        if let InitType::RegularFile(file_path) = &self.init_type {
-            std::fs::remove_file(file_path).unwrap()
+        match self.init_type {
+            InitType::Persistent => {
+                let _ = self.flush();
+            }
+            InitType::RegularFile => std::fs::remove_file(&self.path).unwrap(),
         }
     }
 }
 
+/// Returns the length of data padded to a multiple of the block size.
+fn aligned_length(length: u64, alignment: &Alignment) -> u64 {
+    length.next_multiple_of(block_size(alignment))
+}
+
+fn block_size(alignment: &Alignment) -> u64 {
+    match alignment {
+        Alignment::ByBlockSize(block_size) => *block_size,
+        Alignment::None => 1,
+    }
+}
+
+/// Creates a pool of buffers aligned in memory by the block size, rounded up to a power of two.
+fn buffer_pool(alignment: &Alignment) -> Arc<BufferPool> {
+    let memory_alignment = block_size(alignment).next_power_of_two() as usize;
+    BufferPool::new(memory_alignment, POOLED_BUFFERS_SIZE)
+}
+
 impl<K, V> Database<K, V> for DiskDatabase<K, V>
 where
-    K: ChunkHash,
+    K: ChunkHash + Encode + Decode<()>,
     V: Clone + Encode + Decode<()>,
 {
     fn insert(&mut self, key: K, value: V) -> io::Result<()> {
@@ -212,7 +812,8 @@ where
     }
 
     fn get(&self, key: &K) -> io::Result<V> {
-        self.get_multi(&[key.clone()]).map(|mut vec| vec.remove(0))
+        self.get_multi(std::slice::from_ref(key))
+            .map(|mut vec| vec.remove(0))
     }
 
     fn insert_multi(&mut self, pairs: Vec<(K, V)>) -> io::Result<()> {
@@ -233,11 +834,7 @@ where
     fn get_multi(&self, keys: &[K]) -> io::Result<Vec<V>> {
         let data_infos = keys
             .iter()
-            .map(|k| {
-                self.database_map
-                    .get(k)
-                    .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Key not found"))
-            })
+            .map(|k| self.data_info(k))
             .collect::<io::Result<Vec<_>>>()?;
         self.read_multi(data_infos)
     }
@@ -245,20 +842,64 @@ where
     fn contains(&self, key: &K) -> bool {
         self.database_map.contains_key(key)
     }
+
+    fn remove(&mut self, key: &K) -> io::Result<()> {
+        self.database_map
+            .remove(key)
+            .map(|_| ())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Key not found"))
+    }
 }
 
 impl<K, V> IterableDatabase<K, V> for DiskDatabase<K, V>
 where
-    K: ChunkHash,
+    K: ChunkHash + Encode + Decode<()>,
     V: Clone + Encode + Decode<()>,
 {
-    /// Returns a simple immutable iterator over values.
-    fn iterator(&self) -> Box<dyn Iterator<Item = (&K, &V)> + '_> {
-        unimplemented!()
+    /// Returns an immutable iterator over keys and values, which are read from the disk one by one.
+    ///
+    /// Yields an error for every value that cannot be read or decoded.
+    fn iterator(&self) -> Box<dyn Iterator<Item = io::Result<(&K, V)>> + '_> {
+        Box::new(
+            self.database_map
+                .keys()
+                .map(|k| self.get(k).map(|value| (k, value))),
+        )
     }
 
-    fn iterator_mut(&mut self) -> Box<dyn Iterator<Item = (&K, &mut V)> + '_> {
-        unimplemented!()
+    /// Reads values in batches and writes back the ones changed by `f`.
+    ///
+    /// Changed values are written to new locations, the space taken by their old versions is not reclaimed.
+    fn for_each_mut(&mut self, f: &mut dyn FnMut(&K, &mut V) -> io::Result<()>) -> io::Result<()> {
+        let keys = self.database_map.keys().cloned().collect::<Vec<_>>();
+
+        for keys in keys.chunks(ITERATION_BATCH) {
+            let mut changed_keys = vec![];
+            let mut changed_values = vec![];
+            let mut result = Ok(());
+            for (key, mut value) in keys.iter().zip(self.get_multi(keys)?) {
+                let old = encode_to_vec(&value, bincode::config::standard())
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                result = f(key, &mut value);
+                let new = encode_to_vec(&value, bincode::config::standard())
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+                if new != old {
+                    changed_keys.push(key.clone());
+                    changed_values.push(new);
+                }
+                if result.is_err() {
+                    break;
+                }
+            }
+
+            // values changed before an error are written too
+            let data_infos = self.write_encoded(changed_values)?;
+            self.database_map
+                .extend(changed_keys.into_iter().zip(data_infos));
+            result?;
+        }
+        Ok(())
     }
 
     fn keys<'a>(&'a self) -> Box<dyn Iterator<Item = &'a K> + 'a>
@@ -268,24 +909,39 @@ where
         Box::new(self.database_map.keys())
     }
 
-    fn values(&self) -> Box<dyn Iterator<Item = V> + '_> {
-        Box::new(self.database_map.keys().map(|k| self.get(k).unwrap()))
-    }
-
-    fn values_mut<'a>(&'a mut self) -> Box<dyn Iterator<Item = &'a mut V> + 'a>
-    where
-        K: 'a,
-    {
-        unimplemented!()
+    fn values(&self) -> Box<dyn Iterator<Item = io::Result<V>> + '_> {
+        Box::new(self.database_map.keys().map(|k| self.get(k)))
     }
 
     fn clear(&mut self) -> io::Result<()> {
         self.database_map.clear();
-        self.used_size = 0;
+        self.used_size = SUPERBLOCK_SIZE;
+        if let Some(containers) = &mut self.containers {
+            containers.reset(SUPERBLOCK_SIZE);
+        }
         Ok(())
     }
 }
 
+impl<K, V> PersistentDatabase<K, V> for DiskDatabase<K, V>
+where
+    K: ChunkHash + Encode + Decode<()>,
+    V: Clone + Encode + Decode<()>,
+{
+    /// Flushes the database to its device and saves the path to the device to the given file.
+    fn save(&self, path: &Path) -> io::Result<()> {
+        self.flush()?;
+        let device_path = std::fs::canonicalize(&self.path)?;
+        encode_to_file(&(device_path, self.o_direct), path)
+    }
+
+    /// Opens the database on the device whose path was saved to the given file by [`save`][PersistentDatabase::save].
+    fn load(path: &Path) -> io::Result<Self> {
+        let (device_path, o_direct): (PathBuf, bool) = decode_from_file(path)?;
+        Self::open(device_path, o_direct)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -317,4 +973,210 @@ mod tests {
         let empty = db.get(&k1);
         assert!(empty.is_err());
     }
+
+    #[test]
+    fn diskdb_reads_are_coalesced_in_request_order() {
+        for (file_path, o_direct, read_gap, max_read_size) in [
+            ("pseudo_dev_coalesced_direct", true, 4 * KB as u64, u64::MAX),
+            ("pseudo_dev_coalesced", false, 0, u64::MAX),
+            (
+                "pseudo_dev_coalesced_bounded",
+                true,
+                64 * KB as u64,
+                2 * KB as u64,
+            ),
+        ] {
+            let mut db = DiskDatabase::init_on_regular_file(file_path, 1024 * 1024, o_direct)
+                .unwrap()
+                .with_read_gap(read_gap)
+                .with_max_read_size(max_read_size);
+            let pairs = (0..50u64).map(|i| (i, vec![i as u8; 700])).collect();
+            db.insert_multi(pairs).unwrap();
+
+            // every third value, backwards, with repeated keys
+            let keys = (0..50)
+                .rev()
+                .step_by(3)
+                .chain([2, 47, 2])
+                .collect::<Vec<u64>>();
+            let values = db.get_multi(&keys).unwrap();
+            let expected = keys.iter().map(|&k| vec![k as u8; 700]).collect::<Vec<_>>();
+            assert_eq!(values, expected);
+        }
+    }
+
+    #[test]
+    fn diskdb_is_reopened_from_flushed_index() {
+        let file_path = "pseudo_dev_reopen";
+        let file_size = 1024 * 1024 * 4;
+
+        let mut db = DiskDatabase::init_on_regular_file(file_path, file_size, false)
+            .unwrap()
+            .with_containers(256 * KB as u64, 2)
+            .unwrap();
+        let pairs = (0..100u64).map(|i| (i, vec![i as u8; 3 * KB])).collect();
+        db.insert_multi(pairs).unwrap();
+        db.flush().unwrap();
+        // not flushed, so not seen by the reopened database
+        db.insert(100, vec![100; 3 * KB]).unwrap();
+
+        let mut reopened = DiskDatabase::<u64, Vec<u8>>::open(file_path, false).unwrap();
+        assert!(!reopened.contains(&100));
+        let keys = (0..100).collect::<Vec<_>>();
+        let values = reopened.get_multi(&keys).unwrap();
+        assert!(values
+            .iter()
+            .enumerate()
+            .all(|(i, value)| value == &vec![i as u8; 3 * KB]));
+        assert_eq!(reopened.containers_referenced(&keys).unwrap(), 2);
+
+        // values written after reopening go to a new container
+        reopened.insert(200, vec![200; 3 * KB]).unwrap();
+        assert_eq!(reopened.containers_referenced(&[99, 200]).unwrap(), 2);
+        assert_eq!(reopened.get(&200).unwrap(), vec![200; 3 * KB]);
+
+        let empty = tempfile::NamedTempFile::new().unwrap();
+        empty.as_file().set_len(file_size).unwrap();
+        assert_eq!(
+            DiskDatabase::<u64, Vec<u8>>::open(empty.path(), false)
+                .err()
+                .unwrap()
+                .kind(),
+            io::ErrorKind::InvalidData
+        );
+    }
+
+    #[test]
+    fn diskdb_flush_keeps_the_saved_index_until_the_superblock_is_written() {
+        let file_path = "pseudo_dev_alternating_index";
+        let file_size = 1024 * 1024;
+
+        let mut db = DiskDatabase::create(file_path, file_size, true).unwrap();
+        let mut saved = vec![];
+        for round in 0..3u64 {
+            let pairs = (round * 10..round * 10 + 10)
+                .map(|i| (i, vec![i as u8; 1000]))
+                .collect();
+            db.insert_multi(pairs).unwrap();
+            db.flush().unwrap();
+
+            let superblock = Superblock::read(&db.device, &db.buffers).unwrap();
+            let length = aligned_length(superblock.index_length, &db.alignment) as usize;
+            let mut index = db.buffers.take(length);
+            db.device
+                .read_exact_at(&mut index, superblock.index_offset)
+                .unwrap();
+            saved.push((superblock.index_offset, index.to_vec()));
+        }
+
+        // each index is written next to the previous one, and the first slot is reused by the third index
+        let (first, second, third) = (&saved[0], &saved[1], &saved[2]);
+        assert_eq!(second.0 + second.1.len() as u64, first.0);
+        assert_eq!(third.0 + third.1.len() as u64, file_size);
+        let mut index = db.buffers.take(second.1.len());
+        db.device.read_exact_at(&mut index, second.0).unwrap();
+        assert_eq!(index.to_vec(), second.1);
+
+        drop(db);
+        let reopened = DiskDatabase::<u64, Vec<u8>>::open(file_path, true).unwrap();
+        assert_eq!(reopened.get(&25).unwrap(), vec![25; 1000]);
+        drop(reopened);
+        std::fs::remove_file(file_path).unwrap();
+    }
+
+    #[test]
+    fn diskdb_iteration_yields_errors_for_unreadable_values() {
+        let file_path = "pseudo_dev_unreadable";
+
+        let mut db = DiskDatabase::init_on_regular_file(file_path, 1024 * 1024, false).unwrap();
+        db.insert(0u64, "valid".to_string()).unwrap();
+        db.insert(1u64, "spoiled".to_string()).unwrap();
+
+        // the string bytes after the length prefix become invalid UTF-8
+        let spoiled = &db.database_map[&1];
+        let file = File::options().write(true).open(file_path).unwrap();
+        file.write_all_at(&[0xff; 7], spoiled.offset() + 1).unwrap();
+
+        let results = db.iterator().collect::<Vec<_>>();
+        assert_eq!(results.iter().filter(|result| result.is_err()).count(), 1);
+        assert!(results
+            .iter()
+            .flatten()
+            .all(|(&key, value)| key == 0 && value == "valid"));
+        assert_eq!(db.values().filter(io::Result::is_err).count(), 1);
+    }
+
+    #[test]
+    fn diskdb_compaction_reclaims_removed_values() {
+        for (file_path, container_size) in [
+            ("pseudo_dev_compaction", None),
+            ("pseudo_dev_containers_compaction", Some(64 * KB as u64)),
+        ] {
+            let mut db =
+                DiskDatabase::init_on_regular_file(file_path, 1024 * 1024 * 4, false).unwrap();
+            if let Some(container_size) = container_size {
+                db = db.with_containers(container_size, 1).unwrap();
+            }
+            let value = |i: u64| vec![i as u8; 1000 + i as usize * 37];
+            for batch in (0..120u64).collect::<Vec<_>>().chunks(7) {
+                let pairs = batch.iter().map(|&i| (i, value(i))).collect();
+                db.insert_multi(pairs).unwrap();
+            }
+            let used_size = db.used_size;
+
+            for i in (0..120).filter(|i| i % 3 != 0) {
+                db.remove(&i).unwrap();
+            }
+            let garbage_size = db.garbage_size();
+            let measurements = db.compact().unwrap();
+            assert!(measurements.moved_data > 0);
+            assert_eq!(measurements.reclaimed_space, used_size - db.used_size);
+            assert!(measurements.reclaimed_space > garbage_size / 2);
+            assert!(db.write_amplification() > 1.0);
+
+            let keys = (0..120).filter(|i| i % 3 == 0).collect::<Vec<_>>();
+            let values = db.get_multi(&keys).unwrap();
+            assert!(keys.iter().zip(values).all(|(&i, v)| v == value(i)));
+
+            // compacted database is reopened from the saved index
+            let reopened = DiskDatabase::<u64, Vec<u8>>::open(file_path, false).unwrap();
+            assert_eq!(reopened.get(&117).unwrap(), value(117));
+        }
+    }
+
+    #[test]
+    fn diskdb_containers_group_and_rewrite_values() {
+        let container_size = 64 * KB as u64;
+
+        for (file_path, o_direct) in [
+            ("pseudo_dev_containers_direct", true),
+            ("pseudo_dev_containers", false),
+        ] {
+            let db = DiskDatabase::init_on_regular_file(file_path, 1024 * 1024, o_direct).unwrap();
+            let mut db = db.with_containers(container_size, 1).unwrap();
+            let pairs = (0..32u64).map(|i| (i, vec![i as u8; 4 * KB])).collect();
+            db.insert_multi(pairs).unwrap();
+            let keys = (0..32).collect::<Vec<_>>();
+
+            // 15 values fit into a container after its metadata section
+            assert_eq!(db.containers_referenced(&keys).unwrap(), 3);
+            assert_eq!(db.get_multi(&keys).unwrap()[20], vec![20; 4 * KB]);
+            // the last container is still being filled and is read from memory
+            assert_eq!(db.container_reads(), 2);
+            db.get(&1).unwrap();
+            assert_eq!(db.container_reads(), 3);
+
+            db.rewrite(&[0, 15, 31]).unwrap();
+            assert_eq!(db.containers_referenced(&[0, 15, 31]).unwrap(), 1);
+            assert_eq!(db.get(&15).unwrap(), vec![15; 4 * KB]);
+        }
+
+        let file_path = "pseudo_dev_unaligned_containers";
+        let db = DiskDatabase::<u64, Vec<u8>>::init_on_regular_file(file_path, 1024 * 1024, false)
+            .unwrap();
+        assert_eq!(
+            db.with_containers(1000, 1).err().unwrap().kind(),
+            io::ErrorKind::InvalidInput
+        );
+    }
 }
@@ -0,0 +1,194 @@
+use std::io;
+
+use super::database::Database;
+use super::file_layer::FileHandle;
+use super::storage::DataContainer;
+use super::FileSystem;
+use crate::{ChunkHash, SEG_SIZE};
+
+/// Reader over the contents of a [`FileSystem`] file, created by [`FileSystem::reader`].
+///
+/// Implements [`io::Read`], [`io::BufRead`] and [`io::Seek`], so it can be used with `io::copy`, `BufReader` and the like.
+/// Data is retrieved from the storage in segments of at most 1 MB.
+pub struct FileReader<'fs, B, Hash, K, T>
+where
+    B: Database<Hash, DataContainer<K>>,
+    Hash: ChunkHash,
+    T: Database<K, Vec<u8>>,
+{
+    fs: &'fs FileSystem<B, Hash, K, T>,
+    handle: FileHandle,
+    buffer: Vec<u8>,
+    /// Position of the first unread byte in the buffer.
+    consumed: usize,
+}
+
+/// Writer into a [`FileSystem`] file, created by [`FileSystem::writer`].
+///
+/// Implements [`io::Write`] and [`io::Seek`]. Written data is buffered and passed to the file system
+/// in segments of 1 MB, so that small writes do not produce small chunks. Remaining data is written on
+/// [`flush`][io::Write::flush] or when the writer is dropped.
+///
+/// Data is written at the handle's position using [`FileSystem::write_at`].
+pub struct FileWriter<'fs, B, Hash, K, T>
+where
+    B: Database<Hash, DataContainer<K>>,
+    Hash: ChunkHash,
+    T: Database<K, Vec<u8>>,
+{
+    fs: &'fs mut FileSystem<B, Hash, K, T>,
+    handle: &'fs mut FileHandle,
+    buffer: Vec<u8>,
+}
+
+impl<'fs, B, Hash, K, T> FileReader<'fs, B, Hash, K, T>
+where
+    B: Database<Hash, DataContainer<K>>,
+    Hash: ChunkHash,
+    T: Database<K, Vec<u8>>,
+{
+    pub(crate) fn new(fs: &'fs FileSystem<B, Hash, K, T>, handle: &FileHandle) -> Self {
+        Self {
+            fs,
+            handle: handle.duplicate_readonly(),
+            buffer: vec![],
+            consumed: 0,
+        }
+    }
+}
+
+impl<B, Hash, K, T> io::BufRead for FileReader<'_, B, Hash, K, T>
+where
+    B: Database<Hash, DataContainer<K>>,
+    Hash: ChunkHash,
+    T: Database<K, Vec<u8>>,
+{
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.consumed == self.buffer.len() {
+            // the buffer is reused, so that no allocations are made after the first segment
+            self.buffer.clear();
+            let offset = self.handle.offset();
+            let n = self
+                .fs
+                .read_into(&self.handle, offset, SEG_SIZE, &mut self.buffer)?;
+            self.fs
+                .seek(&mut self.handle, io::SeekFrom::Current(n as i64))?;
+            self.consumed = 0;
+        }
+
+        Ok(&self.buffer[self.consumed..])
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.consumed = (self.consumed + amount).min(self.buffer.len());
+    }
+}
+
+impl<B, Hash, K, T> io::Read for FileReader<'_, B, Hash, K, T>
+where
+    B: Database<Hash, DataContainer<K>>,
+    Hash: ChunkHash,
+    T: Database<K, Vec<u8>>,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = io::BufRead::fill_buf(self)?;
+        let n = available.len().min(buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        io::BufRead::consume(self, n);
+        Ok(n)
+    }
+}
+
+impl<B, Hash, K, T> io::Seek for FileReader<'_, B, Hash, K, T>
+where
+    B: Database<Hash, DataContainer<K>>,
+    Hash: ChunkHash,
+    T: Database<K, Vec<u8>>,
+{
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        // handle is ahead of the reader by the amount of buffered data
+        let unread = (self.buffer.len() - self.consumed) as i64;
+        let pos = match pos {
+            io::SeekFrom::Current(delta) => io::SeekFrom::Current(delta - unread),
+            pos => pos,
+        };
+
+        let offset = self.fs.seek(&mut self.handle, pos)?;
+        self.buffer.clear();
+        self.consumed = 0;
+        Ok(offset)
+    }
+}
+
+impl<'fs, B, Hash, K, T> FileWriter<'fs, B, Hash, K, T>
+where
+    B: Database<Hash, DataContainer<K>>,
+    Hash: ChunkHash,
+    T: Database<K, Vec<u8>>,
+{
+    pub(crate) fn new(fs: &'fs mut FileSystem<B, Hash, K, T>, handle: &'fs mut FileHandle) -> Self {
+        Self {
+            fs,
+            handle,
+            buffer: Vec::with_capacity(SEG_SIZE),
+        }
+    }
+
+    /// Writes buffered data to the file system.
+    fn write_buffer(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+
+        self.fs
+            .write_at(self.handle, self.handle.offset(), &self.buffer)?;
+        self.buffer.clear();
+        Ok(())
+    }
+}
+
+impl<B, Hash, K, T> io::Write for FileWriter<'_, B, Hash, K, T>
+where
+    B: Database<Hash, DataContainer<K>>,
+    Hash: ChunkHash,
+    T: Database<K, Vec<u8>>,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = buf.len().min(SEG_SIZE - self.buffer.len());
+        self.buffer.extend_from_slice(&buf[..n]);
+
+        if self.buffer.len() == SEG_SIZE {
+            self.write_buffer()?;
+        }
+
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_buffer()
+    }
+}
+
+impl<B, Hash, K, T> io::Seek for FileWriter<'_, B, Hash, K, T>
+where
+    B: Database<Hash, DataContainer<K>>,
+    Hash: ChunkHash,
+    T: Database<K, Vec<u8>>,
+{
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        self.write_buffer()?;
+        self.fs.seek(self.handle, pos)
+    }
+}
+
+impl<B, Hash, K, T> Drop for FileWriter<'_, B, Hash, K, T>
+where
+    B: Database<Hash, DataContainer<K>>,
+    Hash: ChunkHash,
+    T: Database<K, Vec<u8>>,
+{
+    fn drop(&mut self) {
+        // errors are ignored, same as in `BufWriter`
+        let _ = self.write_buffer();
+    }
+}
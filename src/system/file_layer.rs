@@ -1,38 +1,99 @@
-use std::collections::HashMap;
+use bincode::{Decode, Encode};
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::io;
 use std::io::ErrorKind;
+use std::mem;
+use std::ops::Range;
+use std::time::SystemTime;
 
 use crate::system::storage::SpansInfo;
+use crate::WriteMeasurements;
 use crate::{ChunkHash, ChunkerRef};
-use crate::{WriteMeasurements, SEG_SIZE};
 
 /// Hashed span, starting at `offset`.
-#[derive(Debug, PartialEq, Eq, Default, Clone, Hash)]
+///
+/// A span without a hash is a hole: it has no chunk behind it and is read as zeros.
+#[derive(Debug, PartialEq, Eq, Default, Clone, Hash, Encode, Decode)]
 pub struct FileSpan<Hash: ChunkHash> {
-    hash: Hash,
+    hash: Option<Hash>,
     offset: usize,
     len: usize,
 }
 
 /// A named file, doesn't store actual contents,
 /// but rather hashes for them.
-#[derive(Clone)]
+#[derive(Clone, Encode, Decode)]
 pub struct File<Hash: ChunkHash> {
+    id: u64,
     name: String,
     spans: Vec<FileSpan<Hash>>,
+    created: SystemTime,
+    modified: SystemTime,
+    chunker: Option<String>,
 }
 
-/// Layer that contains all [`files`][File], accessed by their names.
-#[derive(Default)]
+/// Metadata of a [`file`][File], returned by [`FileLayer::metadata`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileMetadata {
+    /// Logical size of the file, including holes.
+    pub size: usize,
+    /// Time when the file was created.
+    pub created: SystemTime,
+    /// Time of the last modification of the file's contents.
+    pub modified: SystemTime,
+    /// Number of spans the file consists of, including holes.
+    pub span_count: usize,
+    /// Number of distinct chunks referenced by the file.
+    pub unique_chunks: usize,
+    /// `Debug` representation of the chunker that last wrote to the file,
+    /// `None` if the file was never written to.
+    pub chunker: Option<String>,
+}
+
+/// Layer that contains all [`files`][File] and directories, accessed by their paths.
+///
+/// Paths consist of components separated by `/`, e.g. `a/b/c`.
+/// Leading, trailing and repeated separators are ignored, so `/a//b/` is the same path as `a/b`.
+/// The root directory is the empty path; it always exists and can't be removed.
+///
+/// Every file also has a unique id, which is used by [`FileHandle`]s,
+/// so handles stay valid when the file is renamed.
+#[derive(Default, Encode, Decode)]
 pub struct FileLayer<Hash: ChunkHash> {
     files: HashMap<String, File<Hash>>,
+    dirs: HashSet<String>,
+    snapshots: HashMap<String, Snapshot<Hash>>,
+    // current paths of the files by their ids
+    paths: HashMap<u64, String>,
+    // names of the entries of each non-empty directory, and whether they are directories
+    children: HashMap<String, BTreeMap<String, bool>>,
+    next_id: u64,
+}
+
+/// Frozen state of all files and directories of a [`FileLayer`].
+///
+/// Files in a snapshot share spans with the files they were taken from, no data is copied.
+#[derive(Clone, Default, Encode, Decode)]
+pub struct Snapshot<Hash: ChunkHash> {
+    files: HashMap<String, File<Hash>>,
+    dirs: HashSet<String>,
+}
+
+/// Entry of a directory, returned by [`FileLayer::read_dir`].
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DirEntry {
+    /// Name of the entry inside the directory, without the directory path.
+    pub name: String,
+    /// Whether the entry is a directory.
+    pub is_dir: bool,
 }
 
 /// Handle for an open [`file`][File].
 pub struct FileHandle {
-    // can't make file_name a reference
-    // or have a reference to File,
+    // can't have a reference to File,
     // or it would count as an immutable reference for FileSystem
+    file_id: u64,
     file_name: String,
     offset: usize,
     measurements: WriteMeasurements,
@@ -41,38 +102,126 @@ pub struct FileHandle {
 }
 
 impl<Hash: ChunkHash> File<Hash> {
-    fn new(name: String) -> Self {
+    fn new(id: u64, name: String) -> Self {
+        let now = SystemTime::now();
         File {
+            id,
             name,
             spans: vec![],
+            created: now,
+            modified: now,
+            chunker: None,
+        }
+    }
+
+    /// Updates modification time and remembers the chunker used for the write, if there was one.
+    fn touch(&mut self, chunker: Option<&ChunkerRef>) {
+        self.modified = SystemTime::now();
+        if let Some(chunker) = chunker {
+            self.chunker = Some(format!("{chunker:?}"));
+        }
+    }
+
+    /// Returns path of the file.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns unique id of the file, which is also used by its handles.
+    pub(crate) fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Returns metadata of the file.
+    pub fn metadata(&self) -> FileMetadata {
+        FileMetadata {
+            size: self.size(),
+            created: self.created,
+            modified: self.modified,
+            span_count: self.spans.len(),
+            unique_chunks: self.hashes().collect::<HashSet<_>>().len(),
+            chunker: self.chunker.clone(),
         }
     }
+
+    /// Returns total length of the file's contents.
+    pub fn size(&self) -> usize {
+        self.spans
+            .last()
+            .map(|span| span.offset + span.len)
+            .unwrap_or(0)
+    }
+
+    /// Returns total length of the file's spans that are not holes.
+    pub fn data_size(&self) -> usize {
+        self.spans
+            .iter()
+            .filter(|span| span.hash.is_some())
+            .map(|span| span.len)
+            .sum()
+    }
+
+    /// Returns an iterator over hashes of all spans of the file, from beginning to end. Holes are skipped.
+    pub fn hashes(&self) -> impl Iterator<Item = &Hash> {
+        self.spans.iter().filter_map(|span| span.hash.as_ref())
+    }
+}
+
+impl<Hash: ChunkHash> FileSpan<Hash> {
+    /// Returns hash of the span's chunk, or `None` if the span is a hole.
+    pub fn hash(&self) -> Option<&Hash> {
+        self.hash.as_ref()
+    }
+
+    /// Offset of the span from the start of the file.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    pub fn length(&self) -> usize {
+        self.len
+    }
 }
 
 impl FileHandle {
-    fn new<Hash: ChunkHash>(file: &File<Hash>, chunker: ChunkerRef) -> Self {
+    fn new(file_id: u64, file_name: String, chunker: Option<ChunkerRef>) -> Self {
         FileHandle {
-            file_name: file.name.clone(),
+            file_id,
+            file_name,
             offset: 0,
             measurements: Default::default(),
-            chunker: Some(chunker),
+            chunker,
         }
     }
 
-    fn new_readonly<Hash: ChunkHash>(file: &File<Hash>) -> Self {
+    /// Creates a read-only handle for the same file, starting at the same position.
+    pub(crate) fn duplicate_readonly(&self) -> Self {
         FileHandle {
-            file_name: file.name.clone(),
-            offset: 0,
+            file_id: self.file_id,
+            file_name: self.file_name.clone(),
+            offset: self.offset,
             measurements: Default::default(),
             chunker: None,
         }
     }
 
-    /// Returns name of the file.
+    /// Returns name of the file the handle was opened with.
+    ///
+    /// Doesn't follow renames, use [`FileLayer::path`] to get the current path of the file.
     pub fn name(&self) -> &str {
         &self.file_name
     }
 
+    /// Returns current position of the handle in the file.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Returns id of the file the handle belongs to.
+    pub(crate) fn file_id(&self) -> u64 {
+        self.file_id
+    }
+
     /// Closes handle and returns [`WriteMeasurements`] made while file was open.
     pub(crate) fn close(self) -> WriteMeasurements {
         self.measurements
@@ -87,106 +236,514 @@ impl<Hash: ChunkHash> FileLayer<Hash> {
         chunker: ChunkerRef,
         create_new: bool,
     ) -> io::Result<FileHandle> {
-        let name = name.into();
+        let name = normalize(&name.into());
+        if name.is_empty() || self.dirs.contains(&name) {
+            return Err(ErrorKind::IsADirectory.into());
+        }
         if !create_new && self.files.contains_key(&name) {
             return Err(ErrorKind::AlreadyExists.into());
         }
+        self.check_parent(&name)?;
 
-        let file = File::new(name.clone());
-        let _ = self.files.insert(name.clone(), file);
-        let written_file = self.files.get(&name).unwrap();
-        Ok(FileHandle::new(written_file, chunker))
+        let file = File::new(self.allocate_id(), name);
+        let id = self.insert(file).id;
+        Ok(self.handle(id, Some(chunker)))
     }
 
     /// Opens a [`file`][File] based on its name and returns its [`FileHandle`]
     pub fn open(&self, name: &str, chunker: ChunkerRef) -> io::Result<FileHandle> {
-        self.files
-            .get(name)
-            .map(|file| FileHandle::new(file, chunker))
-            .ok_or(ErrorKind::NotFound.into())
+        let id = self.get(name)?.id;
+        Ok(self.handle(id, Some(chunker)))
     }
 
     pub fn open_readonly(&self, name: &str) -> io::Result<FileHandle> {
+        let id = self.get(name)?.id;
+        Ok(self.handle(id, None))
+    }
+
+    /// Creates a handle for the file with the given id, which must be present in the layer.
+    fn handle(&self, file_id: u64, chunker: Option<ChunkerRef>) -> FileHandle {
+        FileHandle::new(file_id, self.paths[&file_id].clone(), chunker)
+    }
+
+    /// Returns reference to a file with the given name.
+    ///
+    /// Returns [`ErrorKind::IsADirectory`] if the name refers to a directory.
+    pub fn get(&self, name: &str) -> io::Result<&File<Hash>> {
+        let name = normalize(name);
         self.files
+            .get(&name)
+            .ok_or_else(|| self.missing_file(&name))
+    }
+
+    /// Removes a file with the given name from the layer and returns it.
+    pub fn delete(&mut self, name: &str) -> io::Result<File<Hash>> {
+        let name = normalize(name);
+        match self.files.remove(&name) {
+            Some(file) => {
+                self.paths.remove(&file.id);
+                self.remove_child(&name);
+                Ok(file)
+            }
+            None => Err(self.missing_file(&name)),
+        }
+    }
+
+    /// Renames the file `old` to `new`, possibly moving it to another directory.
+    /// Handles opened for the file stay valid.
+    ///
+    /// If a file named `new` exists, it is atomically replaced and returned.
+    pub fn rename(&mut self, old: &str, new: &str) -> io::Result<Option<File<Hash>>> {
+        let old = normalize(old);
+        let new = normalize(new);
+        self.get(&old)?;
+        if new.is_empty() || self.dirs.contains(&new) {
+            return Err(ErrorKind::IsADirectory.into());
+        }
+        self.check_parent(&new)?;
+
+        if old == new {
+            return Ok(None);
+        }
+
+        let mut file = self.files.remove(&old).unwrap();
+        self.remove_child(&old);
+        file.name = new.clone();
+        let replaced = self.files.remove(&new);
+        if let Some(replaced) = &replaced {
+            self.paths.remove(&replaced.id);
+        }
+        self.insert(file);
+
+        Ok(replaced)
+    }
+
+    /// Adds the file to the layer, replacing a file with the same name.
+    fn insert(&mut self, file: File<Hash>) -> &File<Hash> {
+        self.set_path(file.id, &file.name);
+        self.add_child(&file.name, false);
+        match self.files.entry(file.name.clone()) {
+            Entry::Occupied(mut entry) => {
+                let replaced = entry.insert(file);
+                self.paths.remove(&replaced.id);
+                entry.into_mut()
+            }
+            Entry::Vacant(entry) => entry.insert(file),
+        }
+    }
+
+    /// Updates the path of the file with the given id.
+    fn set_path(&mut self, id: u64, path: &str) {
+        self.paths.insert(id, path.to_string());
+    }
+
+    /// Adds the entry to the index of its parent directory.
+    fn add_child(&mut self, path: &str, is_dir: bool) {
+        self.children
+            .entry(parent(path).to_string())
+            .or_default()
+            .insert(file_name(path).to_string(), is_dir);
+    }
+
+    /// Removes the entry from the index of its parent directory.
+    fn remove_child(&mut self, path: &str) {
+        let parent = parent(path);
+        if let Some(children) = self.children.get_mut(parent) {
+            children.remove(file_name(path));
+            if children.is_empty() {
+                self.children.remove(parent);
+            }
+        }
+    }
+
+    /// Rebuilds the index of directory entries from all files and directories.
+    fn index_children(&mut self) {
+        self.children.clear();
+        let files = self.files.keys().map(|name| (name, false));
+        let dirs = self.dirs.iter().map(|name| (name, true));
+        for (name, is_dir) in files.chain(dirs) {
+            self.children
+                .entry(parent(name).to_string())
+                .or_default()
+                .insert(file_name(name).to_string(), is_dir);
+        }
+    }
+
+    fn allocate_id(&mut self) -> u64 {
+        self.next_id += 1;
+        self.next_id
+    }
+
+    /// Creates a directory. Its parent directory must already exist.
+    pub fn create_dir(&mut self, path: &str) -> io::Result<()> {
+        let path = normalize(path);
+        if self.entry_exists(&path) {
+            return Err(ErrorKind::AlreadyExists.into());
+        }
+        self.check_parent(&path)?;
+
+        self.add_child(&path, true);
+        self.dirs.insert(path);
+        Ok(())
+    }
+
+    /// Creates a directory and all of its missing parents.
+    ///
+    /// Does nothing if the directory already exists.
+    pub fn create_dir_all(&mut self, path: &str) -> io::Result<()> {
+        let path = normalize(path);
+        if path.is_empty() {
+            return Ok(());
+        }
+
+        let mut end = 0;
+        for component in path.split('/') {
+            end += component.len();
+            let dir = &path[..end];
+            if self.files.contains_key(dir) {
+                return Err(ErrorKind::NotADirectory.into());
+            }
+            if !self.dirs.contains(dir) {
+                self.add_child(dir, true);
+                self.dirs.insert(dir.to_string());
+            }
+            end += 1;
+        }
+        Ok(())
+    }
+
+    /// Returns entries of the directory, sorted by name.
+    pub fn read_dir(&self, path: &str) -> io::Result<Vec<DirEntry>> {
+        let path = normalize(path);
+        self.check_dir(&path)?;
+
+        let entries = self.children.get(&path).into_iter().flatten();
+        Ok(entries
+            .map(|(name, &is_dir)| DirEntry {
+                name: name.clone(),
+                is_dir,
+            })
+            .collect())
+    }
+
+    /// Removes an empty directory.
+    ///
+    /// Returns [`ErrorKind::DirectoryNotEmpty`] if the directory has any entries.
+    pub fn remove_dir(&mut self, path: &str) -> io::Result<()> {
+        let path = normalize(path);
+        if path.is_empty() {
+            let msg = "root directory can't be removed";
+            return Err(io::Error::new(ErrorKind::PermissionDenied, msg));
+        }
+        self.check_dir(&path)?;
+
+        if self.children.contains_key(&path) {
+            return Err(ErrorKind::DirectoryNotEmpty.into());
+        }
+
+        self.remove_child(&path);
+        self.dirs.remove(&path);
+        Ok(())
+    }
+
+    /// Creates a copy of the file `src` named `dst`. Only spans are copied, chunks are shared between the files.
+    ///
+    /// Returns [`ErrorKind::AlreadyExists`] if `dst` exists.
+    pub fn clone_file(&mut self, src: &str, dst: &str) -> io::Result<&File<Hash>> {
+        let dst = normalize(dst);
+        if self.entry_exists(&dst) {
+            return Err(ErrorKind::AlreadyExists.into());
+        }
+        self.check_parent(&dst)?;
+
+        let src = self.get(src)?;
+        let (spans, chunker) = (src.spans.clone(), src.chunker.clone());
+        let file = File {
+            spans,
+            chunker,
+            ..File::new(self.allocate_id(), dst)
+        };
+        Ok(self.insert(file))
+    }
+
+    /// Saves the current state of all files and directories under the given name and returns it.
+    pub fn snapshot(&mut self, name: &str) -> io::Result<&Snapshot<Hash>> {
+        if self.snapshots.contains_key(name) {
+            let msg = format!("snapshot `{name}` already exists");
+            return Err(io::Error::new(ErrorKind::AlreadyExists, msg));
+        }
+
+        let snapshot = Snapshot {
+            files: self.files.clone(),
+            dirs: self.dirs.clone(),
+        };
+        Ok(self.snapshots.entry(name.to_string()).or_insert(snapshot))
+    }
+
+    /// Replaces all files and directories with the ones from the snapshot.
+    /// The snapshot itself is kept and can be restored again.
+    ///
+    /// Returns the replaced state.
+    pub fn restore_snapshot(&mut self, name: &str) -> io::Result<Snapshot<Hash>> {
+        let snapshot = self
+            .snapshots
             .get(name)
-            .map(|file| FileHandle::new_readonly(file))
-            .ok_or(ErrorKind::NotFound.into())
+            .ok_or_else(|| missing_snapshot(name))?;
+
+        let previous = Snapshot {
+            files: mem::replace(&mut self.files, snapshot.files.clone()),
+            dirs: mem::replace(&mut self.dirs, snapshot.dirs.clone()),
+        };
+        let ids: HashMap<u64, String> = self
+            .files
+            .values()
+            .map(|file| (file.id, file.name.clone()))
+            .collect();
+        self.paths.retain(|id, _| ids.contains_key(id));
+        for (id, path) in ids {
+            self.set_path(id, &path);
+        }
+        self.index_children();
+        Ok(previous)
+    }
+
+    /// Removes the snapshot with the given name and returns it.
+    pub fn delete_snapshot(&mut self, name: &str) -> io::Result<Snapshot<Hash>> {
+        self.snapshots
+            .remove(name)
+            .ok_or_else(|| missing_snapshot(name))
+    }
+
+    /// Returns names of all snapshots.
+    pub fn list_snapshots(&self) -> Vec<String> {
+        self.snapshots.keys().cloned().collect()
+    }
+
+    /// Returns an iterator over all files present in the system.
+    pub fn files(&self) -> impl Iterator<Item = &File<Hash>> {
+        self.files.values()
+    }
+
+    /// Checks if the directory with the given path exists.
+    pub fn dir_exists(&self, path: &str) -> bool {
+        let path = normalize(path);
+        path.is_empty() || self.dirs.contains(&path)
+    }
+
+    /// Checks that the path refers to an existing directory.
+    fn check_dir(&self, path: &str) -> io::Result<()> {
+        if path.is_empty() || self.dirs.contains(path) {
+            Ok(())
+        } else if self.files.contains_key(path) {
+            Err(ErrorKind::NotADirectory.into())
+        } else {
+            Err(ErrorKind::NotFound.into())
+        }
+    }
+
+    /// Checks that the parent directory of the path exists.
+    fn check_parent(&self, path: &str) -> io::Result<()> {
+        self.check_dir(parent(path)).map_err(|e| {
+            let msg = format!("parent directory of `{path}` is not available: {e}");
+            io::Error::new(e.kind(), msg)
+        })
+    }
+
+    fn entry_exists(&self, path: &str) -> bool {
+        path.is_empty() || self.dirs.contains(path) || self.files.contains_key(path)
+    }
+
+    /// Returns an error for the file that couldn't be found at the given path.
+    fn missing_file(&self, path: &str) -> io::Error {
+        if path.is_empty() || self.dirs.contains(path) {
+            ErrorKind::IsADirectory.into()
+        } else {
+            ErrorKind::NotFound.into()
+        }
     }
 
     /// Returns reference to a file using [`FileHandle`] that corresponds to it.
-    fn find_file(&self, handle: &FileHandle) -> &File<Hash> {
-        self.files.get(&handle.file_name).unwrap()
+    ///
+    /// Returns [`ErrorKind::NotFound`] if the file was deleted after the handle was opened.
+    fn find_file(&self, handle: &FileHandle) -> io::Result<&File<Hash>> {
+        self.paths
+            .get(&handle.file_id)
+            .and_then(|path| self.files.get(path))
+            .ok_or(ErrorKind::NotFound.into())
     }
 
     /// Returns mutable reference to a file using [`FileHandle`] that corresponds to it.
-    fn find_file_mut(&mut self, handle: &FileHandle) -> &mut File<Hash> {
-        self.files.get_mut(&handle.file_name).unwrap()
+    ///
+    /// Returns [`ErrorKind::NotFound`] if the file was deleted after the handle was opened.
+    fn find_file_mut(&mut self, handle: &FileHandle) -> io::Result<&mut File<Hash>> {
+        self.paths
+            .get(&handle.file_id)
+            .and_then(|path| self.files.get_mut(path))
+            .ok_or(ErrorKind::NotFound.into())
     }
 
-    /// Reads all hashes of the file, from beginning to end.
-    pub fn read_complete(&self, handle: &FileHandle) -> Vec<Hash> {
-        let file = self.find_file(handle);
-        file.spans
-            .iter()
-            .map(|span| span.hash.clone()) // cloning hashes, takes a lot of time
-            .collect()
+    /// Returns current path of the file that corresponds to the handle.
+    pub fn path(&self, handle: &FileHandle) -> io::Result<&str> {
+        self.find_file(handle).map(File::name)
     }
 
-    /// Writes spans to the end of the file.
-    pub fn write(&mut self, handle: &mut FileHandle, info: SpansInfo<Hash>) {
-        let file = self.find_file_mut(handle);
+    /// Writes spans to the end of the file and moves the handle to the new end.
+    ///
+    /// Returns [`ErrorKind::NotFound`] if the file was deleted after the handle was opened.
+    pub fn write(&mut self, handle: &mut FileHandle, info: SpansInfo<Hash>) -> io::Result<()> {
+        let file = self.find_file_mut(handle)?;
+        file.touch(handle.chunker.as_ref());
+        let mut offset = file.size();
         for span in info.spans {
             file.spans.push(FileSpan {
                 hash: span.hash,
-                offset: handle.offset,
+                offset,
                 len: span.length,
             });
-            handle.offset += span.length;
+            offset += span.length;
         }
 
+        handle.offset = offset;
         handle.measurements += info.measurements;
+        Ok(())
     }
 
-    /// Reads 1 MB of data from the open file and returns received hashes,
-    /// starting point is based on the `FileHandle`'s offset.
-    pub fn read(&self, handle: &mut FileHandle) -> Vec<Hash> {
-        let file = self.find_file(handle);
+    /// Returns spans of the file that overlap with the range of `len` bytes starting at `offset`.
+    ///
+    /// The first span is found using binary search over span offsets.
+    pub fn spans_in_range(
+        &self,
+        handle: &FileHandle,
+        offset: usize,
+        len: usize,
+    ) -> io::Result<&[FileSpan<Hash>]> {
+        let range = self.span_indices(handle, offset, len)?;
+        Ok(&self.find_file(handle)?.spans[range])
+    }
 
-        let mut bytes_read = 0;
-        let hashes = file
+    /// Returns indices of the spans that overlap with the range of `len` bytes starting at `offset`.
+    pub fn span_indices(
+        &self,
+        handle: &FileHandle,
+        offset: usize,
+        len: usize,
+    ) -> io::Result<Range<usize>> {
+        let file = self.find_file(handle)?;
+
+        let end = offset.saturating_add(len);
+        let first = file
             .spans
-            .iter()
-            .skip_while(|span| span.offset < handle.offset) // find current span in the file
-            .take_while(|span| {
-                bytes_read += span.len;
-                if bytes_read > SEG_SIZE {
-                    bytes_read -= span.len;
-                    false
-                } else {
-                    true
-                }
-            }) // take 1 MB of spans after current one
-            .map(|span| span.hash.clone()) // take their hashes
-            .collect();
+            .partition_point(|span| span.offset + span.len <= offset);
+        let last = file.spans.partition_point(|span| span.offset < end);
+
+        Ok(first..last.max(first))
+    }
+
+    /// Replaces spans with the given indices with new ones, which are placed starting at `offset`.
+    /// Spans that follow the replaced ones are shifted if the total length has changed.
+    ///
+    /// Moves the handle to the end of the inserted spans.
+    ///
+    /// Returns [`ErrorKind::NotFound`] if the file was deleted after the handle was opened.
+    pub fn splice(
+        &mut self,
+        handle: &mut FileHandle,
+        indices: Range<usize>,
+        offset: usize,
+        infos: Vec<SpansInfo<Hash>>,
+    ) -> io::Result<()> {
+        let file = self.find_file_mut(handle)?;
+        file.touch(handle.chunker.as_ref());
 
-        handle.offset += bytes_read;
+        let old_end = file.spans[indices.clone()]
+            .last()
+            .map(|span| span.offset + span.len)
+            .unwrap_or(offset);
 
-        hashes
+        let mut end = offset;
+        let mut new_spans = vec![];
+        for info in infos {
+            for span in info.spans {
+                new_spans.push(FileSpan {
+                    hash: span.hash,
+                    offset: end,
+                    len: span.length,
+                });
+                end += span.length;
+            }
+            handle.measurements += info.measurements;
+        }
+
+        let tail_start = indices.start + new_spans.len();
+        file.spans.splice(indices, new_spans);
+        if end != old_end {
+            for span in &mut file.spans[tail_start..] {
+                span.offset = span.offset - old_end + end;
+            }
+        }
+
+        handle.offset = end;
+        Ok(())
+    }
+
+    /// Returns metadata of the file with the given name.
+    pub fn metadata(&self, name: &str) -> io::Result<FileMetadata> {
+        self.get(name).map(File::metadata)
+    }
+
+    /// Returns size of the file that corresponds to the handle.
+    pub fn file_size(&self, handle: &FileHandle) -> io::Result<usize> {
+        self.find_file(handle).map(File::size)
+    }
+
+    /// Moves the handle to the given position and returns the new position from the start of the file.
+    ///
+    /// Seeking beyond the end of the file is allowed, reads from there return no data.
+    ///
+    /// # Errors
+    /// `io::ErrorKind::InvalidInput` - if the position is negative or doesn't fit into `i64`
+    pub fn seek(&self, handle: &mut FileHandle, pos: io::SeekFrom) -> io::Result<u64> {
+        let (base, delta) = match pos {
+            io::SeekFrom::Start(offset) => (0, i64::try_from(offset).ok()),
+            io::SeekFrom::Current(delta) => (handle.offset as u64, Some(delta)),
+            io::SeekFrom::End(delta) => (self.file_size(handle)? as u64, Some(delta)),
+        };
+
+        let offset = delta
+            .and_then(|delta| base.checked_add_signed(delta))
+            .filter(|&offset| i64::try_from(offset).is_ok())
+            .and_then(|offset| usize::try_from(offset).ok());
+        let Some(offset) = offset else {
+            let msg = "invalid seek to a negative or overflowing position";
+            return Err(io::Error::new(ErrorKind::InvalidInput, msg));
+        };
+
+        handle.offset = offset;
+        Ok(offset as u64)
     }
 
     /// Checks if the file with the given name exists.
     pub fn file_exists(&self, name: &str) -> bool {
-        self.files.contains_key(name)
+        self.files.contains_key(&normalize(name))
     }
 
-    /// Deletes all file data.
+    /// Deletes all files, directories and snapshots.
     pub fn clear(&mut self) {
-        self.files.clear()
+        self.files.clear();
+        self.dirs.clear();
+        self.snapshots.clear();
+        self.paths.clear();
+        self.children.clear();
     }
 
     /// Gives out a distribution of the chunks with the same hash for the given file.
-    pub fn chunk_count_distribution(&self, handle: &FileHandle) -> HashMap<Hash, (u32, usize)> {
-        let file = self.find_file(handle);
+    ///
+    /// Returns [`ErrorKind::NotFound`] if the file was deleted after the handle was opened.
+    pub fn chunk_count_distribution(
+        &self,
+        handle: &FileHandle,
+    ) -> io::Result<HashMap<Hash, (u32, usize)>> {
+        let file = self.find_file(handle)?;
 
         let mut distribution = HashMap::new();
 
@@ -196,13 +753,18 @@ impl<Hash: ChunkHash> FileLayer<Hash> {
             .zip(file.spans.iter().skip(1))
             .map(|(first, second)| second.offset - first.offset);
 
-        for (span, length) in file.spans.iter().zip(lengths) {
+        for (hash, length) in file
+            .spans
+            .iter()
+            .zip(lengths)
+            .filter_map(|(span, length)| Some((span.hash.as_ref()?, length)))
+        {
             distribution
-                .entry(span.hash.clone())
+                .entry(hash.clone())
                 .and_modify(|(count, _)| *count += 1)
                 .or_insert((1, length));
         }
-        distribution
+        Ok(distribution)
     }
 
     #[cfg(feature = "bench")]
@@ -217,7 +779,8 @@ impl<Hash: ChunkHash> FileLayer<Hash> {
             return Err(io::Error::new(ErrorKind::InvalidInput, msg));
         }
 
-        let file = self.files.get(name).ok_or_else(|| {
+        let name = normalize(name);
+        let file = self.files.get(&name).ok_or_else(|| {
             let msg = format!("file with name `{name}` not found");
             io::Error::new(ErrorKind::NotFound, msg)
         })?;
@@ -227,6 +790,7 @@ impl<Hash: ChunkHash> FileLayer<Hash> {
             .iter()
             .zip(file.spans.iter().skip(1))
             .map(|(first, second)| (first, second.offset - first.offset))
+            .filter(|(span, _)| span.hash.is_some())
             .unique_by(|(span, _)| &span.hash)
             .collect::<Vec<(&FileSpan<Hash>, usize)>>();
 
@@ -249,36 +813,77 @@ impl<Hash: ChunkHash> FileLayer<Hash> {
 
         let remaining_spans = unique_spans.iter().skip(num_repeating);
 
+        let mut offset = 0;
         let spans = repeating_spans
             .chain(remaining_spans)
-            .map(|(span, _)| span)
-            .cloned()
-            .cloned()
+            .map(|(span, length)| {
+                let span = FileSpan {
+                    hash: span.hash.clone(),
+                    offset,
+                    len: *length,
+                };
+                offset += length;
+                span
+            })
             .collect();
+        let chunker = file.chunker.clone();
         let name = format!("{name}.{dedup_ratio:.2}");
 
         let file = File {
-            name: name.clone(),
             spans,
+            chunker,
+            ..File::new(self.allocate_id(), name.clone())
         };
 
-        self.files.insert(name.clone(), file);
+        self.insert(file);
 
         Ok(name)
     }
 
-    /// Returns a list of all file names present in the system.
+    /// Returns a list of all file paths present in the system.
     pub fn list_files(&self) -> Vec<String> {
         self.files.keys().cloned().collect()
     }
 }
 
+impl<Hash: ChunkHash> Snapshot<Hash> {
+    /// Returns an iterator over all files in the snapshot.
+    pub fn files(&self) -> impl Iterator<Item = &File<Hash>> {
+        self.files.values()
+    }
+}
+
+fn missing_snapshot(name: &str) -> io::Error {
+    let msg = format!("snapshot `{name}` not found");
+    io::Error::new(ErrorKind::NotFound, msg)
+}
+
+/// Brings the path to the form used as a key in [`FileLayer`], without empty components.
+fn normalize(path: &str) -> String {
+    path.split('/')
+        .filter(|component| !component.is_empty())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Returns the path of the parent directory for a normalized path.
+fn parent(path: &str) -> &str {
+    path.rsplit_once('/').map_or("", |(parent, _)| parent)
+}
+
+/// Returns the last component of a normalized path.
+fn file_name(path: &str) -> &str {
+    path.rsplit_once('/').map_or(path, |(_, name)| name)
+}
+
 #[cfg(test)]
 mod tests {
-    use std::io::ErrorKind;
+    use std::io::{ErrorKind, SeekFrom};
 
+    use super::{file_name, parent, DirEntry};
     use crate::chunkers::FSChunker;
     use crate::system::file_layer::FileLayer;
+    use crate::system::storage::{Span, SpansInfo};
 
     #[test]
     fn file_layer_create_file() {
@@ -301,4 +906,133 @@ mod tests {
         assert!(result.is_err());
         assert_eq!(result.err().unwrap().kind(), ErrorKind::AlreadyExists);
     }
+
+    #[test]
+    fn handles_follow_renames_and_fail_after_delete() {
+        let mut fl: FileLayer<Vec<u8>> = FileLayer::default();
+        let mut handle = fl.create("a", FSChunker::default().into(), false).unwrap();
+        let mut reader = fl.open_readonly("a").unwrap();
+
+        fl.rename("a", "b").unwrap();
+        assert_eq!(handle.name(), "a");
+        assert_eq!(fl.path(&handle).unwrap(), "b");
+        assert_eq!(fl.path(&reader).unwrap(), "b");
+
+        fl.delete("b").unwrap();
+        assert_eq!(handle.name(), "a");
+        assert_eq!(fl.path(&handle).unwrap_err().kind(), ErrorKind::NotFound);
+        let hole = SpansInfo::new(vec![Span::hole(10)]);
+        let result = fl.write(&mut handle, hole);
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::NotFound);
+        let hole = SpansInfo::new(vec![Span::hole(10)]);
+        let result = fl.splice(&mut reader, 0..0, 0, vec![hole]);
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::NotFound);
+        let result = fl.chunk_count_distribution(&reader);
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn paths_are_normalized() {
+        let mut fl: FileLayer<Vec<u8>> = FileLayer::default();
+        fl.create_dir("/a/").unwrap();
+        fl.create("a//b", FSChunker::default().into(), false)
+            .unwrap();
+
+        assert!(fl.dir_exists("a"));
+        assert!(fl.file_exists("/a/b"));
+        assert_eq!(fl.list_files(), vec!["a/b"]);
+    }
+
+    #[test]
+    fn files_need_existing_parent_directory() {
+        let mut fl: FileLayer<Vec<u8>> = FileLayer::default();
+        let result = fl.create("a/b", FSChunker::default().into(), false);
+        assert_eq!(result.err().unwrap().kind(), ErrorKind::NotFound);
+
+        fl.create("a", FSChunker::default().into(), false).unwrap();
+        let result = fl.create("a/b", FSChunker::default().into(), false);
+        assert_eq!(result.err().unwrap().kind(), ErrorKind::NotADirectory);
+        assert_eq!(
+            fl.create_dir("a").unwrap_err().kind(),
+            ErrorKind::AlreadyExists
+        );
+    }
+
+    /// Lists the directory by scanning all files and directories.
+    fn scan_dir(fl: &FileLayer<Vec<u8>>, path: &str) -> Vec<DirEntry> {
+        let files = fl.files.keys().map(|name| (name, false));
+        let dirs = fl.dirs.iter().map(|name| (name, true));
+        let mut entries = files
+            .chain(dirs)
+            .filter(|(name, _)| parent(name) == path)
+            .map(|(name, is_dir)| DirEntry {
+                name: file_name(name).to_string(),
+                is_dir,
+            })
+            .collect::<Vec<_>>();
+        entries.sort();
+        entries
+    }
+
+    #[test]
+    fn directory_index_follows_changes() {
+        let mut fl: FileLayer<Vec<u8>> = FileLayer::default();
+        let check = |fl: &FileLayer<Vec<u8>>| {
+            for dir in ["", "a", "a/b", "c"] {
+                if fl.dir_exists(dir) {
+                    assert_eq!(fl.read_dir(dir).unwrap(), scan_dir(fl, dir), "{dir}");
+                }
+            }
+        };
+
+        fl.create_dir_all("a/b").unwrap();
+        fl.create_dir("c").unwrap();
+        for name in ["a/1", "a/b/2", "c/3", "4"] {
+            fl.create(name, FSChunker::default().into(), false).unwrap();
+        }
+        check(&fl);
+        fl.snapshot("before").unwrap();
+
+        fl.rename("a/1", "c/3").unwrap();
+        fl.rename("a/b/2", "a/5").unwrap();
+        fl.clone_file("4", "a/b/6").unwrap();
+        fl.create("4", FSChunker::default().into(), true).unwrap();
+        check(&fl);
+        assert_eq!(fl.read_dir("c").unwrap().len(), 1);
+
+        fl.delete("a/b/6").unwrap();
+        fl.remove_dir("a/b").unwrap();
+        assert_eq!(
+            fl.remove_dir("a").unwrap_err().kind(),
+            ErrorKind::DirectoryNotEmpty
+        );
+        check(&fl);
+
+        fl.restore_snapshot("before").unwrap();
+        check(&fl);
+        assert_eq!(fl.read_dir("a/b").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn seek_out_of_range_fails() {
+        let mut fl: FileLayer<Vec<u8>> = FileLayer::default();
+        let mut handle = fl.create("a", FSChunker::default().into(), false).unwrap();
+
+        for pos in [
+            SeekFrom::Start(u64::MAX),
+            SeekFrom::Start(i64::MAX as u64 + 1),
+            SeekFrom::Current(-1),
+            SeekFrom::End(i64::MIN),
+        ] {
+            let result = fl.seek(&mut handle, pos);
+            assert_eq!(result.unwrap_err().kind(), ErrorKind::InvalidInput);
+        }
+
+        assert_eq!(fl.seek(&mut handle, SeekFrom::Start(10)).unwrap(), 10);
+        let result = fl.seek(&mut handle, SeekFrom::Current(i64::MAX));
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::InvalidInput);
+        // failed seeks don't move the handle
+        assert_eq!(fl.seek(&mut handle, SeekFrom::Current(-10)).unwrap(), 0);
+        assert_eq!(fl.seek(&mut handle, SeekFrom::End(5)).unwrap(), 5);
+    }
 }
@@ -0,0 +1,314 @@
+use std::hash::{BuildHasher, Hash, RandomState};
+use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use super::database::{Database, IterableDatabase};
+use crate::CacheStats;
+
+/// Database wrapper that keeps an in-memory Bloom filter of the stored keys.
+///
+/// Lookups of keys that the filter rules out are answered without touching the underlying database,
+/// which saves index accesses for slow databases, e.g. [`DiskDatabase`][crate::DiskDatabase].
+/// Keys that pass the filter are looked up in the database as usual, so answers are always exact.
+///
+/// Removed keys can't be taken out of the filter, they only make false positives more likely until the database is cleared.
+pub struct FilteredDatabase<D> {
+    database: D,
+    filter: BloomFilter,
+    /// Number of lookups answered by the filter alone.
+    negatives: AtomicUsize,
+    /// Number of lookups that passed the filter, but were not found in the database.
+    false_positives: AtomicUsize,
+}
+
+impl<D> FilteredDatabase<D> {
+    /// Wraps the database into a filter sized for `expected_keys` keys with the given false positive rate.
+    ///
+    /// Keys already present in the database are added to the filter, so a loaded database can be wrapped as well.
+    /// `expected_keys` should account for them.
+    ///
+    /// # Panics
+    /// Panics if `false_positive_rate` is not in the (0, 1) range.
+    pub fn new<K, V>(database: D, expected_keys: usize, false_positive_rate: f64) -> Self
+    where
+        K: Hash,
+        D: IterableDatabase<K, V>,
+    {
+        assert!(
+            false_positive_rate > 0.0 && false_positive_rate < 1.0,
+            "false positive rate must be between 0 and 1"
+        );
+
+        let mut filter = BloomFilter::new(expected_keys, false_positive_rate);
+        for key in database.keys() {
+            filter.add(filter.hash(key));
+        }
+
+        Self {
+            database,
+            filter,
+            negatives: AtomicUsize::new(0),
+            false_positives: AtomicUsize::new(0),
+        }
+    }
+
+    /// Returns the false positive rate observed so far,
+    /// i.e. the share of lookups of absent keys that were not ruled out by the filter.
+    pub fn false_positive_rate(&self) -> f64 {
+        let negatives = self.negatives.load(Ordering::Relaxed);
+        let false_positives = self.false_positives.load(Ordering::Relaxed);
+        if negatives + false_positives == 0 {
+            return 0.0;
+        }
+        false_positives as f64 / (negatives + false_positives) as f64
+    }
+
+    /// Returns the false positive rate expected from the filter for the number of keys inserted into it.
+    pub fn expected_false_positive_rate(&self) -> f64 {
+        self.filter.expected_false_positive_rate()
+    }
+
+    /// Returns the number of bytes taken by the filter.
+    pub fn memory_usage(&self) -> usize {
+        self.filter.memory_usage()
+    }
+
+    /// Consumes the wrapper and returns the underlying database.
+    pub fn into_inner(self) -> D {
+        self.database
+    }
+
+    /// Returns `false` if the key is definitely absent, counting it as a negative.
+    fn may_contain<K: Hash>(&self, key: &K) -> bool {
+        let may_contain = self.filter.may_contain(key);
+        if !may_contain {
+            self.negatives.fetch_add(1, Ordering::Relaxed);
+        }
+        may_contain
+    }
+
+    fn count_false_positive(&self) {
+        self.false_positives.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+impl<K, V, D> Database<K, V> for FilteredDatabase<D>
+where
+    K: Hash,
+    D: Database<K, V>,
+{
+    fn insert(&mut self, key: K, value: V) -> io::Result<()> {
+        let hash = self.filter.hash(&key);
+        self.database.insert(key, value)?;
+        self.filter.add(hash);
+        Ok(())
+    }
+
+    fn get(&self, key: &K) -> io::Result<V> {
+        if !self.may_contain(key) {
+            return Err(io::ErrorKind::NotFound.into());
+        }
+
+        let value = self.database.get(key);
+        if value
+            .as_ref()
+            .is_err_and(|e| e.kind() == io::ErrorKind::NotFound)
+        {
+            self.count_false_positive();
+        }
+        value
+    }
+
+    fn insert_multi(&mut self, pairs: Vec<(K, V)>) -> io::Result<()> {
+        let hashes = pairs
+            .iter()
+            .map(|(key, _)| self.filter.hash(key))
+            .collect::<Vec<_>>();
+        self.database.insert_multi(pairs)?;
+        for hash in hashes {
+            self.filter.add(hash);
+        }
+        Ok(())
+    }
+
+    fn get_multi(&self, keys: &[K]) -> io::Result<Vec<V>> {
+        if !keys.iter().all(|key| self.may_contain(key)) {
+            return Err(io::ErrorKind::NotFound.into());
+        }
+        self.database.get_multi(keys)
+    }
+
+    fn contains(&self, key: &K) -> bool {
+        if !self.may_contain(key) {
+            return false;
+        }
+
+        let contains = self.database.contains(key);
+        if !contains {
+            self.count_false_positive();
+        }
+        contains
+    }
+
+    fn remove(&mut self, key: &K) -> io::Result<()> {
+        if !self.may_contain(key) {
+            return Err(io::ErrorKind::NotFound.into());
+        }
+        self.database.remove(key)
+    }
+
+    fn cache_stats(&self) -> Option<CacheStats> {
+        self.database.cache_stats()
+    }
+}
+
+impl<K, V, D> IterableDatabase<K, V> for FilteredDatabase<D>
+where
+    K: Hash,
+    D: IterableDatabase<K, V>,
+{
+    fn iterator(&self) -> Box<dyn Iterator<Item = io::Result<(&K, V)>> + '_> {
+        self.database.iterator()
+    }
+
+    fn for_each_mut(&mut self, f: &mut dyn FnMut(&K, &mut V) -> io::Result<()>) -> io::Result<()> {
+        self.database.for_each_mut(f)
+    }
+
+    fn keys<'a>(&'a self) -> Box<dyn Iterator<Item = &'a K> + 'a>
+    where
+        V: 'a,
+    {
+        self.database.keys()
+    }
+
+    fn values(&self) -> Box<dyn Iterator<Item = io::Result<V>> + '_> {
+        self.database.values()
+    }
+
+    fn clear(&mut self) -> io::Result<()> {
+        self.database.clear()?;
+        self.filter.clear();
+        Ok(())
+    }
+}
+
+/// Bloom filter over key hashes, bit positions are derived from a single 64-bit hash by double hashing.
+struct BloomFilter {
+    bits: Vec<u64>,
+    bit_count: u64,
+    hash_count: u32,
+    /// Number of keys added since the filter was created or cleared.
+    /// Keys whose bits were all set already are not counted, as they are most likely duplicates.
+    keys: usize,
+    state: RandomState,
+}
+
+impl BloomFilter {
+    fn new(expected_keys: usize, false_positive_rate: f64) -> Self {
+        let expected_keys = expected_keys.max(1) as f64;
+        let ln2 = std::f64::consts::LN_2;
+
+        let bit_count = (-expected_keys * false_positive_rate.ln() / (ln2 * ln2))
+            .ceil()
+            .max(64.0) as u64;
+        let hash_count = ((bit_count as f64 / expected_keys) * ln2).round().max(1.0) as u32;
+
+        Self {
+            bits: vec![0; bit_count.div_ceil(64) as usize],
+            bit_count,
+            hash_count,
+            keys: 0,
+            state: RandomState::new(),
+        }
+    }
+
+    fn hash<K: Hash>(&self, key: &K) -> u64 {
+        self.state.hash_one(key)
+    }
+
+    /// Returns position of the `i`-th bit that corresponds to the hash.
+    fn position(&self, hash: u64, i: u32) -> usize {
+        let step = hash.rotate_left(32) | 1;
+        (hash.wrapping_add(u64::from(i).wrapping_mul(step)) % self.bit_count) as usize
+    }
+
+    fn add(&mut self, hash: u64) {
+        let mut changed = false;
+        for i in 0..self.hash_count {
+            let position = self.position(hash, i);
+            let word = &mut self.bits[position / 64];
+            changed |= *word & (1 << (position % 64)) == 0;
+            *word |= 1 << (position % 64);
+        }
+        if changed {
+            self.keys += 1;
+        }
+    }
+
+    fn may_contain<K: Hash>(&self, key: &K) -> bool {
+        let hash = self.hash(key);
+        (0..self.hash_count).all(|i| {
+            let position = self.position(hash, i);
+            self.bits[position / 64] & (1 << (position % 64)) != 0
+        })
+    }
+
+    fn expected_false_positive_rate(&self) -> f64 {
+        let k = self.hash_count as f64;
+        let fill = 1.0 - (-k * self.keys as f64 / self.bit_count as f64).exp();
+        fill.powf(k)
+    }
+
+    fn memory_usage(&self) -> usize {
+        self.bits.len() * size_of::<u64>()
+    }
+
+    fn clear(&mut self) {
+        self.bits.fill(0);
+        self.keys = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::io;
+
+    use super::FilteredDatabase;
+    use crate::system::database::Database;
+
+    #[test]
+    fn filter_has_no_false_negatives_and_expected_false_positives() {
+        let mut db = FilteredDatabase::new(HashMap::<u64, u64>::new(), 10_000, 0.01);
+
+        let pairs = (0..10_000).map(|i| (i, i)).collect();
+        db.insert_multi(pairs).unwrap();
+        assert!((0..10_000).all(|i| db.contains(&i)));
+        assert_eq!(db.false_positive_rate(), 0.0);
+
+        let found = (10_000..110_000).filter(|i| db.contains(i)).count();
+        assert_eq!(found, 0);
+        assert!(db.false_positive_rate() < 0.03);
+        assert!(db.expected_false_positive_rate() < 0.02);
+        // about 9.6 bits per key for 1% false positives
+        assert!(db.memory_usage() < 10_000 * 10 / 8 + 8);
+
+        assert_eq!(
+            db.get(&200_000).unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+        assert_eq!(db.get(&5).unwrap(), 5);
+    }
+
+    #[test]
+    fn keys_of_wrapped_database_pass_the_filter() {
+        let map = (0..1000).map(|i| (i, i)).collect::<HashMap<u64, u64>>();
+        let db = FilteredDatabase::new(map, 1000, 0.01);
+
+        assert!((0..1000).all(|i| db.contains(&i)));
+        assert_eq!(db.get(&500).unwrap(), 500);
+        assert!(db.expected_false_positive_rate() > 0.0);
+        assert!(!db.contains(&1000));
+    }
+}
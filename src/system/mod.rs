@@ -1,21 +1,38 @@
 use std::collections::HashMap;
-use std::io;
-use std::io::Write;
+use std::io::{self, Read, Write};
 use std::path::Path;
-
-use database::{Database, IterableDatabase};
-use file_layer::{FileHandle, FileLayer};
+use std::time::Duration;
+
+use bincode::{Decode, Encode};
+use database::{decode_from_file, encode_to_file};
+use database::{Database, IterableDatabase, PersistentDatabase};
+use file_io::{FileReader, FileWriter};
+use file_layer::{DirEntry, FileHandle, FileLayer, FileMetadata, FileSpan};
+use pipeline::Pipeline;
 use scrub::{Scrub, ScrubMeasurements};
-use storage::{ChunkStorage, DataContainer};
+use storage::{ChunkStorage, CollisionPolicy, DataContainer, Span, SpansInfo};
 
-use super::{ChunkHash, ChunkerRef, Hasher, WriteMeasurements};
+use super::{
+    ChunkHash, ChunkerRef, ChunkerRegistry, Compressor, ConvergentCipher, Hasher,
+    WriteMeasurements, SEG_SIZE,
+};
 
+mod aligned_buffer;
+pub mod cached_database;
+mod chunk_stream;
+mod container;
 mod data_block;
 pub mod database;
 pub mod disk_database;
+pub mod file_io;
 pub mod file_layer;
+pub mod filtered_database;
+pub mod pipeline;
 pub mod scrub;
+pub mod sharded_database;
+pub mod shared;
 pub mod storage;
+mod superblock;
 
 /// A file system provided by chunkfs.
 ///
@@ -30,6 +47,8 @@ where
 {
     storage: ChunkStorage<Hash, B, K, T>,
     file_layer: FileLayer<Hash>,
+    /// Chunkers that files are reopened with by [`reopen_file`][Self::reopen_file].
+    chunkers: ChunkerRegistry,
 }
 
 /// Creates a file system that can be used to compare CDC algorithms.
@@ -61,6 +80,15 @@ where
         self.file_layer.file_exists(name)
     }
 
+    /// Returns metadata of the file with the given `name`: its size, timestamps,
+    /// span and chunk counts, and the chunker that last wrote to it.
+    ///
+    /// # Errors
+    /// `io::ErrorKind::NotFound` - if the file does not exist
+    pub fn metadata(&self, name: &str) -> io::Result<FileMetadata> {
+        self.file_layer.metadata(name)
+    }
+
     /// Tries to open a file with the given name and returns its `FileHandle` if it exists,
     /// or `None`, if it doesn't.
     pub fn open_file<S, C>(&self, name: S, chunker: C) -> io::Result<FileHandle>
@@ -71,6 +99,24 @@ where
         self.file_layer.open(name.as_ref(), chunker.into())
     }
 
+    /// Opens a file with a new instance of the chunker that last wrote to it, taken from the chunker registry
+    /// given to [`load`][Self::load].
+    ///
+    /// # Errors
+    /// * `io::ErrorKind::NotFound` - if the file does not exist, or its chunker is not in the registry.
+    pub fn reopen_file(&self, name: &str) -> io::Result<FileHandle> {
+        let metadata = self.metadata(name)?;
+        let chunker = metadata
+            .chunker
+            .as_deref()
+            .and_then(|chunker| self.chunkers.get(chunker))
+            .ok_or_else(|| {
+                let msg = format!("chunker of file {name} is not registered");
+                io::Error::new(io::ErrorKind::NotFound, msg)
+            })?;
+        self.file_layer.open(name, chunker)
+    }
+
     pub fn open_file_readonly<S>(&self, name: S) -> io::Result<FileHandle>
     where
         S: AsRef<str>,
@@ -85,17 +131,163 @@ where
         S: Into<String>,
         C: Into<ChunkerRef>,
     {
+        let name = name.into();
+        if self.file_exists(&name) {
+            self.delete_file(&name)?;
+        }
+
         self.file_layer.create(name, chunker.into(), true)
     }
 
-    /// Writes given data to the file.
+    /// Deletes the file with the given name.
+    ///
+    /// Chunks of the file stay in the storage until [`collect_garbage`][Self::collect_garbage] is called.
+    /// File handles opened for this file become invalid.
+    ///
+    /// # Errors
+    /// `io::ErrorKind::NotFound` - if the file does not exist
+    pub fn delete_file(&mut self, name: &str) -> io::Result<()> {
+        let file = self.file_layer.delete(name)?;
+        self.storage.release(file.hashes(), file.data_size());
+        Ok(())
+    }
+
+    /// Renames the file `old` to `new`. The file can be moved to another existing directory this way.
+    ///
+    /// If a file named `new` exists, it is replaced, as if it was deleted right before the rename.
+    /// Open handles of the renamed file stay valid, handles of the replaced file become invalid.
+    /// Nothing is changed if an error is returned.
+    ///
+    /// # Errors
+    /// * `io::ErrorKind::NotFound` - if `old` or the parent directory of `new` does not exist
+    /// * `io::ErrorKind::IsADirectory` - if `old` or `new` is a directory
+    pub fn rename(&mut self, old: &str, new: &str) -> io::Result<()> {
+        if let Some(replaced) = self.file_layer.rename(old, new)? {
+            self.storage
+                .release(replaced.hashes(), replaced.data_size());
+        }
+        Ok(())
+    }
+
+    /// Returns current name of the file that corresponds to the handle.
+    ///
+    /// # Errors
+    /// `io::ErrorKind::NotFound` - if the file was deleted
+    pub fn file_name<'a>(&'a self, handle: &FileHandle) -> io::Result<&'a str> {
+        self.file_layer.path(handle)
+    }
+
+    /// Creates a copy of the file `src` named `dst`.
+    ///
+    /// Only the list of spans is duplicated, the chunks are shared by both files, so no data is written.
+    ///
+    /// # Errors
+    /// * `io::ErrorKind::NotFound` - if `src` does not exist
+    /// * `io::ErrorKind::AlreadyExists` - if `dst` exists
+    pub fn clone_file(&mut self, src: &str, dst: &str) -> io::Result<()> {
+        let file = self.file_layer.clone_file(src, dst)?;
+        self.storage.retain(file.hashes(), file.data_size());
+        Ok(())
+    }
+
+    /// Saves the current state of all files and directories as a snapshot with the given name.
+    ///
+    /// Chunks referenced by the snapshot are kept in the storage until the snapshot is deleted,
+    /// even if the files they belong to are changed or deleted.
+    /// Snapshots are not counted as written data.
+    ///
+    /// # Errors
+    /// `io::ErrorKind::AlreadyExists` - if a snapshot with the same name exists
+    pub fn snapshot(&mut self, name: &str) -> io::Result<()> {
+        let snapshot = self.file_layer.snapshot(name)?;
+        for file in snapshot.files() {
+            self.storage.retain(file.hashes(), 0);
+        }
+        Ok(())
+    }
+
+    /// Replaces all files and directories with the ones saved in the snapshot.
+    /// The snapshot is kept and can be restored again later.
+    ///
+    /// File handles opened before the restore refer to the restored versions of their files,
+    /// handles of the files that are not in the snapshot become invalid.
+    ///
+    /// # Errors
+    /// `io::ErrorKind::NotFound` - if the snapshot does not exist
+    pub fn restore_snapshot(&mut self, name: &str) -> io::Result<()> {
+        let previous = self.file_layer.restore_snapshot(name)?;
+        for file in self.file_layer.files() {
+            self.storage.retain(file.hashes(), file.data_size());
+        }
+        for file in previous.files() {
+            self.storage.release(file.hashes(), file.data_size());
+        }
+        Ok(())
+    }
+
+    /// Deletes the snapshot with the given name.
+    ///
+    /// Chunks that were referenced only by the snapshot stay in the storage until
+    /// [`collect_garbage`][Self::collect_garbage] is called.
+    ///
+    /// # Errors
+    /// `io::ErrorKind::NotFound` - if the snapshot does not exist
+    pub fn delete_snapshot(&mut self, name: &str) -> io::Result<()> {
+        let snapshot = self.file_layer.delete_snapshot(name)?;
+        for file in snapshot.files() {
+            self.storage.release(file.hashes(), 0);
+        }
+        Ok(())
+    }
+
+    /// Returns names of all snapshots.
+    pub fn list_snapshots(&self) -> Vec<String> {
+        self.file_layer.list_snapshots()
+    }
+
+    /// Checks if the directory with the given `path` exists.
+    pub fn dir_exists(&self, path: &str) -> bool {
+        self.file_layer.dir_exists(path)
+    }
+
+    /// Creates a directory. Components of the path are separated by `/`.
+    ///
+    /// # Errors
+    /// * `io::ErrorKind::AlreadyExists` - if a file or a directory with the same path exists
+    /// * `io::ErrorKind::NotFound` - if the parent directory does not exist
+    pub fn create_dir(&mut self, path: &str) -> io::Result<()> {
+        self.file_layer.create_dir(path)
+    }
+
+    /// Creates a directory together with all of its missing parents.
+    pub fn create_dir_all(&mut self, path: &str) -> io::Result<()> {
+        self.file_layer.create_dir_all(path)
+    }
+
+    /// Returns entries of the directory, sorted by name.
+    ///
+    /// # Errors
+    /// * `io::ErrorKind::NotFound` - if the directory does not exist
+    /// * `io::ErrorKind::NotADirectory` - if the path refers to a file
+    pub fn read_dir(&self, path: &str) -> io::Result<Vec<DirEntry>> {
+        self.file_layer.read_dir(path)
+    }
+
+    /// Removes an empty directory.
+    ///
+    /// # Errors
+    /// `io::ErrorKind::DirectoryNotEmpty` - if the directory contains files or other directories
+    pub fn remove_dir(&mut self, path: &str) -> io::Result<()> {
+        self.file_layer.remove_dir(path)
+    }
+
+    /// Writes given data to the end of the file.
     ///
     /// # Errors
     /// `io::ErrorKind::PermissionDenied` - if the handle was opened in read-only mode
     pub fn write_to_file(&mut self, handle: &mut FileHandle, data: &[u8]) -> io::Result<()> {
-        if !self.file_exists(handle.name()) {
-            return Err(io::ErrorKind::NotFound.into());
-        }
+        // fails if the file was deleted
+        self.file_layer.path(handle)?;
 
         let Some(chunker) = &mut handle.chunker else {
             let msg = "file handle is read-only";
@@ -105,12 +297,205 @@ where
         let all_spans = self.storage.write(data, chunker)?;
 
         for spans in all_spans {
-            self.file_layer.write(handle, spans);
+            self.file_layer.write(handle, spans)?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes given data to the file starting at `offset`, overwriting existing contents
+    /// and extending the file if necessary. Moves the handle to the end of the written data.
+    ///
+    /// Spans affected by the write are split: their remaining parts are re-chunked together with
+    /// the new data using the handle's chunker, and the resulting spans replace the old ones.
+    ///
+    /// If `offset` is beyond the end of the file, the gap is filled with a hole.
+    ///
+    /// # Errors
+    /// `io::ErrorKind::PermissionDenied` - if the handle was opened in read-only mode
+    pub fn write_at(
+        &mut self,
+        handle: &mut FileHandle,
+        offset: usize,
+        data: &[u8],
+    ) -> io::Result<()> {
+        let size = self.file_size(handle)?;
+
+        if handle.chunker.is_none() {
+            let msg = "file handle is read-only";
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied, msg));
+        };
+
+        if offset > size {
+            self.append_hole(handle, size, offset - size)?;
+        }
+
+        let indices = self.file_layer.span_indices(handle, offset, data.len())?;
+        let affected = self.file_layer.spans_in_range(handle, offset, data.len())?;
+
+        let start = affected.first().map_or(offset, |span| span.offset());
+        let hashes = affected
+            .iter()
+            .filter_map(|span| span.hash().cloned())
+            .collect::<Vec<_>>();
+        let released = affected
+            .iter()
+            .filter(|span| span.hash().is_some())
+            .map(|span| span.length())
+            .sum();
+
+        let old = self.retrieve_spans(affected)?;
+        let old_end = start + old.len();
+        let data_end = offset + data.len();
+
+        let mut region = Vec::with_capacity(old_end.max(data_end) - start);
+        region.extend_from_slice(&old[..offset - start]);
+        region.extend_from_slice(data);
+        if data_end < old_end {
+            region.extend_from_slice(&old[data_end - start..]);
+        }
+
+        let chunker = handle.chunker.as_ref().unwrap();
+        let all_spans = self.storage.write(&region, chunker)?;
+        self.storage.release(&hashes, released);
+
+        self.file_layer.splice(handle, indices, start, all_spans)?;
+        self.file_layer
+            .seek(handle, io::SeekFrom::Start(data_end as u64))?;
+
+        Ok(())
+    }
+
+    /// Truncates or extends the file with the given name to `new_len` bytes.
+    ///
+    /// If the file is extended, the new part is a hole, which is read as zeros and takes no space in the storage.
+    /// If the file is shrunk, the part of the last chunk that remains in the file is stored as a new chunk.
+    ///
+    /// # Errors
+    /// `io::ErrorKind::NotFound` - if the file does not exist
+    pub fn truncate(&mut self, name: &str, new_len: usize) -> io::Result<()> {
+        let mut handle = self.open_file_readonly(name)?;
+        let size = self.file_size(&handle)?;
+
+        if new_len >= size {
+            return self.append_hole(&mut handle, size, new_len - size);
+        }
+
+        let indices = self
+            .file_layer
+            .span_indices(&handle, new_len, size - new_len)?;
+        let affected = self
+            .file_layer
+            .spans_in_range(&handle, new_len, size - new_len)?;
+
+        let first = affected[0].clone();
+        let hashes = affected
+            .iter()
+            .filter_map(|span| span.hash().cloned())
+            .collect::<Vec<_>>();
+        let released = affected
+            .iter()
+            .filter(|span| span.hash().is_some())
+            .map(|span| span.length())
+            .sum();
+
+        let kept = new_len - first.offset();
+        let mut infos = vec![];
+        if kept > 0 {
+            let info = match first.hash() {
+                Some(hash) => {
+                    let chunk = self.storage.retrieve(std::slice::from_ref(hash))?;
+                    self.storage.write_chunk(&chunk[0][..kept])?
+                }
+                None => SpansInfo::new(vec![Span::hole(kept)]),
+            };
+            infos.push(info);
         }
 
+        self.storage.release(&hashes, released);
+        self.file_layer
+            .splice(&mut handle, indices, first.offset(), infos)?;
+
+        Ok(())
+    }
+
+    /// Appends a hole of the given length to the end of the file, which has the given size.
+    fn append_hole(&mut self, handle: &mut FileHandle, size: usize, len: usize) -> io::Result<()> {
+        if len == 0 {
+            return Ok(());
+        }
+
+        let position = handle.offset();
+        let indices = self.file_layer.span_indices(handle, size, len)?;
+        let hole = SpansInfo::new(vec![Span::hole(len)]);
+        self.file_layer.splice(handle, indices, size, vec![hole])?;
+        self.file_layer
+            .seek(handle, io::SeekFrom::Start(position as u64))?;
+
         Ok(())
     }
 
+    /// Sets minimal length of an all-zero chunk that is stored as a hole instead of being hashed and saved.
+    /// Holes are not counted towards written size, so they do not affect deduplication ratio.
+    ///
+    /// Hole detection is disabled by default, or if `None` is passed.
+    pub fn set_zero_chunk_threshold(&mut self, threshold: Option<usize>) {
+        self.storage.set_zero_chunk_threshold(threshold)
+    }
+
+    /// Enables the paranoid mode, in which every written chunk whose hash is already stored
+    /// is compared byte by byte with the stored chunk. A mismatch means a hash collision,
+    /// which is handled according to `policy`: counted in [`WriteMeasurements::collisions`] or failing the write.
+    ///
+    /// Collisions are not checked by default, or if `None` is passed.
+    pub fn set_collision_policy(&mut self, policy: Option<CollisionPolicy>) {
+        self.storage.set_collision_policy(policy)
+    }
+
+    /// Sets the compressor applied to chunks after deduplication, before they are inserted into the database.
+    /// If the cipher is set, chunks are compressed before encryption instead, so duplicates are compressed too.
+    /// Chunks are decompressed when files are read. Chunks are stored uncompressed by default, or if `None` is passed.
+    ///
+    /// Stored chunks are not recompressed, so the compressor must be set before anything is written,
+    /// or, for a loaded file system, be the same it was written with. The compressor is not saved with the file system.
+    pub fn set_compressor(&mut self, compressor: Option<Box<dyn Compressor>>) {
+        self.storage.set_compressor(compressor)
+    }
+
+    /// Returns the total time spent on decompressing chunks while reading files.
+    pub fn decompress_time(&self) -> Duration {
+        self.storage.decompress_time()
+    }
+
+    /// Enables convergent encryption of the written chunks with the given cipher, or disables it if `None`.
+    ///
+    /// Each chunk is encrypted with a key derived from its contents and stored under the hash of the ciphertext,
+    /// so equal chunks are still deduplicated. Keys are kept with the file system metadata and saved along with it,
+    /// while the database only holds the ciphertexts. The cipher itself is not saved,
+    /// so it has to be set again after loading the file system to read encrypted chunks.
+    pub fn set_cipher(&mut self, cipher: Option<Box<dyn ConvergentCipher>>) {
+        self.storage.set_cipher(cipher)
+    }
+
+    /// Returns the total time spent on decrypting chunks while reading files.
+    pub fn decrypt_time(&self) -> Duration {
+        self.storage.decrypt_time()
+    }
+
+    /// Returns the database where chunks are stored, e.g. to get statistics of a
+    /// [`CachedDatabase`][crate::CachedDatabase] or a [`FilteredDatabase`][crate::FilteredDatabase].
+    pub fn database(&self) -> &B {
+        self.storage.database()
+    }
+
+    /// Returns the database where chunks are stored for maintenance, e.g. to [compact][crate::DiskDatabase::compact]
+    /// a [`DiskDatabase`][crate::DiskDatabase] after garbage collection.
+    ///
+    /// Chunks must not be inserted or removed directly, as the file system doesn't track such changes.
+    pub fn database_mut(&mut self) -> &mut B {
+        self.storage.database_mut()
+    }
+
     /// Writes given data to the file. Takes any reader as an input, including slices.
     ///
     /// # Errors
@@ -119,9 +504,8 @@ where
     where
         R: io::Read,
     {
-        if !self.file_exists(handle.name()) {
-            return Err(io::ErrorKind::NotFound.into());
-        }
+        // fails if the file was deleted
+        self.file_layer.path(handle)?;
 
         let Some(chunker) = &mut handle.chunker else {
             let msg = "file handle is read-only";
@@ -131,7 +515,7 @@ where
         let all_spans = self.storage.write_from_stream(reader, chunker)?;
 
         for spans in all_spans {
-            self.file_layer.write(handle, spans);
+            self.file_layer.write(handle, spans)?;
         }
 
         Ok(())
@@ -140,32 +524,127 @@ where
     /// Closes the file and ensures that all data that was written to it is stored.
     /// Returns [WriteMeasurements] containing chunking and hashing times.
     pub fn close_file(&mut self, handle: FileHandle) -> io::Result<WriteMeasurements> {
-        if !self.file_exists(handle.name()) {
-            return Err(io::ErrorKind::NotFound.into());
-        }
+        // fails if the file was deleted
+        self.file_layer.path(&handle)?;
 
         Ok(handle.close())
     }
 
     /// Reads all contents of the file from beginning to end and returns them.
     pub fn read_file_complete(&self, handle: &FileHandle) -> io::Result<Vec<u8>> {
-        let hashes = self.file_layer.read_complete(handle);
-        Ok(self.storage.retrieve(&hashes)?.concat()) // it assumes that all retrieved data segments are in correct order
+        self.read_at(handle, 0, usize::MAX)
     }
 
-    /// Reads at most 1 MB of data from a file and returns it.
+    /// Reads at most 1 MB of data from a file, starting from the handle's position, and returns it.
     ///
-    /// **Careful:** it modifies internal `FileHandle` data. After using this `write_to_file` should not be used on the same FileHandle.
+    /// Moves the handle's position by the amount of data read.
     pub fn read_from_file(&self, handle: &mut FileHandle) -> io::Result<Vec<u8>> {
-        let hashes = self.file_layer.read(handle);
-        Ok(self.storage.retrieve(&hashes)?.concat())
+        let data = self.read_at(handle, handle.offset(), SEG_SIZE)?;
+        self.file_layer
+            .seek(handle, io::SeekFrom::Current(data.len() as i64))?;
+        Ok(data)
+    }
+
+    /// Reads at most `len` bytes of the file, starting at `offset`, and returns them.
+    ///
+    /// Only the chunks that overlap the requested range are retrieved from the storage.
+    /// Doesn't change the handle's position.
+    pub fn read_at(&self, handle: &FileHandle, offset: usize, len: usize) -> io::Result<Vec<u8>> {
+        let mut data = vec![];
+        self.read_into(handle, offset, len, &mut data)?;
+        Ok(data)
+    }
+
+    /// Writes at most `len` bytes of the file, starting at `offset`, into `writer`,
+    /// and returns the number of bytes written.
+    ///
+    /// Chunks are written one by one as they are retrieved from the storage,
+    /// the requested range is never assembled in memory. Doesn't change the handle's position.
+    pub fn read_into<W>(
+        &self,
+        handle: &FileHandle,
+        offset: usize,
+        len: usize,
+        writer: &mut W,
+    ) -> io::Result<usize>
+    where
+        W: io::Write + ?Sized,
+    {
+        let spans = self.file_layer.spans_in_range(handle, offset, len)?;
+        let Some(first) = spans.first() else {
+            return Ok(0);
+        };
+
+        let mut writer = RangeWriter {
+            inner: writer,
+            skip: offset - first.offset(),
+            remaining: len,
+        };
+        self.retrieve_spans_into(spans, &mut writer)?;
+
+        Ok(len - writer.remaining)
+    }
+
+    /// Retrieves contents of the given spans from the storage. Holes are filled with zeros.
+    fn retrieve_spans(&self, spans: &[FileSpan<Hash>]) -> io::Result<Vec<u8>> {
+        let mut data = Vec::with_capacity(spans.iter().map(|span| span.length()).sum());
+        self.retrieve_spans_into(spans, &mut data)?;
+        Ok(data)
+    }
+
+    /// Writes contents of the given spans into `writer`. Holes are filled with zeros.
+    fn retrieve_spans_into<W>(&self, spans: &[FileSpan<Hash>], writer: &mut W) -> io::Result<()>
+    where
+        W: io::Write + ?Sized,
+    {
+        let mut spans = spans.iter();
+        // chunks are retrieved in the order of the spans, holes in between are written before each chunk
+        self.storage
+            .retrieve_with(&span_hashes(spans.as_slice()), |chunk| {
+                write_holes(&mut spans, writer)?;
+                writer.write_all(chunk)
+            })?;
+        write_holes(&mut spans, writer)
+    }
+
+    /// Returns a reader over the file's contents, starting at the handle's position.
+    ///
+    /// The reader implements [`io::Read`], [`io::BufRead`] and [`io::Seek`]. It keeps its own position,
+    /// so the handle's position is not changed.
+    pub fn reader(&self, handle: &FileHandle) -> FileReader<'_, B, Hash, K, T> {
+        FileReader::new(self, handle)
+    }
+
+    /// Returns a writer into the file, implementing [`io::Write`] and [`io::Seek`].
+    ///
+    /// Written data is buffered, it is guaranteed to be stored only after [`flush`][io::Write::flush] is called
+    /// or the writer is dropped.
+    pub fn writer<'a>(&'a mut self, handle: &'a mut FileHandle) -> FileWriter<'a, B, Hash, K, T> {
+        FileWriter::new(self, handle)
+    }
+
+    /// Returns size of the file that corresponds to the handle.
+    fn file_size(&self, handle: &FileHandle) -> io::Result<usize> {
+        self.file_layer.file_size(handle)
+    }
+
+    /// Moves the handle to the given position in the file and returns the new position from the start of the file.
+    ///
+    /// # Errors
+    /// `io::ErrorKind::InvalidInput` - if the resulting position is negative
+    pub fn seek(&self, handle: &mut FileHandle, pos: io::SeekFrom) -> io::Result<u64> {
+        self.file_layer.seek(handle, pos)
     }
 
     /// Gives out a distribution of the chunks with the same hash for the given file.
     ///
     /// Output is a hash map, where the key is the used hash and the value is
     /// count of the same chunks and the length of one of them.
-    pub fn chunk_count_distribution(&self, handle: &FileHandle) -> HashMap<Hash, (u32, usize)> {
+    /// Returns `io::ErrorKind::NotFound` if the file was deleted after the handle was opened.
+    pub fn chunk_count_distribution(
+        &self,
+        handle: &FileHandle,
+    ) -> io::Result<HashMap<Hash, (u32, usize)>> {
         self.file_layer.chunk_count_distribution(handle)
     }
 
@@ -174,31 +653,36 @@ where
     ///
     /// Returns the name of the new file.
     pub fn get_to_dedup_ratio(&mut self, name: &str, dedup_ratio: f64) -> io::Result<String> {
-        self.file_layer.get_to_dedup_ratio(name, dedup_ratio)
+        let new_name = self.file_layer.get_to_dedup_ratio(name, dedup_ratio)?;
+
+        let file = self.file_layer.get(&new_name)?;
+        self.storage.retain(file.hashes(), file.data_size());
+
+        Ok(new_name)
     }
 
     /// Writes a file from the file system to the disk by the specified path.
     ///
     /// Will fail if the file already exists by the specified path.
     pub fn write_file_to_disk<P: AsRef<Path>>(&self, name: &str, path: P) -> io::Result<()> {
-        let mut handle = self.open_file_readonly(name)?;
+        let handle = self.open_file_readonly(name)?;
 
-        let mut file = std::fs::File::options()
+        let file = std::fs::File::options()
             .create_new(true)
             .write(true)
             .open(path)?;
+        let mut file = io::BufWriter::new(file);
 
+        let mut offset = 0;
         loop {
-            let data = self.read_from_file(&mut handle)?;
-
-            if data.is_empty() {
+            let written = self.read_into(&handle, offset, SEG_SIZE, &mut file)?;
+            if written == 0 {
                 break;
             }
-
-            file.write_all(&data)?;
+            offset += written;
         }
 
-        Ok(())
+        file.flush()
     }
 
     /// Returns a list of all file names present in the system.
@@ -212,6 +696,7 @@ where
         Self {
             storage: ChunkStorage::new(base, hasher, target_map),
             file_layer: Default::default(),
+            chunkers: ChunkerRegistry::default(),
         }
     }
 }
@@ -222,6 +707,17 @@ where
     Hash: ChunkHash,
     T: Database<K, Vec<u8>>,
 {
+    /// Removes chunks that are not referenced by any file from the storage.
+    ///
+    /// Target map keys of the removed chunks are removed too, unless other chunks still use them.
+    /// Returns the number of removed chunks.
+    pub fn collect_garbage(&mut self) -> io::Result<usize>
+    where
+        K: std::hash::Hash + Eq,
+    {
+        self.storage.collect_garbage()
+    }
+
     /// Creates a file system with the given [`hasher`][Hasher], original [`database`][Database] and target map, and a [`scrubber`][Scrub].
     ///
     /// Provided `database` must implement [`IterableDatabase`].
@@ -237,6 +733,7 @@ where
         Self {
             storage: ChunkStorage::new_with_scrubber(database, target_map, scrubber, hasher.into()),
             file_layer: Default::default(),
+            chunkers: ChunkerRegistry::default(),
         }
     }
 
@@ -250,23 +747,31 @@ where
 
     /// Calculates deduplication ratio of the storage, not accounting for chunks processed with scrubber,
     /// if there had been any.
-    pub fn cdc_dedup_ratio(&self) -> f64 {
+    pub fn cdc_dedup_ratio(&self) -> io::Result<f64> {
         self.storage.cdc_dedup_ratio()
     }
 
     /// Calculates full deduplication ratio of the storage, not accounting for chunks processed with scrubber,
     /// if there had been any.
-    pub fn full_cdc_dedup_ratio(&self) -> f64 {
+    pub fn full_cdc_dedup_ratio(&self) -> io::Result<f64> {
         self.storage.full_cdc_dedup_ratio()
     }
 
+    /// Calculates compression ratio of the stored chunks, i.e. their total size before compression
+    /// divided by the size they take in the storage. Equals 1 if no compressor is set.
+    pub fn compression_ratio(&self) -> io::Result<f64> {
+        self.storage.compression_ratio()
+    }
+
     /// Returns average chunk size in the storage.
-    pub fn average_chunk_size(&self) -> usize {
+    pub fn average_chunk_size(&self) -> io::Result<usize> {
         self.storage.average_chunk_size()
     }
 
-    /// Returns an immutable iterator over storage chunks.
-    pub fn storage_iterator(&self) -> Box<dyn Iterator<Item = (&Hash, &DataContainer<K>)> + '_> {
+    /// Returns an immutable iterator over copies of storage chunks, yielding an error for every chunk that can't be read.
+    pub fn storage_iterator(
+        &self,
+    ) -> Box<dyn Iterator<Item = io::Result<(&Hash, DataContainer<K>)>> + '_> {
         self.storage.iterator()
     }
 
@@ -280,6 +785,65 @@ where
     }
 }
 
+/// Name of the file where file layer is saved by [`FileSystem::save`].
+const FILE_LAYER_FILE: &str = "files";
+
+impl<B, Hash, K, T> FileSystem<B, Hash, K, T>
+where
+    B: Database<Hash, DataContainer<K>> + Send,
+    Hash: ChunkHash + Send,
+    K: Send,
+    T: Database<K, Vec<u8>>,
+{
+    /// Enables the pipelined write mode with the given settings, or disables it if `None`.
+    ///
+    /// In the pipelined mode, data is chunked on the writing thread, hashed by a pool of workers
+    /// and inserted into the database on another thread. See [`Pipeline`] for details.
+    /// Resulting spans are the same as for serial writes.
+    pub fn set_pipeline(&mut self, pipeline: Option<Pipeline<Hash>>) {
+        self.storage.set_pipeline(pipeline)
+    }
+}
+
+impl<B, Hash, K, T> FileSystem<B, Hash, K, T>
+where
+    B: PersistentDatabase<Hash, DataContainer<K>>,
+    Hash: ChunkHash + Encode + Decode<()>,
+    K: Encode + Decode<()>,
+    T: PersistentDatabase<K, Vec<u8>>,
+{
+    /// Saves the file system to the given directory, creating it if necessary.
+    ///
+    /// Saves file metadata, storage counters, the database and the target map, so that the file system
+    /// can be restored with [`load`][Self::load] without writing the data again. Open file handles are not saved.
+    pub fn save<P: AsRef<Path>>(&self, dir: P) -> io::Result<()> {
+        let dir = dir.as_ref();
+        std::fs::create_dir_all(dir)?;
+
+        self.storage.save(dir)?;
+        encode_to_file(&self.file_layer, &dir.join(FILE_LAYER_FILE))
+    }
+
+    /// Loads a file system previously saved to the given directory by [`save`][Self::save].
+    ///
+    /// Given `hasher` must be the same as the one used by the saved file system.
+    /// Files are reopened for writing with their chunkers from `chunker_registry` by [`reopen_file`][Self::reopen_file].
+    /// Loaded file system has no scrubber.
+    pub fn load<P, H>(dir: P, hasher: H, chunker_registry: ChunkerRegistry) -> io::Result<Self>
+    where
+        P: AsRef<Path>,
+        H: Into<Box<dyn Hasher<Hash = Hash> + 'static>>,
+    {
+        let dir = dir.as_ref();
+
+        Ok(Self {
+            storage: ChunkStorage::load(dir, hasher.into())?,
+            file_layer: decode_from_file(&dir.join(FILE_LAYER_FILE))?,
+            chunkers: chunker_registry,
+        })
+    }
+}
+
 impl<B, Hash, K, T> FileSystem<B, Hash, K, T>
 where
     Hash: ChunkHash,
@@ -288,7 +852,7 @@ where
 {
     /// Calculates total deduplication ratio of the storage,
     /// accounting for chunks both unprocessed and processed with scrubber.
-    pub fn total_dedup_ratio(&self) -> f64 {
+    pub fn total_dedup_ratio(&self) -> io::Result<f64> {
         self.storage.total_dedup_ratio()
     }
 
@@ -298,3 +862,73 @@ where
         self.storage.clear_database_full()
     }
 }
+
+/// Returns hashes of the spans that are not holes.
+fn span_hashes<Hash: ChunkHash>(spans: &[FileSpan<Hash>]) -> Vec<Hash> {
+    spans
+        .iter()
+        .filter_map(|span| span.hash().cloned())
+        .collect()
+}
+
+/// Writes zeros for the holes at the start of `spans`, up to and including the first span that is not a hole.
+fn write_holes<'a, Hash, W>(
+    spans: &mut impl Iterator<Item = &'a FileSpan<Hash>>,
+    writer: &mut W,
+) -> io::Result<()>
+where
+    Hash: ChunkHash + 'a,
+    W: io::Write + ?Sized,
+{
+    for span in spans {
+        if span.hash().is_some() {
+            break;
+        }
+        io::copy(&mut io::repeat(0).take(span.length() as u64), writer)?;
+    }
+    Ok(())
+}
+
+/// Writer that skips the first `skip` bytes written to it and passes at most `remaining` bytes after them to `inner`.
+///
+/// All data is reported as written, so that the contents outside of the range are silently dropped.
+struct RangeWriter<'a, W: ?Sized> {
+    inner: &'a mut W,
+    skip: usize,
+    remaining: usize,
+}
+
+impl<W: io::Write + ?Sized> io::Write for RangeWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let skipped = self.skip.min(buf.len());
+        self.skip -= skipped;
+
+        let data = &buf[skipped..];
+        let data = &data[..data.len().min(self.remaining)];
+        self.inner.write_all(data)?;
+        self.remaining -= data.len();
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Concatenates contents of the spans, filling holes with zeros.
+///
+/// `chunks` must contain data of the spans that are not holes, in the same order.
+fn assemble_spans<Hash: ChunkHash>(spans: &[FileSpan<Hash>], chunks: Vec<Vec<u8>>) -> Vec<u8> {
+    let mut chunks = chunks.into_iter();
+
+    let total_length = spans.iter().map(|span| span.length()).sum();
+    let mut data = Vec::with_capacity(total_length);
+    for span in spans {
+        match span.hash() {
+            Some(_) => data.extend_from_slice(&chunks.next().unwrap()),
+            None => data.resize(data.len() + span.length(), 0),
+        }
+    }
+    data
+}
@@ -0,0 +1,225 @@
+use std::io;
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use super::chunk_stream::{ChunkStream, ChunkedSegment};
+use super::database::Database;
+use super::storage::{ChunkHasher, ChunkInserter, DataContainer, HashedSegment, SpansInfo};
+use crate::{ChunkHash, ChunkerRef, Hasher, WriteMeasurements, SEG_SIZE};
+
+/// Creates a new hasher, so that data can be hashed on several threads at once.
+pub(crate) type HasherFactory<Hash> = Box<dyn Fn() -> Box<dyn Hasher<Hash = Hash>> + Send + Sync>;
+
+/// Function that writes data in the pipelined mode.
+///
+/// Stored instead of calling [`write_pipelined`] directly,
+/// so that the storage doesn't need thread-safety bounds unless the pipelined mode is enabled.
+pub(crate) type PipelinedWrite<Hash, B> = fn(
+    &Pipeline<Hash>,
+    &mut dyn io::Read,
+    &ChunkerRef,
+    &mut B,
+    ChunkHasher,
+    ChunkInserter,
+) -> io::Result<Vec<SpansInfo<Hash>>>;
+
+/// Settings of the pipelined write mode.
+///
+/// In this mode, data is chunked on the writing thread, chunks are hashed by a pool of workers,
+/// and hashed chunks are inserted into the database in batches, one segment at a time, on a separate thread.
+/// Time measurements of each stage are reported the same way as for serial writes,
+/// hashing time is summed over all workers.
+pub struct Pipeline<Hash: ChunkHash> {
+    hasher: HasherFactory<Hash>,
+    workers: usize,
+}
+
+impl<Hash: ChunkHash> Pipeline<Hash> {
+    /// Creates pipeline settings with the given number of hashing workers.
+    ///
+    /// `hasher` is called once per worker to create its hasher, e.g. `Sha256Hasher::default`.
+    ///
+    /// # Panics
+    /// Panics if `workers` is 0.
+    pub fn new<F, H>(workers: usize, hasher: F) -> Self
+    where
+        F: Fn() -> H + Send + Sync + 'static,
+        H: Into<Box<dyn Hasher<Hash = Hash>>>,
+    {
+        assert!(workers > 0, "pipeline needs at least one hashing worker");
+
+        Self {
+            hasher: Box::new(move || hasher().into()),
+            workers,
+        }
+    }
+
+    /// Returns the number of hashing workers.
+    pub fn workers(&self) -> usize {
+        self.workers
+    }
+}
+
+/// Segment split into chunks, waiting to be hashed.
+struct Chunked {
+    index: usize,
+    data: Vec<u8>,
+    segment: ChunkedSegment,
+}
+
+/// Hashed segment, waiting to be inserted into the database.
+struct Hashed<Hash: ChunkHash, K> {
+    index: usize,
+    segment: io::Result<HashedSegment<Hash, K>>,
+    chunk_time: Duration,
+}
+
+/// Writes all data from the reader to the database in the pipelined mode, see [`Pipeline`].
+///
+/// Returns spans of all segments in the order of the data. Spans are not referenced.
+pub(crate) fn write_pipelined<Hash, K, B>(
+    pipeline: &Pipeline<Hash>,
+    reader: &mut dyn io::Read,
+    chunker: &ChunkerRef,
+    base: &mut B,
+    hashing: ChunkHasher,
+    inserter: ChunkInserter,
+) -> io::Result<Vec<SpansInfo<Hash>>>
+where
+    Hash: ChunkHash + Send,
+    K: Send,
+    B: Database<Hash, DataContainer<K>> + Send,
+{
+    // bounded channels keep only a few segments in memory
+    let (chunked_tx, chunked_rx) = mpsc::sync_channel::<Chunked>(pipeline.workers * 2);
+    let (hashed_tx, hashed_rx) = mpsc::sync_channel::<Hashed<Hash, K>>(pipeline.workers * 2);
+    // shared by the workers, it's dropped once all of them stop, so that the chunking stage doesn't block
+    let chunked_rx = Arc::new(Mutex::new(chunked_rx));
+
+    thread::scope(|s| {
+        for _ in 0..pipeline.workers {
+            let chunked_rx = Arc::clone(&chunked_rx);
+            let hashed_tx = hashed_tx.clone();
+            s.spawn(move || hash_segments(pipeline, &chunked_rx, hashed_tx, hashing));
+        }
+        drop((chunked_rx, hashed_tx));
+
+        let inserting = s.spawn(move || insert_segments(hashed_rx, base, inserter));
+
+        let chunked = chunk_segments(reader, chunker, |segment| {
+            // fails only if a later stage has stopped because of an error
+            chunked_tx.send(segment).is_ok()
+        });
+        drop(chunked_tx);
+
+        let inserted = inserting.join().unwrap();
+        chunked?;
+        inserted
+    })
+}
+
+/// Reads the data segment by segment and splits it into chunks, passing segments to `send`.
+/// Stops early if `send` returns `false`.
+fn chunk_segments<F>(reader: &mut dyn io::Read, chunker: &ChunkerRef, mut send: F) -> io::Result<()>
+where
+    F: FnMut(Chunked) -> bool,
+{
+    let mut stream = ChunkStream::new(chunker);
+    let mut index = 0;
+
+    loop {
+        // every segment is read into a new buffer, as it's passed to the workers along with its chunks
+        let mut data = vec![0u8; SEG_SIZE];
+        let n = reader.read(&mut data)?;
+        if n == 0 {
+            break;
+        }
+        data.truncate(n);
+
+        let segment = stream.next(&data);
+        if segment.is_empty() {
+            continue;
+        }
+
+        if !send(Chunked {
+            index,
+            data,
+            segment,
+        }) {
+            return Ok(());
+        }
+        index += 1;
+    }
+
+    let last = stream.finish();
+    if !last.is_empty() {
+        send(Chunked {
+            index,
+            data: vec![],
+            segment: last,
+        });
+    }
+
+    Ok(())
+}
+
+/// Hashes chunked segments until there are no more of them, or the insertion stage stops.
+fn hash_segments<Hash: ChunkHash, K>(
+    pipeline: &Pipeline<Hash>,
+    chunked_rx: &Mutex<Receiver<Chunked>>,
+    hashed_tx: mpsc::SyncSender<Hashed<Hash, K>>,
+    hashing: ChunkHasher,
+) {
+    let mut hasher = (pipeline.hasher)();
+
+    loop {
+        let received = chunked_rx.lock().unwrap().recv();
+        let Ok(segment) = received else {
+            break;
+        };
+
+        let chunks = segment.segment.chunk_data(&segment.data);
+        let hashed = Hashed {
+            index: segment.index,
+            segment: hashing.hash(&mut *hasher, &chunks),
+            chunk_time: segment.segment.chunk_time,
+        };
+        if hashed_tx.send(hashed).is_err() {
+            break;
+        }
+    }
+}
+
+/// Inserts hashed segments into the database and returns their spans, ordered as the segments were read.
+fn insert_segments<Hash: ChunkHash, K, B>(
+    hashed_rx: Receiver<Hashed<Hash, K>>,
+    base: &mut B,
+    inserter: ChunkInserter,
+) -> io::Result<Vec<SpansInfo<Hash>>>
+where
+    B: Database<Hash, DataContainer<K>>,
+{
+    let mut all_spans = vec![];
+
+    for hashed in hashed_rx {
+        let segment = hashed.segment?;
+        let inserted = inserter.insert(base, segment.pairs)?;
+
+        let mut info = SpansInfo::new(segment.spans);
+        info.measurements =
+            WriteMeasurements::new(Duration::default(), hashed.chunk_time, Duration::default())
+                + segment.measurements
+                + inserted;
+        info.keys = segment.keys;
+        all_spans.push((hashed.index, info));
+    }
+
+    all_spans.sort_by_key(|(index, _)| *index);
+    Ok(all_spans
+        .into_iter()
+        .map(|(_, info)| info)
+        .filter(|info| !info.spans.is_empty())
+        .collect())
+}
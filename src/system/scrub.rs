@@ -13,7 +13,7 @@ use super::storage::DataContainer;
 /// The `database` stores [DataContainers][DataContainer], which are either a CDC chunk, that is, a `Vec<u8>`,
 /// or a collection of target keys, using which the original chunk could be restored.
 ///
-/// The basic idea behind the scrubber is that it takes chunks from `database` via an iterator and
+/// The basic idea behind the scrubber is that it takes chunks from `database` via [IterableDatabase::for_each_mut] and
 /// processes them, e.g., splits, or simply transfers them to the `target_map`, leaving only a collection of `Keys` in the initial [DataContainer].
 ///
 /// After moving the data from `database` to `target_map`, we should be able to have access to it via the `database`.
@@ -35,8 +35,8 @@ where
     T: Database<Key, Vec<u8>>,
 {
     /// # How to implement
-    /// To iterate over the underlying chunks, `database.iterator_mut()` should be used.
-    /// It will automatically yield pairs, which consist of `&Hash` and `&mut DataContainer`. To access the underlying data in the container,
+    /// To iterate over the underlying chunks, `database.for_each_mut()` should be used.
+    /// It calls the given closure on pairs, which consist of `&Hash` and `&mut DataContainer`. To access the underlying data in the container,
     /// [DataContainer::extract] or [DataContainer::extract_mut] should be used.
     ///
     /// If the chunk is suitable for being transferred to the `target_map`, it should NOT be deleted, but instead be replaced by the `target_map`'s keys,
@@ -55,7 +55,7 @@ where
     ///
     /// # CDC Database
     /// We should be able to iterate over the `database` to process all chunks we had stored before.
-    /// The [IntoIterator] trait should be implemented for `database`, but it should not be a big concern, because the only structure that should be implemented
+    /// The [IterableDatabase] trait should be implemented for `database`, but it should not be a big concern, because the only structure that should be implemented
     /// for the algorithm is the scrubber itself. `database` should be considered a given entity, along with the `target_map`.
     fn scrub<'a>(&mut self, database: &mut B, target_map: &mut T) -> io::Result<ScrubMeasurements>
     where
@@ -94,7 +94,7 @@ where
     {
         let now = Instant::now();
         let mut processed_data = 0;
-        for (hash, container) in database.iterator_mut() {
+        database.for_each_mut(&mut |hash, container| {
             match container.extract() {
                 Data::Chunk(chunk) => {
                     target.insert(hash.clone(), chunk.clone())?;
@@ -103,7 +103,8 @@ where
                 Data::TargetChunk(_) => (),
             }
             container.make_target(vec![hash.clone()]);
-        }
+            Ok(())
+        })?;
         let running_time = now.elapsed();
         Ok(ScrubMeasurements {
             processed_data,
@@ -0,0 +1,122 @@
+use std::hash::{BuildHasher, Hash, RandomState};
+use std::io;
+use std::sync::RwLock;
+
+use super::database::{ConcurrentDatabase, Database};
+
+/// Database that splits keys between several shards, each of them guarded by its own lock.
+///
+/// Shard of a key is chosen by its hash, so writers inserting different keys rarely block each other.
+pub struct ShardedDatabase<D> {
+    shards: Vec<RwLock<D>>,
+    state: RandomState,
+}
+
+impl<D> ShardedDatabase<D> {
+    /// Creates a database that distributes keys between the given shards.
+    ///
+    /// # Panics
+    /// Panics if `shards` is empty.
+    pub fn new(shards: Vec<D>) -> Self {
+        assert!(
+            !shards.is_empty(),
+            "sharded database needs at least one shard"
+        );
+
+        Self {
+            shards: shards.into_iter().map(RwLock::new).collect(),
+            state: RandomState::new(),
+        }
+    }
+
+    /// Returns the number of shards.
+    pub fn shard_count(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Consumes the database and returns its shards.
+    pub fn into_shards(self) -> Vec<D> {
+        self.shards
+            .into_iter()
+            .map(|shard| shard.into_inner().unwrap())
+            .collect()
+    }
+
+    fn shard_index<K: Hash>(&self, key: &K) -> usize {
+        (self.state.hash_one(key) % self.shards.len() as u64) as usize
+    }
+
+    fn shard<K: Hash>(&self, key: &K) -> &RwLock<D> {
+        &self.shards[self.shard_index(key)]
+    }
+}
+
+impl<K, V, D> ConcurrentDatabase<K, V> for ShardedDatabase<D>
+where
+    K: Hash,
+    D: Database<K, V> + Send + Sync,
+{
+    fn insert(&self, key: K, value: V) -> io::Result<()> {
+        self.shard(&key).write().unwrap().insert(key, value)
+    }
+
+    fn get(&self, key: &K) -> io::Result<V> {
+        self.shard(key).read().unwrap().get(key)
+    }
+
+    /// Groups the pairs by shards, so that each shard is locked only once.
+    fn insert_multi(&self, pairs: Vec<(K, V)>) -> io::Result<()> {
+        let mut groups = self.shards.iter().map(|_| vec![]).collect::<Vec<_>>();
+        for (key, value) in pairs {
+            groups[self.shard_index(&key)].push((key, value));
+        }
+
+        for (shard, group) in self.shards.iter().zip(groups) {
+            if !group.is_empty() {
+                shard.write().unwrap().insert_multi(group)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn contains(&self, key: &K) -> bool {
+        self.shard(key).read().unwrap().contains(key)
+    }
+
+    fn remove(&self, key: &K) -> io::Result<()> {
+        self.shard(key).write().unwrap().remove(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::thread;
+
+    use super::ShardedDatabase;
+    use crate::system::database::ConcurrentDatabase;
+
+    #[test]
+    fn sharded_database_accepts_concurrent_inserts() {
+        let db = ShardedDatabase::new(vec![HashMap::<u64, u64>::new(); 4]);
+
+        thread::scope(|s| {
+            for t in 0..4 {
+                let db = &db;
+                s.spawn(move || {
+                    let pairs = (0..1000).map(|i| (t * 1000 + i, i)).collect();
+                    db.insert_multi(pairs).unwrap();
+                });
+            }
+        });
+
+        assert_eq!(db.get(&3999).unwrap(), 999);
+        assert!(db.contains(&0));
+        db.remove(&0).unwrap();
+        assert!(!db.contains(&0));
+
+        let shards = db.into_shards();
+        assert_eq!(shards.iter().map(HashMap::len).sum::<usize>(), 3999);
+        assert!(shards.iter().all(|shard| !shard.is_empty()));
+    }
+}
@@ -0,0 +1,305 @@
+use std::collections::HashMap;
+use std::io;
+use std::sync::{Arc, Mutex, RwLock};
+
+use super::database::{ConcurrentDatabase, Database};
+use super::file_layer::{FileHandle, FileLayer, FileMetadata};
+use super::pipeline::HasherFactory;
+use super::storage::{
+    ChunkHasher, ChunkInserter, DataContainer, References, SpansInfo, StorageWriter,
+};
+use super::{assemble_spans, span_hashes};
+use crate::{ChunkHash, ChunkerRef, Hasher, WriteMeasurements};
+
+/// A file system that can be shared between threads, so that several files are written at once.
+///
+/// All methods take `&self`. Chunking and hashing are done without holding any locks,
+/// each write uses its own hasher, created by the factory given to [`new`][Self::new].
+/// Chunks are inserted into a [`ConcurrentDatabase`], so inserts from different writers can run in parallel too.
+///
+/// Writes to the same file are serialized by a per-file lock, writes to different files don't block each other.
+/// The file layer is locked only to append spans of already stored data.
+/// Garbage collection waits for the writes in progress and blocks new ones until it's done.
+///
+/// Only CDC is supported, chunks can't be scrubbed.
+pub struct SharedFileSystem<B, Hash>
+where
+    B: ConcurrentDatabase<Hash, DataContainer<()>>,
+    Hash: ChunkHash,
+{
+    database: B,
+    hasher: HasherFactory<Hash>,
+    file_layer: RwLock<FileLayer<Hash>>,
+    references: Mutex<References<Hash>>,
+    file_locks: Mutex<HashMap<u64, Arc<Mutex<()>>>>,
+    // held for reading by writes, so that garbage collection doesn't remove chunks they deduplicated against
+    gc_lock: RwLock<()>,
+}
+
+/// Allows a [`ConcurrentDatabase`] to be used by [`StorageWriter`], which expects a [`Database`].
+struct Concurrent<'a, D>(&'a D);
+
+impl<K, V, D: ConcurrentDatabase<K, V>> Database<K, V> for Concurrent<'_, D> {
+    fn insert(&mut self, key: K, value: V) -> io::Result<()> {
+        self.0.insert(key, value)
+    }
+
+    fn get(&self, key: &K) -> io::Result<V> {
+        self.0.get(key)
+    }
+
+    fn insert_multi(&mut self, pairs: Vec<(K, V)>) -> io::Result<()> {
+        self.0.insert_multi(pairs)
+    }
+
+    fn contains(&self, key: &K) -> bool {
+        self.0.contains(key)
+    }
+
+    fn remove(&mut self, key: &K) -> io::Result<()> {
+        self.0.remove(key)
+    }
+}
+
+impl<B, Hash> SharedFileSystem<B, Hash>
+where
+    B: ConcurrentDatabase<Hash, DataContainer<()>>,
+    Hash: ChunkHash,
+{
+    /// Creates a file system over the given database.
+    ///
+    /// `hasher` is called to create a new hasher for every write, e.g. `Sha256Hasher::default`.
+    pub fn new<F, H>(database: B, hasher: F) -> Self
+    where
+        F: Fn() -> H + Send + Sync + 'static,
+        H: Into<Box<dyn Hasher<Hash = Hash>>>,
+    {
+        Self {
+            database,
+            hasher: Box::new(move || hasher().into()),
+            file_layer: RwLock::default(),
+            references: Mutex::default(),
+            file_locks: Mutex::default(),
+            gc_lock: RwLock::default(),
+        }
+    }
+
+    /// Checks if the file with the given `name` exists.
+    pub fn file_exists(&self, name: &str) -> bool {
+        self.file_layer.read().unwrap().file_exists(name)
+    }
+
+    /// Returns a list of all file names present in the system.
+    pub fn list_files(&self) -> Vec<String> {
+        self.file_layer.read().unwrap().list_files()
+    }
+
+    /// Returns metadata of the file with the given `name`.
+    pub fn metadata(&self, name: &str) -> io::Result<FileMetadata> {
+        self.file_layer.read().unwrap().metadata(name)
+    }
+
+    /// Creates a directory together with all of its missing parents.
+    pub fn create_dir_all(&self, path: &str) -> io::Result<()> {
+        self.file_layer.write().unwrap().create_dir_all(path)
+    }
+
+    /// Creates a file with the given name and returns its `FileHandle`.
+    ///
+    /// If the file with the same name exists, it is replaced, as if it was deleted right before the creation.
+    /// Handles of the replaced file become invalid, writes to it that are in progress fail.
+    pub fn create_file<S, C>(&self, name: S, chunker: C) -> io::Result<FileHandle>
+    where
+        S: Into<String>,
+        C: Into<ChunkerRef>,
+    {
+        let name = name.into();
+        let mut file_layer = self.file_layer.write().unwrap();
+        let replaced = match file_layer.file_exists(&name) {
+            true => Some(file_layer.delete(&name)?),
+            false => None,
+        };
+        let handle = file_layer.create(name, chunker.into(), true)?;
+
+        if let Some(replaced) = replaced {
+            self.file_locks.lock().unwrap().remove(&replaced.id());
+            self.references
+                .lock()
+                .unwrap()
+                .release(replaced.hashes(), replaced.data_size());
+        }
+        Ok(handle)
+    }
+
+    /// Tries to open a file with the given name and returns its `FileHandle` if it exists.
+    pub fn open_file<S, C>(&self, name: S, chunker: C) -> io::Result<FileHandle>
+    where
+        S: AsRef<str>,
+        C: Into<ChunkerRef>,
+    {
+        self.file_layer
+            .read()
+            .unwrap()
+            .open(name.as_ref(), chunker.into())
+    }
+
+    pub fn open_file_readonly<S>(&self, name: S) -> io::Result<FileHandle>
+    where
+        S: AsRef<str>,
+    {
+        self.file_layer.read().unwrap().open_readonly(name.as_ref())
+    }
+
+    /// Deletes the file with the given name. Waits for the writes to the file that are in progress.
+    ///
+    /// Chunks of the file are not removed from the database.
+    pub fn delete_file(&self, name: &str) -> io::Result<()> {
+        let handle = self.open_file_readonly(name)?;
+        let lock = self.file_lock(&handle);
+        let _guard = lock.lock().unwrap();
+
+        let mut file_layer = self.file_layer.write().unwrap();
+        // the file could have been deleted while the lock was awaited
+        let path = file_layer.path(&handle)?.to_string();
+        let file = file_layer.delete(&path)?;
+        self.file_locks.lock().unwrap().remove(&handle.file_id());
+        drop(file_layer);
+
+        self.references
+            .lock()
+            .unwrap()
+            .release(file.hashes(), file.data_size());
+        Ok(())
+    }
+
+    /// Writes given data to the end of the file.
+    ///
+    /// # Errors
+    /// * `io::ErrorKind::NotFound` - if the file was deleted
+    /// * `io::ErrorKind::PermissionDenied` - if the handle was opened in read-only mode
+    pub fn write_to_file(&self, handle: &mut FileHandle, data: &[u8]) -> io::Result<()> {
+        self.write_with(handle, |writer, database| writer.write_all(data, database))
+    }
+
+    /// Writes all data from the reader to the end of the file.
+    ///
+    /// # Errors
+    /// * `io::ErrorKind::NotFound` - if the file was deleted
+    /// * `io::ErrorKind::PermissionDenied` - if the handle was opened in read-only mode
+    pub fn write_from_stream<R>(&self, handle: &mut FileHandle, reader: R) -> io::Result<()>
+    where
+        R: io::Read,
+    {
+        self.write_with(handle, |writer, database| {
+            writer.write_stream(reader, database)
+        })
+    }
+
+    /// Stores data with the given function while holding the file's lock, then appends resulting spans to the file.
+    fn write_with<F>(&self, handle: &mut FileHandle, write: F) -> io::Result<()>
+    where
+        F: FnOnce(StorageWriter<Hash>, &mut Concurrent<B>) -> io::Result<Vec<SpansInfo<Hash>>>,
+    {
+        let _gc_guard = self.gc_lock.read().unwrap();
+        let lock = self.file_lock(handle);
+        let _guard = lock.lock().unwrap();
+        self.file_layer.read().unwrap().path(handle)?;
+
+        let Some(chunker) = &handle.chunker else {
+            let msg = "file handle is read-only";
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied, msg));
+        };
+
+        let mut hasher = (self.hasher)();
+        let writer = StorageWriter::new(
+            chunker,
+            &mut hasher,
+            ChunkHasher::default(),
+            ChunkInserter::default(),
+        );
+        let all_spans = write(writer, &mut Concurrent(&self.database))?;
+
+        let mut file_layer = self.file_layer.write().unwrap();
+        // the file could have been replaced while the data was stored
+        file_layer.path(handle)?;
+        self.references.lock().unwrap().reference(&all_spans);
+        for spans in all_spans {
+            file_layer.write(handle, spans)?;
+        }
+
+        Ok(())
+    }
+
+    /// Closes the file and returns [WriteMeasurements] containing chunking and hashing times.
+    pub fn close_file(&self, handle: FileHandle) -> io::Result<WriteMeasurements> {
+        self.file_layer.read().unwrap().path(&handle)?;
+        Ok(handle.close())
+    }
+
+    /// Reads all contents of the file from beginning to end and returns them.
+    pub fn read_file_complete(&self, handle: &FileHandle) -> io::Result<Vec<u8>> {
+        self.read_at(handle, 0, usize::MAX)
+    }
+
+    /// Reads up to `len` bytes of the file starting at `offset`. Doesn't change the handle's position.
+    ///
+    /// The file layer is locked only while the spans are looked up, chunks are retrieved without holding it.
+    pub fn read_at(&self, handle: &FileHandle, offset: usize, len: usize) -> io::Result<Vec<u8>> {
+        let spans = self
+            .file_layer
+            .read()
+            .unwrap()
+            .spans_in_range(handle, offset, len)?
+            .to_vec();
+        let Some(first) = spans.first() else {
+            return Ok(vec![]);
+        };
+
+        let chunks = self
+            .database
+            .get_multi(&span_hashes(&spans))?
+            .into_iter()
+            .map(|container| {
+                container.into_chunk().ok_or_else(|| {
+                    let msg = "scrubbed chunks are not supported by shared file system";
+                    io::Error::new(io::ErrorKind::InvalidData, msg)
+                })
+            })
+            .collect::<io::Result<_>>()?;
+        let mut data = assemble_spans(&spans, chunks);
+
+        let start = offset - first.offset();
+        data.truncate(start.saturating_add(len));
+        data.drain(..start);
+
+        Ok(data)
+    }
+
+    /// Returns the total length of the data written to the files that are present in the system.
+    pub fn size_written(&self) -> usize {
+        self.references.lock().unwrap().size_written()
+    }
+
+    /// Removes chunks that are not referenced by any file from the database.
+    ///
+    /// Waits for the writes in progress to finish, new writes wait for the collection.
+    /// Returns the number of removed chunks.
+    pub fn collect_garbage(&self) -> io::Result<usize> {
+        let _gc_guard = self.gc_lock.write().unwrap();
+        let mut references = self.references.lock().unwrap();
+        let garbage = references.unreferenced();
+
+        for hash in &garbage {
+            self.database.remove(hash)?;
+            references.forget(hash);
+        }
+
+        Ok(garbage.len())
+    }
+
+    /// Returns the lock of the file the handle belongs to.
+    fn file_lock(&self, handle: &FileHandle) -> Arc<Mutex<()>> {
+        let mut locks = self.file_locks.lock().unwrap();
+        locks.entry(handle.file_id()).or_default().clone()
+    }
+}
@@ -1,14 +1,31 @@
-use crate::{ChunkHash, Hasher, SEG_SIZE};
+use crate::{ChunkHash, ChunkKey, Compressor, ConvergentCipher, Hasher, SEG_SIZE};
 use crate::{ChunkerRef, WriteMeasurements};
 use bincode::{Decode, Encode};
+use std::borrow::Cow;
 use std::cmp::min;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
 use std::fmt::Formatter;
 use std::io;
+use std::path::Path;
+use std::sync::Mutex;
 use std::time::{Duration, Instant};
 
-use super::database::{Database, IterableDatabase};
+use super::chunk_stream::ChunkStream;
+use super::database::{decode_from_file, encode_to_file};
+use super::database::{Database, IterableDatabase, PersistentDatabase};
+use super::pipeline::{write_pipelined, Pipeline, PipelinedWrite};
 use super::scrub::{Scrub, ScrubMeasurements};
 
+/// Name of the file where storage counters are saved.
+const COUNTERS_FILE: &str = "storage";
+/// Name of the file where the database is saved.
+const DATABASE_FILE: &str = "database";
+/// Name of the file where the target map is saved.
+const TARGET_MAP_FILE: &str = "target_map";
+/// Maximal number of chunks fetched from the database at once by [`ChunkStorage::retrieve_with`].
+const RETRIEVE_BATCH: usize = 16;
+
 /// Container for storage data.
 #[derive(Clone, Debug, Default, Encode, Decode)]
 pub struct DataContainer<K>(Data<K>);
@@ -21,9 +38,11 @@ pub enum Data<K> {
 }
 
 /// Hashed span in a [`file`][crate::file_layer::File] with a certain length.
+///
+/// Span without a hash is a hole, no chunk is stored for it.
 #[derive(Debug)]
 pub struct Span<Hash: ChunkHash> {
-    pub hash: Hash,
+    pub hash: Option<Hash>,
     pub length: usize,
 }
 
@@ -33,11 +52,118 @@ pub struct SpansInfo<Hash: ChunkHash> {
     pub spans: Vec<Span<Hash>>,
     pub measurements: WriteMeasurements,
     total_length: usize,
+    /// Keys of the encrypted chunks among the spans.
+    pub(crate) keys: Vec<(Hash, ChunkKey)>,
 }
 
 impl<Hash: ChunkHash> Span<Hash> {
     pub fn new(hash: Hash, length: usize) -> Self {
-        Self { hash, length }
+        Self {
+            hash: Some(hash),
+            length,
+        }
+    }
+
+    /// Creates a hole of the given length.
+    pub fn hole(length: usize) -> Self {
+        Self { hash: None, length }
+    }
+}
+
+impl<Hash: ChunkHash> SpansInfo<Hash> {
+    /// Creates spans info without any time measurements.
+    pub fn new(spans: Vec<Span<Hash>>) -> Self {
+        let total_length = spans.iter().map(|span| span.length).sum();
+        Self {
+            spans,
+            measurements: WriteMeasurements::default(),
+            total_length,
+            keys: vec![],
+        }
+    }
+}
+
+/// Hashed chunks, ready to be inserted into the database.
+pub(crate) type HashedChunks<Hash, K> = Vec<(Hash, DataContainer<K>)>;
+
+/// Number of file spans referencing each stored chunk, along with the total length of referenced data.
+///
+/// Also keeps the keys of encrypted chunks, as they are needed to read the chunks back.
+#[derive(Default, Encode, Decode)]
+pub(crate) struct References<Hash: ChunkHash> {
+    counts: HashMap<Hash, usize>,
+    size_written: usize,
+    keys: HashMap<Hash, ChunkKey>,
+}
+
+impl<Hash: ChunkHash> References<Hash> {
+    /// Increments reference counts for all chunks contained in the written spans
+    /// and counts their length towards written size. Holes are not counted.
+    pub(crate) fn reference(&mut self, all_spans: &[SpansInfo<Hash>]) {
+        for span in all_spans.iter().flat_map(|info| info.spans.iter()) {
+            if let Some(hash) = &span.hash {
+                *self.counts.entry(hash.clone()).or_default() += 1;
+                self.size_written += span.length;
+            }
+        }
+
+        for (hash, key) in all_spans.iter().flat_map(|info| info.keys.iter()) {
+            self.keys.insert(hash.clone(), *key);
+        }
+    }
+
+    /// Returns the key of the chunk, if the chunk is encrypted.
+    pub(crate) fn key(&self, hash: &Hash) -> Option<&ChunkKey> {
+        self.keys.get(hash)
+    }
+
+    pub(crate) fn retain<'a, I>(&mut self, hashes: I, size: usize)
+    where
+        I: IntoIterator<Item = &'a Hash>,
+        Hash: 'a,
+    {
+        for hash in hashes {
+            *self.counts.entry(hash.clone()).or_default() += 1;
+        }
+        self.size_written += size;
+    }
+
+    pub(crate) fn release<'a, I>(&mut self, hashes: I, size: usize)
+    where
+        I: IntoIterator<Item = &'a Hash>,
+        Hash: 'a,
+    {
+        for hash in hashes {
+            if let Some(count) = self.counts.get_mut(hash) {
+                *count = count.saturating_sub(1);
+            }
+        }
+        self.size_written = self.size_written.saturating_sub(size);
+    }
+
+    /// Returns hashes of the chunks that are not referenced anymore.
+    pub(crate) fn unreferenced(&self) -> Vec<Hash> {
+        self.counts
+            .iter()
+            .filter(|(_, &count)| count == 0)
+            .map(|(hash, _)| hash.clone())
+            .collect()
+    }
+
+    /// Stops tracking the chunk, e.g. after it was removed from the database.
+    pub(crate) fn forget(&mut self, hash: &Hash) {
+        self.counts.remove(hash);
+        self.keys.remove(hash);
+    }
+
+    pub(crate) fn size_written(&self) -> usize {
+        self.size_written
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.counts.clear();
+        self.size_written = 0;
+        self.keys.clear();
     }
 }
 
@@ -52,7 +178,21 @@ where
     scrubber: Option<Box<dyn Scrub<Hash, B, K, T>>>,
     target_map: T,
     hasher: Box<dyn Hasher<Hash = Hash>>,
-    size_written: usize,
+    references: References<Hash>,
+    /// Minimal length of an all-zero chunk to be stored as a hole. Hole detection is disabled if `None`.
+    zero_chunk_threshold: Option<usize>,
+    /// What to do when a chunk collides with a stored one. Collisions are not checked if `None`.
+    collision_policy: Option<CollisionPolicy>,
+    /// Compressor applied to the stored chunks. Chunks are stored as they are if `None`.
+    compressor: Option<Box<dyn Compressor>>,
+    /// Total time spent on decompressing retrieved chunks.
+    decompress_time: Mutex<Duration>,
+    /// Cipher used for convergent encryption of the stored chunks. Chunks are not encrypted if `None`.
+    cipher: Option<Box<dyn ConvergentCipher>>,
+    /// Total time spent on decrypting retrieved chunks.
+    decrypt_time: Mutex<Duration>,
+    /// Settings of the pipelined write mode along with the function that implements it, if the mode is enabled.
+    pipeline: Option<(Pipeline<Hash>, PipelinedWrite<Hash, B>)>,
 }
 
 impl<Hash, B, K, T> ChunkStorage<Hash, B, K, T>
@@ -67,7 +207,14 @@ where
             scrubber: None,
             target_map,
             hasher,
-            size_written: 0,
+            references: References::default(),
+            zero_chunk_threshold: None,
+            collision_policy: None,
+            compressor: None,
+            decompress_time: Mutex::default(),
+            cipher: None,
+            decrypt_time: Mutex::default(),
+            pipeline: None,
         }
     }
 
@@ -76,28 +223,43 @@ where
     /// Returns resulting lengths of [chunks][crate::chunker::Chunk] with corresponding hash,
     /// along with amount of time spent on chunking and hashing.
     pub fn write(&mut self, data: &[u8], chunker: &ChunkerRef) -> io::Result<Vec<SpansInfo<Hash>>> {
-        let mut writer = StorageWriter::new(chunker, &mut self.hasher);
-
-        let mut current = 0;
-        let mut all_spans = vec![];
-
-        while current < data.len() {
-            let remaining = data.len() - current;
-            let to_process = min(SEG_SIZE, remaining);
-
-            let spans = writer.write(&data[current..current + to_process], &mut self.database)?;
-
-            current += to_process;
-
-            all_spans.push(spans);
-        }
-
-        let last_span = writer.flush(&mut self.database)?;
-
-        all_spans.push(last_span);
-        all_spans.retain(|span| span.total_length > 0);
+        let all_spans = match &self.pipeline {
+            Some((pipeline, write)) => write(
+                pipeline,
+                &mut &data[..],
+                chunker,
+                &mut self.database,
+                ChunkHasher::new(
+                    self.zero_chunk_threshold,
+                    self.cipher.as_deref(),
+                    self.compressor.as_deref(),
+                ),
+                ChunkInserter::new(
+                    self.collision_policy,
+                    self.cipher.as_deref(),
+                    self.compressor.as_deref(),
+                ),
+            )?,
+            None => {
+                let writer = StorageWriter::new(
+                    chunker,
+                    &mut self.hasher,
+                    ChunkHasher::new(
+                        self.zero_chunk_threshold,
+                        self.cipher.as_deref(),
+                        self.compressor.as_deref(),
+                    ),
+                    ChunkInserter::new(
+                        self.collision_policy,
+                        self.cipher.as_deref(),
+                        self.compressor.as_deref(),
+                    ),
+                );
+                writer.write_all(data, &mut self.database)?
+            }
+        };
 
-        self.size_written += data.len();
+        self.references.reference(&all_spans);
 
         Ok(all_spans)
     }
@@ -110,30 +272,136 @@ where
     where
         R: io::Read,
     {
-        let mut writer = StorageWriter::new(chunker, &mut self.hasher);
+        let all_spans = match &self.pipeline {
+            Some((pipeline, write)) => write(
+                pipeline,
+                &mut reader,
+                chunker,
+                &mut self.database,
+                ChunkHasher::new(
+                    self.zero_chunk_threshold,
+                    self.cipher.as_deref(),
+                    self.compressor.as_deref(),
+                ),
+                ChunkInserter::new(
+                    self.collision_policy,
+                    self.cipher.as_deref(),
+                    self.compressor.as_deref(),
+                ),
+            )?,
+            None => {
+                let writer = StorageWriter::new(
+                    chunker,
+                    &mut self.hasher,
+                    ChunkHasher::new(
+                        self.zero_chunk_threshold,
+                        self.cipher.as_deref(),
+                        self.compressor.as_deref(),
+                    ),
+                    ChunkInserter::new(
+                        self.collision_policy,
+                        self.cipher.as_deref(),
+                        self.compressor.as_deref(),
+                    ),
+                );
+                writer.write_stream(reader, &mut self.database)?
+            }
+        };
 
-        let mut all_spans = vec![];
-        let mut buffer = vec![0u8; SEG_SIZE];
+        self.references.reference(&all_spans);
 
-        loop {
-            let n = reader.read(&mut buffer)?;
-            if n == 0 {
-                break;
-            }
+        Ok(all_spans)
+    }
 
-            let spans = writer.write(&buffer[..n], &mut self.database)?;
-            self.size_written += spans.total_length;
+    /// Stores the data as a single chunk, without splitting it, and returns its span.
+    pub fn write_chunk(&mut self, data: &[u8]) -> io::Result<SpansInfo<Hash>> {
+        let hashed = ChunkHasher::new(None, self.cipher.as_deref(), self.compressor.as_deref())
+            .hash(&mut *self.hasher, &[data])?;
+        let inserted = ChunkInserter::new(
+            self.collision_policy,
+            self.cipher.as_deref(),
+            self.compressor.as_deref(),
+        )
+        .insert(&mut self.database, hashed.pairs)?;
+
+        let mut info = SpansInfo::new(hashed.spans);
+        info.measurements = hashed.measurements + inserted;
+        info.keys = hashed.keys;
+        self.references.reference(std::slice::from_ref(&info));
+
+        Ok(info)
+    }
 
-            all_spans.push(spans);
-        }
+    /// Sets minimal length of an all-zero chunk that is stored as a hole instead of being hashed and saved.
+    /// If `None`, all chunks are stored.
+    pub fn set_zero_chunk_threshold(&mut self, threshold: Option<usize>) {
+        self.zero_chunk_threshold = threshold;
+    }
 
-        let last_span = writer.flush(&mut self.database)?;
-        self.size_written += last_span.total_length;
+    /// Sets what to do when a written chunk has the same hash as a stored chunk with different contents.
+    /// If `None`, collisions are not checked.
+    pub fn set_collision_policy(&mut self, policy: Option<CollisionPolicy>) {
+        self.collision_policy = policy;
+    }
 
-        all_spans.push(last_span);
-        all_spans.retain(|span| span.total_length > 0);
+    /// Sets the compressor applied to the chunks before they are stored. If `None`, chunks are stored as they are.
+    ///
+    /// Stored chunks are not recompressed, so the compressor should be set before anything is written,
+    /// or right after a storage written with the same compressor is loaded.
+    pub fn set_compressor(&mut self, compressor: Option<Box<dyn Compressor>>) {
+        self.compressor = compressor;
+    }
 
-        Ok(all_spans)
+    /// Returns the total time spent on decompressing retrieved chunks.
+    pub fn decompress_time(&self) -> Duration {
+        *self.decompress_time.lock().unwrap()
+    }
+
+    /// Sets the cipher used for convergent encryption of the written chunks. If `None`, chunks are not encrypted.
+    ///
+    /// Chunks are encrypted before hashing, so that their hashes are computed over the ciphertext.
+    /// If the compressor is set, chunks are compressed before encryption, as the ciphertext hardly compresses.
+    /// Chunks that were written with a cipher can only be read while a cipher is set.
+    pub fn set_cipher(&mut self, cipher: Option<Box<dyn ConvergentCipher>>) {
+        self.cipher = cipher;
+    }
+
+    /// Returns the total time spent on decrypting retrieved chunks.
+    pub fn decrypt_time(&self) -> Duration {
+        *self.decrypt_time.lock().unwrap()
+    }
+
+    /// Returns the underlying database.
+    pub fn database(&self) -> &B {
+        &self.database
+    }
+
+    /// Returns the underlying database for modification.
+    pub fn database_mut(&mut self) -> &mut B {
+        &mut self.database
+    }
+
+    /// Adds references to already stored chunks, e.g. when a new file is built from the spans of another one.
+    ///
+    /// `size` is the amount of data the new references represent, it is counted towards written size.
+    pub fn retain<'a, I>(&mut self, hashes: I, size: usize)
+    where
+        I: IntoIterator<Item = &'a Hash>,
+        Hash: 'a,
+    {
+        self.references.retain(hashes, size)
+    }
+
+    /// Removes references to stored chunks, e.g. when a file that contained them is deleted.
+    ///
+    /// Chunks that are no longer referenced are not removed until [`collect_garbage`][Self::collect_garbage] is called.
+    /// `size` is the amount of data the references represent, it is subtracted from written size.
+    pub fn release<'a, I>(&mut self, hashes: I, size: usize)
+    where
+        I: IntoIterator<Item = &'a Hash>,
+        Hash: 'a,
+    {
+        self.references.release(hashes, size)
     }
 
     /// Retrieves the data from the storage based on hashes of the data [`segments`][Segment],
@@ -143,17 +411,137 @@ where
 
         retrieved
             .into_iter()
-            .map(|container| match &container.0 {
-                Data::Chunk(chunk) => Ok(chunk.clone()),
-                Data::TargetChunk(keys) => Ok(self
-                    .target_map
-                    .get_multi(keys)?
-                    .into_iter()
-                    .flatten()
-                    .collect()),
-            })
+            .zip(request)
+            .map(|(container, hash)| self.unpack(hash, container))
             .collect()
     }
+
+    /// Passes the data of the given [`segments`][Segment] to `f`, one chunk at a time and in the order of the request,
+    /// or returns Error(NotFound) if some of the hashes were not present in the base.
+    ///
+    /// Unlike [`retrieve`][Self::retrieve], chunks are not collected together, so the caller can write them
+    /// straight to their destination. Chunks are fetched from the database in small batches,
+    /// so at most a few of them are kept in memory, however large the request is.
+    pub fn retrieve_with<F>(&self, request: &[Hash], mut f: F) -> io::Result<()>
+    where
+        F: FnMut(&[u8]) -> io::Result<()>,
+    {
+        for batch in request.chunks(RETRIEVE_BATCH) {
+            for (container, hash) in self.database.get_multi(batch)?.into_iter().zip(batch) {
+                f(&self.unpack(hash, container)?)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the data of the chunk, collecting it from the target map if the chunk was scrubbed,
+    /// decrypting it if the chunk is encrypted, and decompressing it if the compressor is set.
+    fn unpack(&self, hash: &Hash, container: DataContainer<K>) -> io::Result<Vec<u8>> {
+        let stored = match container.0 {
+            Data::Chunk(chunk) => chunk,
+            Data::TargetChunk(keys) => self
+                .target_map
+                .get_multi(&keys)?
+                .into_iter()
+                .flatten()
+                .collect(),
+        };
+
+        let chunk = match self.references.key(hash) {
+            Some(key) => {
+                let Some(cipher) = &self.cipher else {
+                    let msg = "chunk is encrypted, but no cipher is set";
+                    return Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+                };
+
+                let start = Instant::now();
+                let chunk = cipher.decrypt(key, &stored);
+                *self.decrypt_time.lock().unwrap() += start.elapsed();
+                chunk
+            }
+            None => stored,
+        };
+
+        match &self.compressor {
+            Some(compressor) => {
+                let start = Instant::now();
+                let chunk = compressor.decompress(&chunk)?;
+                *self.decompress_time.lock().unwrap() += start.elapsed();
+                Ok(chunk)
+            }
+            None => Ok(chunk),
+        }
+    }
+
+    /// Returns the length of the stored chunk before compression.
+    ///
+    /// Encrypted chunks are compressed before encryption, so they are decrypted to read the length.
+    fn chunk_length(&self, hash: &Hash, chunk: &[u8]) -> usize {
+        let Some(compressor) = &self.compressor else {
+            return chunk.len();
+        };
+        match (self.references.key(hash), &self.cipher) {
+            (Some(key), Some(cipher)) => compressor.decompressed_len(&cipher.decrypt(key, chunk)),
+            _ => compressor.decompressed_len(chunk),
+        }
+    }
+}
+
+impl<Hash, B, K, T> ChunkStorage<Hash, B, K, T>
+where
+    Hash: ChunkHash + Send,
+    B: Database<Hash, DataContainer<K>> + Send,
+    K: Send,
+    T: Database<K, Vec<u8>>,
+{
+    /// Enables the pipelined write mode with the given settings, or disables it if `None`.
+    ///
+    /// Hasher of the storage is not used in the pipelined mode, workers create their own.
+    pub fn set_pipeline(&mut self, pipeline: Option<Pipeline<Hash>>) {
+        self.pipeline = pipeline.map(|pipeline| {
+            let write: PipelinedWrite<Hash, B> = write_pipelined::<Hash, K, B>;
+            (pipeline, write)
+        });
+    }
+}
+
+impl<Hash, B, K, T> ChunkStorage<Hash, B, K, T>
+where
+    Hash: ChunkHash + Encode + Decode<()>,
+    B: PersistentDatabase<Hash, DataContainer<K>>,
+    K: Encode + Decode<()>,
+    T: PersistentDatabase<K, Vec<u8>>,
+{
+    /// Saves storage counters, the database and the target map to the given directory.
+    pub fn save(&self, dir: &Path) -> io::Result<()> {
+        let counters = (&self.references, self.zero_chunk_threshold);
+        encode_to_file(&counters, &dir.join(COUNTERS_FILE))?;
+
+        self.database.save(&dir.join(DATABASE_FILE))?;
+        self.target_map.save(&dir.join(TARGET_MAP_FILE))
+    }
+
+    /// Loads storage previously saved to the given directory by [`save`][Self::save].
+    ///
+    /// Loaded storage has no scrubber.
+    pub fn load(dir: &Path, hasher: Box<dyn Hasher<Hash = Hash>>) -> io::Result<Self> {
+        let (references, zero_chunk_threshold) = decode_from_file(&dir.join(COUNTERS_FILE))?;
+
+        Ok(Self {
+            database: B::load(&dir.join(DATABASE_FILE))?,
+            scrubber: None,
+            target_map: T::load(&dir.join(TARGET_MAP_FILE))?,
+            hasher,
+            references,
+            zero_chunk_threshold,
+            collision_policy: None,
+            compressor: None,
+            decompress_time: Mutex::default(),
+            cipher: None,
+            decrypt_time: Mutex::default(),
+            pipeline: None,
+        })
+    }
 }
 
 impl<Hash, B, K, T> ChunkStorage<Hash, B, K, T>
@@ -162,6 +550,46 @@ where
     B: IterableDatabase<Hash, DataContainer<K>>,
     T: Database<K, Vec<u8>>,
 {
+    /// Removes all chunks that are not referenced by any file from the database.
+    ///
+    /// If a removed chunk was processed by the scrubber, its keys are removed from the target map as well,
+    /// unless they are listed by a chunk that stays in the database, as scrubbers may share keys between chunks.
+    ///
+    /// Returns the number of removed chunks.
+    pub fn collect_garbage(&mut self) -> io::Result<usize>
+    where
+        K: std::hash::Hash + Eq,
+    {
+        let garbage = self.references.unreferenced();
+
+        let mut dead_keys = HashSet::new();
+        for hash in &garbage {
+            if let Data::TargetChunk(keys) = self.database.get(hash)?.0 {
+                dead_keys.extend(keys);
+            }
+            self.database.remove(hash)?;
+            self.references.forget(hash);
+        }
+
+        if !dead_keys.is_empty() {
+            for pair in self.database.iterator() {
+                if let Data::TargetChunk(keys) = pair?.1.extract() {
+                    for key in keys {
+                        dead_keys.remove(key);
+                    }
+                }
+            }
+        }
+        for key in &dead_keys {
+            match self.target_map.remove(key) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => (),
+            }
+        }
+
+        Ok(garbage.len())
+    }
+
     pub fn new_with_scrubber(
         database: B,
         target_map: T,
@@ -173,7 +601,14 @@ where
             scrubber: Some(scrubber),
             target_map,
             hasher,
-            size_written: 0,
+            references: References::default(),
+            zero_chunk_threshold: None,
+            collision_policy: None,
+            compressor: None,
+            decompress_time: Mutex::default(),
+            cipher: None,
+            decrypt_time: Mutex::default(),
+            pipeline: None,
         }
     }
 
@@ -190,53 +625,72 @@ where
     }
 
     /// Returns size of CDC chunks in the storage. Doesn't count for chunks processed with SBC or FBC.
-    fn total_cdc_size(&self) -> usize {
-        self.database
-            .values()
-            .fold(0, |total_size, container| match container.extract() {
+    fn total_cdc_size(&self) -> io::Result<usize> {
+        self.database.iterator().try_fold(0, |total_size, pair| {
+            let (hash, container) = pair?;
+            Ok(match container.extract() {
+                Data::Chunk(chunk) => total_size + self.chunk_length(hash, chunk),
+                Data::TargetChunk(_) => total_size,
+            })
+        })
+    }
+
+    /// Returns the total length of the chunks as they are stored in the database, i.e. after compression.
+    fn stored_cdc_size(&self) -> io::Result<usize> {
+        self.database.values().try_fold(0, |total_size, container| {
+            Ok(match container?.extract() {
                 Data::Chunk(chunk) => total_size + chunk.len(),
                 Data::TargetChunk(_) => total_size,
             })
+        })
+    }
+
+    /// Calculates compression ratio of the chunks stored in the database, not accounting for chunks processed with scrubber.
+    /// Equals 1 if the compressor is not set.
+    pub fn compression_ratio(&self) -> io::Result<f64> {
+        Ok((self.total_cdc_size()? as f64) / (self.stored_cdc_size()? as f64))
     }
 
     /// Calculates deduplication ratio of the storage, not accounting for chunks processed with scrubber.
-    pub fn cdc_dedup_ratio(&self) -> f64 {
-        (self.size_written as f64) / (self.total_cdc_size() as f64)
+    pub fn cdc_dedup_ratio(&self) -> io::Result<f64> {
+        Ok((self.references.size_written as f64) / (self.total_cdc_size()? as f64))
     }
 
     /// Returns average chunk size in the storage.
-    pub fn average_chunk_size(&self) -> usize {
+    pub fn average_chunk_size(&self) -> io::Result<usize> {
         let (count, size) = self
             .database
-            .values()
-            .fold((0, 0), |(count, size), container| {
+            .iterator()
+            .try_fold((0, 0), |(count, size), pair| {
+                let (hash, container) = pair?;
                 let chunk_size = match container.extract() {
-                    Data::Chunk(chunk) => chunk.len(),
+                    Data::Chunk(chunk) => self.chunk_length(hash, chunk),
                     Data::TargetChunk(_) => 0,
                 };
-                (count + 1, size + chunk_size)
-            });
+                io::Result::Ok((count + 1, size + chunk_size))
+            })?;
 
-        size / count
+        Ok(size / count)
     }
 
-    pub fn full_cdc_dedup_ratio(&self) -> f64 {
+    pub fn full_cdc_dedup_ratio(&self) -> io::Result<f64> {
         let key_size = self
             .database
             .keys()
             .map(|key| self.hasher.len(key))
             .sum::<usize>();
 
-        (self.size_written as f64) / (self.total_cdc_size() as f64 + key_size as f64)
+        Ok((self.references.size_written as f64)
+            / (self.total_cdc_size()? as f64 + key_size as f64))
     }
 
-    pub fn iterator(&self) -> Box<dyn Iterator<Item = (&Hash, &DataContainer<K>)> + '_> {
+    pub fn iterator(&self) -> Box<dyn Iterator<Item = io::Result<(&Hash, DataContainer<K>)>> + '_> {
         self.database.iterator()
     }
 
     /// Removes all stored data in the database and sets written size to 0.
     pub fn clear_database(&mut self) -> io::Result<()> {
-        self.size_written = 0;
+        self.references.clear();
         self.database.clear()
     }
 }
@@ -247,22 +701,21 @@ where
     B: IterableDatabase<Hash, DataContainer<K>>,
     T: IterableDatabase<K, Vec<u8>>,
 {
-    fn total_size(&self) -> usize {
-        let cdc_size = self.total_cdc_size();
-        let scrubbed_size = self
-            .target_map
-            .values()
-            .fold(0, |total_size, data| total_size + data.len());
-        cdc_size + scrubbed_size
+    fn total_size(&self) -> io::Result<usize> {
+        let cdc_size = self.total_cdc_size()?;
+        let scrubbed_size = self.target_map.values().try_fold(0, |total_size, data| {
+            io::Result::Ok(total_size + data?.len())
+        })?;
+        Ok(cdc_size + scrubbed_size)
     }
 
-    pub fn total_dedup_ratio(&self) -> f64 {
-        (self.size_written as f64) / (self.total_size() as f64)
+    pub fn total_dedup_ratio(&self) -> io::Result<f64> {
+        Ok((self.references.size_written as f64) / (self.total_size()? as f64))
     }
 
     /// Removes all stored data in the target map and sets written size to 0.
     pub fn clear_database_full(&mut self) -> io::Result<()> {
-        self.size_written = 0;
+        self.references.clear();
         self.database.clear()?;
         self.target_map.clear()
     }
@@ -271,30 +724,98 @@ where
 /// Writer that conducts operations on [Storage].
 /// Only exists during [FileSystem::write_to_file][crate::FileSystem::write_to_file].
 /// Receives `buffer` from [FileHandle][crate::file_layer::FileHandle] and gives it back after a successful write.
-struct StorageWriter<'handle, Hash>
+pub(crate) struct StorageWriter<'handle, Hash>
 where
     Hash: ChunkHash,
 {
-    chunker: &'handle ChunkerRef,
+    stream: ChunkStream<'handle>,
     hasher: &'handle mut Box<dyn Hasher<Hash = Hash>>,
-    rest: Vec<u8>,
+    hashing: ChunkHasher<'handle>,
+    inserter: ChunkInserter<'handle>,
 }
 
 impl<'handle, Hash> StorageWriter<'handle, Hash>
 where
     Hash: ChunkHash,
 {
-    fn new(
+    pub(crate) fn new(
         chunker: &'handle ChunkerRef,
         hasher: &'handle mut Box<dyn Hasher<Hash = Hash>>,
+        hashing: ChunkHasher<'handle>,
+        inserter: ChunkInserter<'handle>,
     ) -> Self {
         Self {
-            chunker,
+            stream: ChunkStream::new(chunker),
             hasher,
-            rest: vec![],
+            hashing,
+            inserter,
         }
     }
 
+    /// Writes all data to the database, one segment at a time, and flushes the rest.
+    ///
+    /// Returns spans of all segments. Spans are not referenced.
+    pub(crate) fn write_all<K, B: Database<Hash, DataContainer<K>>>(
+        mut self,
+        data: &[u8],
+        base: &mut B,
+    ) -> io::Result<Vec<SpansInfo<Hash>>> {
+        let mut current = 0;
+        let mut all_spans = vec![];
+
+        while current < data.len() {
+            let remaining = data.len() - current;
+            let to_process = min(SEG_SIZE, remaining);
+
+            let spans = self.write(&data[current..current + to_process], base)?;
+
+            current += to_process;
+
+            all_spans.push(spans);
+        }
+
+        let last_span = self.flush(base)?;
+
+        all_spans.push(last_span);
+        all_spans.retain(|span| span.total_length > 0);
+
+        Ok(all_spans)
+    }
+
+    /// Writes all data from the reader to the database, one segment at a time, and flushes the rest.
+    ///
+    /// Returns spans of all segments. Spans are not referenced.
+    pub(crate) fn write_stream<R, K, B>(
+        mut self,
+        mut reader: R,
+        base: &mut B,
+    ) -> io::Result<Vec<SpansInfo<Hash>>>
+    where
+        R: io::Read,
+        B: Database<Hash, DataContainer<K>>,
+    {
+        let mut all_spans = vec![];
+        let mut buffer = vec![0u8; SEG_SIZE];
+
+        loop {
+            let n = reader.read(&mut buffer)?;
+            if n == 0 {
+                break;
+            }
+
+            let spans = self.write(&buffer[..n], base)?;
+
+            all_spans.push(spans);
+        }
+
+        let last_span = self.flush(base)?;
+
+        all_spans.push(last_span);
+        all_spans.retain(|span| span.total_length > 0);
+
+        Ok(all_spans)
+    }
+
     /// Writes 1 MB of data to the [`base`][crate::base::Base] storage after deduplication.
     ///
     /// Returns resulting lengths of [chunks][crate::chunker::Chunk] with corresponding hash,
@@ -306,85 +827,338 @@ where
     ) -> io::Result<SpansInfo<Hash>> {
         //debug_assert!(data.len() == SEG_SIZE); // we assume that all given data segments are 1MB long for now
 
-        let mut buffer = self.rest.clone();
-        buffer.extend_from_slice(data);
+        let segment = self.stream.next(data);
+        if segment.is_empty() {
+            return Ok(SpansInfo::default());
+        }
 
-        let empty = Vec::with_capacity(self.chunker.lock().unwrap().estimate_chunk_count(&buffer));
+        let hashed = self
+            .hashing
+            .hash(&mut **self.hasher, &segment.chunk_data(data))?;
 
-        let start = Instant::now();
-        let mut chunks = self.chunker.lock().unwrap().chunk_data(&buffer, empty);
-        let chunk_time = start.elapsed();
+        let inserted = self.inserter.insert(base, hashed.pairs)?;
 
-        if chunks.is_empty() {
+        let mut info = SpansInfo::new(hashed.spans);
+        info.measurements =
+            WriteMeasurements::new(Duration::default(), segment.chunk_time, Duration::default())
+                + hashed.measurements
+                + inserted;
+        info.keys = hashed.keys;
+        Ok(info)
+    }
+
+    /// Flushes remaining data to the storage and returns its [`spans`][Span] with hashing and chunking times.
+    fn flush<K, B: Database<Hash, DataContainer<K>>>(
+        &mut self,
+        base: &mut B,
+    ) -> io::Result<SpansInfo<Hash>> {
+        let last = self.stream.finish();
+        if last.is_empty() {
             return Ok(SpansInfo::default());
         }
 
-        self.rest = buffer[chunks.pop().unwrap().range()].to_vec();
+        let hashed = self
+            .hashing
+            .hash(&mut **self.hasher, &last.chunk_data(&[]))?;
 
-        let start = Instant::now();
-        let hashes = chunks
-            .iter()
-            .map(|chunk| self.hasher.hash(&buffer[chunk.range()]))
-            .collect::<Vec<_>>();
-        let hash_time = start.elapsed();
+        let inserted = self.inserter.insert(base, hashed.pairs)?;
 
-        let chunks = chunks
-            .iter()
-            .map(|chunk| buffer[chunk.range()].to_vec())
-            .collect::<Vec<_>>();
+        let mut info = SpansInfo::new(hashed.spans);
+        info.measurements =
+            WriteMeasurements::new(Duration::default(), last.chunk_time, Duration::default())
+                + hashed.measurements
+                + inserted;
+        info.keys = hashed.keys;
+        Ok(info)
+    }
+}
+
+/// Settings of how chunks are hashed, shared by the serial and pipelined writers.
+#[derive(Clone, Copy, Default)]
+pub(crate) struct ChunkHasher<'a> {
+    /// Minimal length of an all-zero chunk to be stored as a hole. Hole detection is disabled if `None`.
+    pub zero_chunk_threshold: Option<usize>,
+    pub cipher: Option<&'a dyn ConvergentCipher>,
+    /// Compressor applied to the chunks before encryption. Compressed ciphertexts hardly get smaller,
+    /// so it should only be set along with the cipher, and otherwise chunks are compressed by [`ChunkInserter`].
+    pub compressor: Option<&'a dyn Compressor>,
+}
 
-        let total_length = chunks.iter().map(|chunk| chunk.len()).sum::<usize>();
+/// Chunks hashed by [`ChunkHasher`], ready to be inserted into the database.
+pub(crate) struct HashedSegment<Hash: ChunkHash, K> {
+    pub spans: Vec<Span<Hash>>,
+    pub pairs: HashedChunks<Hash, K>,
+    /// Keys of the encrypted chunks, empty if the cipher is not set.
+    pub keys: Vec<(Hash, ChunkKey)>,
+    /// Hashing, compression and encryption times.
+    pub measurements: WriteMeasurements,
+}
 
-        // have to copy hashes? or do something else?
-        let spans = hashes
-            .iter()
-            .zip(chunks.iter())
-            .map(|(hash, chunk)| Span::new(hash.clone(), chunk.len()))
-            .collect();
+impl<'a> ChunkHasher<'a> {
+    /// Creates the settings of hashing chunks for a storage with the given cipher and compressor.
+    ///
+    /// If the cipher is set, chunks are compressed before encryption, so the compressor is applied while hashing.
+    pub(crate) fn new(
+        zero_chunk_threshold: Option<usize>,
+        cipher: Option<&'a dyn ConvergentCipher>,
+        compressor: Option<&'a dyn Compressor>,
+    ) -> Self {
+        Self {
+            zero_chunk_threshold,
+            cipher,
+            compressor: cipher.and(compressor),
+        }
+    }
 
-        let converted_chunks = chunks
-            .into_iter()
-            .map(|chunk| DataContainer(Data::Chunk(chunk)));
+    /// Hashes the chunks and prepares them for insertion into the database.
+    ///
+    /// All-zero chunks that are at least `zero_chunk_threshold` long become holes: they are not hashed or stored.
+    /// If the cipher is set, the other chunks are encrypted, and their ciphertexts are hashed and stored.
+    /// If the compressor is set too, chunks are compressed before encryption, while the key is derived from the plaintext.
+    pub(crate) fn hash<Hash: ChunkHash, K>(
+        &self,
+        hasher: &mut dyn Hasher<Hash = Hash>,
+        chunks: &[&[u8]],
+    ) -> io::Result<HashedSegment<Hash, K>> {
+        let is_hole = |chunk: &[u8]| {
+            self.zero_chunk_threshold
+                .is_some_and(|threshold| chunk.len() >= threshold && chunk.iter().all(|&b| b == 0))
+        };
 
-        let pairs = hashes.into_iter().zip(converted_chunks).collect(); // we allocate memory for (K, V) pairs, which is not really required
-        let start = Instant::now();
-        base.insert_multi(pairs)?;
-        let save_time = start.elapsed();
+        let mut hash_time = Duration::default();
+        let mut encrypt_time = Duration::default();
+        let mut compress_time = Duration::default();
+        let mut spans = Vec::with_capacity(chunks.len());
+        let mut pairs = Vec::with_capacity(chunks.len());
+        let mut keys = vec![];
+
+        for &chunk in chunks {
+            // zero chunk detection is counted towards hashing time, as it replaces hashing for holes
+            let start = Instant::now();
+            let hole = is_hole(chunk);
+            hash_time += start.elapsed();
+            if hole {
+                spans.push(Span::hole(chunk.len()));
+                continue;
+            }
 
-        Ok(SpansInfo {
+            let (stored, key) = match self.cipher {
+                Some(cipher) => {
+                    let plaintext = match self.compressor {
+                        Some(compressor) => {
+                            let start = Instant::now();
+                            let compressed = compressor.compress(chunk)?;
+                            compress_time += start.elapsed();
+                            Cow::Owned(compressed)
+                        }
+                        None => Cow::Borrowed(chunk),
+                    };
+
+                    let start = Instant::now();
+                    let key = cipher.derive_key(chunk);
+                    let encrypted = cipher.encrypt(&key, &plaintext);
+                    encrypt_time += start.elapsed();
+                    (encrypted, Some(key))
+                }
+                None => (chunk.to_vec(), None),
+            };
+
+            let start = Instant::now();
+            let hash = hasher.hash(&stored);
+            hash_time += start.elapsed();
+
+            if let Some(key) = key {
+                keys.push((hash.clone(), key));
+            }
+            spans.push(Span::new(hash.clone(), chunk.len()));
+            pairs.push((hash, DataContainer(Data::Chunk(stored))));
+        }
+
+        Ok(HashedSegment {
             spans,
-            measurements: WriteMeasurements::new(save_time, chunk_time, hash_time),
-            total_length,
+            pairs,
+            keys,
+            measurements: WriteMeasurements {
+                hash_time,
+                encrypt_time,
+                compress_time,
+                ..Default::default()
+            },
         })
     }
+}
 
-    /// Flushes remaining data to the storage and returns its [`span`][Span] with hashing and chunking times.
-    fn flush<K, B: Database<Hash, DataContainer<K>>>(
-        &mut self,
-        base: &mut B,
-    ) -> io::Result<SpansInfo<Hash>> {
-        // is this necessary?
-        if self.rest.is_empty() {
-            return Ok(SpansInfo::default());
+/// Settings of how hashed chunks are inserted into the database, shared by the serial and pipelined writers.
+#[derive(Clone, Copy, Default)]
+pub(crate) struct ChunkInserter<'a> {
+    pub collision_policy: Option<CollisionPolicy>,
+    pub compressor: Option<&'a dyn Compressor>,
+}
+
+impl<'a> ChunkInserter<'a> {
+    /// Creates the settings of inserting chunks for a storage with the given cipher and compressor.
+    ///
+    /// If the cipher is set, chunks are already compressed by [`ChunkHasher`], so the compressor is not applied again.
+    pub(crate) fn new(
+        collision_policy: Option<CollisionPolicy>,
+        cipher: Option<&'a dyn ConvergentCipher>,
+        compressor: Option<&'a dyn Compressor>,
+    ) -> Self {
+        Self {
+            collision_policy,
+            compressor: compressor.filter(|_| cipher.is_none()),
         }
+    }
 
-        let remainder = self.rest.to_vec();
-        let remainder_length = remainder.len();
+    /// Inserts hash-chunk pairs into the database.
+    ///
+    /// If the collision policy is set, every chunk whose hash is already present, in the database or earlier in `pairs`,
+    /// is compared byte by byte with the stored one. Scrubbed chunks can't be compared, so they are skipped.
+    /// With [`CollisionPolicy::Count`] pairs are inserted as usual, with [`CollisionPolicy::Fail`]
+    /// nothing is inserted if there is a collision.
+    ///
+    /// If the compressor is set, chunks that are not stored yet are compressed, and the others are not inserted again.
+    ///
+    /// Returns measurements with save and compression times and the number of collisions.
+    ///
+    /// # Errors
+    /// * `io::ErrorKind::InvalidData` - if a collision is found and the policy is [`CollisionPolicy::Fail`]
+    pub(crate) fn insert<Hash, K, B>(
+        &self,
+        base: &mut B,
+        pairs: Vec<(Hash, DataContainer<K>)>,
+    ) -> io::Result<WriteMeasurements>
+    where
+        Hash: ChunkHash,
+        B: Database<Hash, DataContainer<K>>,
+    {
         let start = Instant::now();
-        let hash = self.hasher.hash(&remainder);
-        let hash_time = start.elapsed();
+        let collisions = match self.collision_policy {
+            Some(_) => self.count_collisions(base, &pairs)?,
+            None => 0,
+        };
+        if collisions > 0 && self.collision_policy == Some(CollisionPolicy::Fail) {
+            let msg = format!("{collisions} chunks have the same hash as different stored chunks");
+            return Err(io::Error::new(io::ErrorKind::InvalidData, msg));
+        }
+        let mut save_time = start.elapsed();
+
+        let (pairs, compress_time) = match self.compressor {
+            Some(compressor) => compress_new(compressor, base, pairs)?,
+            None => (pairs, Duration::default()),
+        };
 
         let start = Instant::now();
-        base.insert(hash.clone(), DataContainer(Data::Chunk(remainder)))?;
-        let save_time = start.elapsed();
-
-        let span = Span::new(hash, remainder_length);
-        Ok(SpansInfo {
-            spans: vec![span],
-            measurements: WriteMeasurements::new(save_time, Duration::default(), hash_time),
-            total_length: remainder_length,
+        base.insert_multi(pairs)?;
+        save_time += start.elapsed();
+
+        Ok(WriteMeasurements {
+            save_time,
+            compress_time,
+            collisions,
+            ..Default::default()
         })
     }
+
+    /// Counts chunks among `pairs` whose contents differ from the chunk with the same hash,
+    /// stored in the database or met earlier in `pairs`.
+    fn count_collisions<Hash, K, B>(
+        &self,
+        base: &B,
+        pairs: &[(Hash, DataContainer<K>)],
+    ) -> io::Result<usize>
+    where
+        Hash: ChunkHash,
+        B: Database<Hash, DataContainer<K>>,
+    {
+        // contents that chunks with the same hash are compared with, `None` if the stored chunk is scrubbed
+        let mut known: HashMap<&Hash, Option<Cow<[u8]>>> = HashMap::new();
+        let mut collisions = 0;
+
+        for (hash, container) in pairs {
+            let Data::Chunk(chunk) = &container.0 else {
+                continue;
+            };
+
+            let expected = match known.entry(hash) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => {
+                    let stored = if base.contains(hash) {
+                        match base.get(hash)?.0 {
+                            Data::Chunk(stored) => match self.compressor {
+                                Some(compressor) => {
+                                    Some(Cow::Owned(compressor.decompress(&stored)?))
+                                }
+                                None => Some(Cow::Owned(stored)),
+                            },
+                            Data::TargetChunk(_) => None,
+                        }
+                    } else {
+                        Some(Cow::Borrowed(chunk.as_slice()))
+                    };
+                    entry.insert(stored)
+                }
+            };
+
+            if expected
+                .as_deref()
+                .is_some_and(|expected| expected != chunk)
+            {
+                collisions += 1;
+            }
+        }
+
+        Ok(collisions)
+    }
+}
+
+/// Compresses the chunks that are not stored in the database yet, and drops the others,
+/// so that stored chunks are not compressed and inserted again.
+///
+/// Returns the pairs to insert and the time spent on compression.
+fn compress_new<Hash, K, B>(
+    compressor: &dyn Compressor,
+    base: &B,
+    pairs: Vec<(Hash, DataContainer<K>)>,
+) -> io::Result<(HashedChunks<Hash, K>, Duration)>
+where
+    Hash: ChunkHash,
+    B: Database<Hash, DataContainer<K>>,
+{
+    let mut seen = HashSet::new();
+    let mut compress_time = Duration::default();
+    let mut compressed = Vec::with_capacity(pairs.len());
+
+    for (hash, container) in pairs {
+        if base.contains(&hash) || !seen.insert(hash.clone()) {
+            continue;
+        }
+
+        let container = match container.0 {
+            Data::Chunk(chunk) => {
+                let start = Instant::now();
+                let chunk = compressor.compress(&chunk)?;
+                compress_time += start.elapsed();
+                DataContainer(Data::Chunk(chunk))
+            }
+            data => DataContainer(data),
+        };
+        compressed.push((hash, container));
+    }
+
+    Ok((compressed, compress_time))
+}
+
+/// What to do when a written chunk has the same hash as a stored chunk with different contents.
+///
+/// Checking for collisions requires reading every chunk that is already present in the database,
+/// so it slows writes down. It is useful to evaluate weak hashers, e.g. truncated or non-cryptographic ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CollisionPolicy {
+    /// Count collisions in [`WriteMeasurements`] and store chunks as if they were not checked.
+    Count,
+    /// Fail the write with `io::ErrorKind::InvalidData`.
+    Fail,
 }
 
 impl<K> DataContainer<K> {
@@ -403,6 +1177,14 @@ impl<K> DataContainer<K> {
         &mut self.0
     }
 
+    /// Returns a contained chunk, or `None` if it is of type `Data::TargetChunk`.
+    pub(crate) fn into_chunk(self) -> Option<Vec<u8>> {
+        match self.0 {
+            Data::Chunk(chunk) => Some(chunk),
+            Data::TargetChunk(_) => None,
+        }
+    }
+
     /// Returns a contained chunk if it is of type `Data::Chunk`.
     ///
     /// Will panic otherwise.
@@ -440,13 +1222,19 @@ impl<K> Default for Data<K> {
 #[cfg(test)]
 mod tests {
     use std::collections::HashMap;
+    use std::io;
+    use std::sync::Mutex;
 
     use super::ChunkStorage;
+    use super::Data;
     use super::DataContainer;
+    use super::References;
     use super::ScrubMeasurements;
+    use super::RETRIEVE_BATCH;
     use crate::chunkers::{FSChunker, SuperChunker};
     use crate::hashers::SimpleHasher;
-    use crate::system::scrub::DumbScrubber;
+    use crate::system::database::{Database, IterableDatabase};
+    use crate::system::scrub::{DumbScrubber, Scrub};
 
     #[test]
     fn hashmap_works_as_cdc_map() {
@@ -458,7 +1246,14 @@ mod tests {
             scrubber: Some(Box::new(DumbScrubber)),
             target_map: HashMap::default(),
             hasher: Box::new(SimpleHasher),
-            size_written: 0,
+            references: References::default(),
+            zero_chunk_threshold: None,
+            collision_policy: None,
+            compressor: None,
+            decompress_time: Mutex::default(),
+            cipher: None,
+            decrypt_time: Mutex::default(),
+            pipeline: None,
         };
 
         let measurements = chunk_storage
@@ -485,7 +1280,131 @@ mod tests {
 
         chunk_storage.write(&data, &chunker).unwrap();
 
-        assert_eq!(chunk_storage.total_cdc_size(), 4096)
+        assert_eq!(chunk_storage.total_cdc_size().unwrap(), 4096)
+    }
+
+    /// Database that remembers the largest number of keys requested at once.
+    #[derive(Default)]
+    struct BatchRecorder {
+        map: HashMap<Vec<u8>, DataContainer<()>>,
+        largest_batch: Mutex<usize>,
+    }
+
+    impl Database<Vec<u8>, DataContainer<()>> for BatchRecorder {
+        fn insert(&mut self, key: Vec<u8>, value: DataContainer<()>) -> io::Result<()> {
+            Database::insert(&mut self.map, key, value)
+        }
+
+        fn get(&self, key: &Vec<u8>) -> io::Result<DataContainer<()>> {
+            self.get_multi(std::slice::from_ref(key))
+                .map(|mut values| values.remove(0))
+        }
+
+        fn get_multi(&self, keys: &[Vec<u8>]) -> io::Result<Vec<DataContainer<()>>> {
+            let mut largest_batch = self.largest_batch.lock().unwrap();
+            *largest_batch = (*largest_batch).max(keys.len());
+            keys.iter()
+                .map(|key| Database::get(&self.map, key))
+                .collect()
+        }
+
+        fn contains(&self, key: &Vec<u8>) -> bool {
+            Database::contains(&self.map, key)
+        }
+    }
+
+    #[test]
+    fn chunks_are_retrieved_in_bounded_batches() {
+        let mut chunk_storage = ChunkStorage::new(
+            BatchRecorder::default(),
+            SimpleHasher.into(),
+            HashMap::default(),
+        );
+
+        let data = (0..100 * 64).map(|i| (i / 64) as u8).collect::<Vec<_>>();
+        let chunker = FSChunker::new(64).into();
+        let request = chunk_storage
+            .write(&data, &chunker)
+            .unwrap()
+            .iter()
+            .flat_map(|info| info.spans.iter())
+            .map(|span| span.hash.clone().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(request.len(), 100);
+
+        let mut retrieved = vec![];
+        chunk_storage
+            .retrieve_with(&request, |chunk| {
+                retrieved.extend_from_slice(chunk);
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(retrieved, data);
+        assert_eq!(
+            *chunk_storage.database.largest_batch.lock().unwrap(),
+            RETRIEVE_BATCH
+        );
+    }
+
+    /// Scrubber that stores chunk halves in the target map, keyed by their content,
+    /// so that chunks with equal halves share target keys.
+    struct HalvesScrubber;
+
+    impl
+        Scrub<Vec<u8>, HashMap<Vec<u8>, DataContainer<Vec<u8>>>, Vec<u8>, HashMap<Vec<u8>, Vec<u8>>>
+        for HalvesScrubber
+    {
+        fn scrub<'a>(
+            &mut self,
+            database: &mut HashMap<Vec<u8>, DataContainer<Vec<u8>>>,
+            target: &mut HashMap<Vec<u8>, Vec<u8>>,
+        ) -> io::Result<ScrubMeasurements>
+        where
+            Vec<u8>: 'a,
+        {
+            database.for_each_mut(&mut |_, container| {
+                if let Data::Chunk(chunk) = container.extract() {
+                    let (left, right) = chunk.split_at(chunk.len() / 2);
+                    let keys = vec![left.to_vec(), right.to_vec()];
+                    for key in &keys {
+                        Database::insert(target, key.clone(), key.clone())?;
+                    }
+                    container.make_target(keys);
+                }
+                Ok(())
+            })?;
+            Ok(ScrubMeasurements::default())
+        }
+    }
+
+    #[test]
+    fn garbage_collection_keeps_target_keys_shared_with_live_chunks() {
+        let mut chunk_storage = ChunkStorage::new_with_scrubber(
+            HashMap::new(),
+            HashMap::new(),
+            Box::new(HalvesScrubber),
+            SimpleHasher.into(),
+        );
+        let chunker = FSChunker::new(64).into();
+
+        let dead = [vec![1; 32], vec![2; 32]].concat();
+        let live = [vec![1; 32], vec![3; 32]].concat();
+        let dead_hash = chunk_storage.write(&dead, &chunker).unwrap()[0].spans[0]
+            .hash
+            .clone()
+            .unwrap();
+        let live_hash = chunk_storage.write(&live, &chunker).unwrap()[0].spans[0]
+            .hash
+            .clone()
+            .unwrap();
+        chunk_storage.scrub().unwrap();
+
+        chunk_storage.release([&dead_hash], dead.len());
+        assert_eq!(chunk_storage.collect_garbage().unwrap(), 1);
+
+        assert_eq!(chunk_storage.retrieve(&[live_hash]).unwrap(), vec![live]);
+        assert!(chunk_storage.target_map.contains_key(&vec![1; 32]));
+        assert!(!chunk_storage.target_map.contains_key(&vec![2; 32]));
     }
 
     #[test]
@@ -509,6 +1428,6 @@ mod tests {
         chunk_storage.write(&data, &chunker).unwrap();
         chunk_storage.write(&data, &chunker).unwrap();
 
-        assert_eq!(chunk_storage.size_written, 1024 * 1024 * 2);
+        assert_eq!(chunk_storage.references.size_written, 1024 * 1024 * 2);
     }
 }
@@ -0,0 +1,104 @@
+use std::fs::File;
+use std::io;
+use std::os::unix::fs::FileExt;
+use std::sync::Arc;
+
+use bincode::{decode_from_slice, encode_into_slice, Decode, Encode};
+
+use crate::system::aligned_buffer::BufferPool;
+
+/// Size of the superblock at the start of the device. Data of [`DiskDatabase`][crate::DiskDatabase] follows it.
+///
+/// Multiple of the common block sizes, so that the data stays aligned.
+pub(crate) const SUPERBLOCK_SIZE: u64 = 4096;
+/// Marks a device formatted by [`DiskDatabase`][crate::DiskDatabase].
+const MAGIC: &[u8; 8] = b"CHUNKFSD";
+/// Version of the on-disk format, bumped on incompatible changes.
+const VERSION: u32 = 1;
+
+/// State of a [`DiskDatabase`][crate::DiskDatabase] needed to reopen it, stored at the start of the device.
+///
+/// Starts with [`MAGIC`] and [`VERSION`], followed by the fields encoded with bincode. The rest of the block is zeroed.
+#[derive(Debug, PartialEq, Encode, Decode)]
+pub(crate) struct Superblock {
+    /// End of the written data.
+    pub used_size: u64,
+    /// Offset of the saved index of keys and locations of their values.
+    pub index_offset: u64,
+    /// Length of the encoded index, 0 if it was never saved.
+    pub index_length: u64,
+    /// Size of the containers, 0 if the database doesn't use them.
+    pub container_size: u64,
+    /// Number of containers kept in memory after reads.
+    pub cached_containers: u64,
+}
+
+impl Superblock {
+    /// Reads the superblock from the start of the device.
+    ///
+    /// # Errors
+    /// * `io::ErrorKind::InvalidData` - if the device is not formatted by [`DiskDatabase`][crate::DiskDatabase],
+    ///   or is formatted by an incompatible version.
+    pub fn read(device: &File, buffers: &Arc<BufferPool>) -> io::Result<Self> {
+        let mut block = buffers.take(SUPERBLOCK_SIZE as usize);
+        device.read_exact_at(&mut block, 0)?;
+
+        if &block[..MAGIC.len()] != MAGIC {
+            let msg = "device doesn't contain a database";
+            return Err(io::Error::new(io::ErrorKind::InvalidData, msg));
+        }
+        let version = u32::from_le_bytes(block[MAGIC.len()..MAGIC.len() + 4].try_into().unwrap());
+        if version != VERSION {
+            let msg = format!("unsupported database version {version}, expected {VERSION}");
+            return Err(io::Error::new(io::ErrorKind::InvalidData, msg));
+        }
+
+        decode_from_slice(&block[MAGIC.len() + 4..], bincode::config::standard())
+            .map(|(superblock, _)| superblock)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Writes the superblock to the start of the device.
+    pub fn write(&self, device: &File, buffers: &Arc<BufferPool>) -> io::Result<()> {
+        let mut block = buffers.take(SUPERBLOCK_SIZE as usize);
+        block[..MAGIC.len()].copy_from_slice(MAGIC);
+        block[MAGIC.len()..MAGIC.len() + 4].copy_from_slice(&VERSION.to_le_bytes());
+        encode_into_slice(
+            self,
+            &mut block[MAGIC.len() + 4..],
+            bincode::config::standard(),
+        )
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        device.write_all_at(&block, 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::{Superblock, SUPERBLOCK_SIZE};
+    use crate::system::aligned_buffer::BufferPool;
+
+    #[test]
+    fn superblock_is_read_back() {
+        let file = tempfile::tempfile().unwrap();
+        file.set_len(8192).unwrap();
+        let buffers = BufferPool::new(SUPERBLOCK_SIZE as usize, 0);
+        assert_eq!(
+            Superblock::read(&file, &buffers).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+
+        let superblock = Superblock {
+            used_size: 1 << 40,
+            index_offset: 1 << 41,
+            index_length: 12345,
+            container_size: 4 << 20,
+            cached_containers: 8,
+        };
+        superblock.write(&file, &buffers).unwrap();
+        assert_eq!(Superblock::read(&file, &buffers).unwrap(), superblock);
+    }
+}
@@ -1,17 +1,26 @@
 extern crate chunkfs;
 
 use std::io;
-use std::io::{Seek, Write};
+use std::io::{BufRead, Read, Seek, Write};
 
 use approx::assert_relative_eq;
+use chunkfs::bench::{CDCFixture, Dataset};
 use chunkfs::chunkers::{FSChunker, LeapChunker, SuperChunker};
+use chunkfs::compressors::{Lz4Compressor, ZstdCompressor};
+use chunkfs::encryption::Sha256Cipher;
 use chunkfs::hashers::{Sha256Hasher, SimpleHasher};
+use chunkfs::Hasher;
 use chunkfs::{
-    create_cdc_filesystem, ChunkerRef, DataContainer, Database, DiskDatabase, IterableDatabase,
+    create_cdc_filesystem, CachedDatabase, ChunkerRef, ChunkerRegistry, CollisionPolicy,
+    Compressor, ConcurrentDatabase, CopyScrubber, Data, DataContainer, Database, DiskDatabase,
+    FileSystem, FilteredDatabase, IterableDatabase, Pipeline, ShardedDatabase, SharedFileSystem,
     WriteMeasurements,
 };
 use rstest::rstest;
 use std::collections::HashMap;
+use std::sync::RwLock;
+use std::thread;
+use std::time::Duration;
 use uuid::Uuid;
 
 const MB: usize = 1024 * 1024;
@@ -128,6 +137,39 @@ fn scrub_compiles_on_cdc_map_but_returns_error(
     assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidInput)
 }
 
+#[rstest]
+#[case(DiskDatabase::init_on_regular_file(generate_unique_filename(), FILE_SIZE, true).unwrap())]
+#[case(DiskDatabase::init_on_regular_file(generate_unique_filename(), FILE_SIZE, false).unwrap())]
+#[case(HashMap::default())]
+fn copy_scrubber_moves_chunks_to_target_map(
+    #[case] db: impl IterableDatabase<[u8; 32], DataContainer<[u8; 32]>>,
+) {
+    let data = (0..2 * MB)
+        .map(|i| ((i / 8192) % 13) as u8)
+        .collect::<Vec<_>>();
+
+    let mut fs = FileSystem::new_with_scrubber(
+        db,
+        HashMap::default(),
+        Box::new(CopyScrubber),
+        Sha256Hasher::default(),
+    );
+    let mut fh = fs.create_file("file", FSChunker::new(8192)).unwrap();
+    fs.write_to_file(&mut fh, &data).unwrap();
+    fs.close_file(fh).unwrap();
+
+    let measurements = fs.scrub().unwrap();
+    assert_eq!(measurements.processed_data, 13 * 8192);
+    assert_eq!(fs.storage_iterator().count(), 13);
+    assert!(fs.storage_iterator().all(|pair| {
+        let (hash, container) = pair.unwrap();
+        matches!(container.extract(), Data::TargetChunk(keys) if keys == &[*hash])
+    }));
+
+    let fh = fs.open_file_readonly("file").unwrap();
+    assert_eq!(fs.read_file_complete(&fh).unwrap(), data);
+}
+
 #[rstest]
 #[case(DiskDatabase::init_on_regular_file(generate_unique_filename(), FILE_SIZE, true).unwrap())]
 #[case(DiskDatabase::init_on_regular_file(generate_unique_filename(), FILE_SIZE, false).unwrap())]
@@ -180,13 +222,16 @@ fn dedup_ratio_is_correct_for_fixed_size_chunker(
     let mut fh = fs.create_file("file", FSChunker::new(CHUNK_SIZE)).unwrap();
     fs.write_to_file(&mut fh, &data).unwrap();
     fs.close_file(fh).unwrap();
-    assert_relative_eq!(fs.cdc_dedup_ratio(), MB as f64 / CHUNK_SIZE as f64);
+    assert_relative_eq!(fs.cdc_dedup_ratio().unwrap(), MB as f64 / CHUNK_SIZE as f64);
 
     // second write, same data => 2 MBs, 1 chunk
     let mut fh = fs.open_file("file", FSChunker::new(CHUNK_SIZE)).unwrap();
     fs.write_to_file(&mut fh, &data).unwrap();
     fs.close_file(fh).unwrap();
-    assert_relative_eq!(fs.cdc_dedup_ratio(), (2 * MB) as f64 / CHUNK_SIZE as f64);
+    assert_relative_eq!(
+        fs.cdc_dedup_ratio().unwrap(),
+        (2 * MB) as f64 / CHUNK_SIZE as f64
+    );
 
     // third write, different data => 3 MBs, 2 chunks
     let new_data = vec![20; MB];
@@ -195,7 +240,7 @@ fn dedup_ratio_is_correct_for_fixed_size_chunker(
     fs.close_file(fh).unwrap();
 
     assert_relative_eq!(
-        fs.cdc_dedup_ratio(),
+        fs.cdc_dedup_ratio().unwrap(),
         (3 * MB) as f64 / (CHUNK_SIZE * 2) as f64
     );
 }
@@ -294,3 +339,986 @@ fn write_from_stream_buf_reader(#[case] db: impl Database<Vec<u8>, DataContainer
     assert_eq!(read.len(), MB);
     assert_eq!(read, [1; MB]);
 }
+
+#[rstest]
+#[case(DiskDatabase::init_on_regular_file(generate_unique_filename(), FILE_SIZE, true).unwrap())]
+#[case(DiskDatabase::init_on_regular_file(generate_unique_filename(), FILE_SIZE, false).unwrap())]
+#[case(HashMap::default())]
+fn deleted_file_chunks_are_collected_only_when_unreferenced(
+    #[case] db: impl IterableDatabase<Vec<u8>, DataContainer<()>>,
+) {
+    let mut fs = create_cdc_filesystem(db, SimpleHasher);
+
+    let shared = vec![1; MB];
+    let unique = vec![2; MB];
+
+    let mut fh = fs.create_file("first", FSChunker::new(4096)).unwrap();
+    fs.write_to_file(&mut fh, &shared).unwrap();
+    fs.write_to_file(&mut fh, &unique).unwrap();
+    fs.close_file(fh).unwrap();
+
+    let mut fh = fs.create_file("second", FSChunker::new(4096)).unwrap();
+    fs.write_to_file(&mut fh, &shared).unwrap();
+    fs.close_file(fh).unwrap();
+
+    fs.delete_file("first").unwrap();
+    assert!(!fs.file_exists("first"));
+    assert_eq!(
+        fs.delete_file("first").unwrap_err().kind(),
+        io::ErrorKind::NotFound
+    );
+
+    // only the chunk of `unique` is not referenced anymore
+    assert_eq!(fs.collect_garbage().unwrap(), 1);
+    assert_eq!(fs.collect_garbage().unwrap(), 0);
+
+    let fh = fs.open_file_readonly("second").unwrap();
+    assert_eq!(fs.read_file_complete(&fh).unwrap(), shared);
+
+    fs.delete_file("second").unwrap();
+    assert_eq!(fs.collect_garbage().unwrap(), 1);
+    assert_eq!(
+        fs.read_file_complete(&fh).unwrap_err().kind(),
+        io::ErrorKind::NotFound
+    );
+}
+
+#[test]
+fn disk_database_space_is_reclaimed_after_garbage_collection() {
+    let db =
+        DiskDatabase::init_on_regular_file(generate_unique_filename(), FILE_SIZE, false).unwrap();
+    let mut fs = create_cdc_filesystem(db, Sha256Hasher::default());
+
+    let first = (0..2 * MB).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+    let second = (0..2 * MB).map(|i| (i % 241) as u8).collect::<Vec<_>>();
+    for (name, data) in [("first", &first), ("second", &second)] {
+        let mut fh = fs.create_file(name, SuperChunker::default()).unwrap();
+        fs.write_to_file(&mut fh, data).unwrap();
+        fs.close_file(fh).unwrap();
+    }
+
+    fs.delete_file("first").unwrap();
+    assert!(fs.collect_garbage().unwrap() > 0);
+    assert!(fs.database().garbage_size() > MB as u64);
+
+    let measurements = fs.database_mut().compact().unwrap();
+    assert!(measurements.reclaimed_space > MB as u64);
+    assert!(fs.database().garbage_size() < 4096);
+    assert!(fs.database().write_amplification() > 1.0);
+
+    let fh = fs.open_file_readonly("second").unwrap();
+    assert_eq!(fs.read_file_complete(&fh).unwrap(), second);
+}
+
+#[rstest]
+#[case(DiskDatabase::init_on_regular_file(generate_unique_filename(), FILE_SIZE, true).unwrap())]
+#[case(DiskDatabase::init_on_regular_file(generate_unique_filename(), FILE_SIZE, false).unwrap())]
+#[case(HashMap::default())]
+fn read_at_and_seek_return_requested_ranges(#[case] db: impl Database<Vec<u8>, DataContainer<()>>) {
+    let mut fs = create_cdc_filesystem(db, SimpleHasher);
+
+    let data = (0..3 * MB).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+    let mut fh = fs.create_file("file", FSChunker::new(4096)).unwrap();
+    fs.write_to_file(&mut fh, &data).unwrap();
+    fs.close_file(fh).unwrap();
+
+    let mut fh = fs.open_file_readonly("file").unwrap();
+    assert_eq!(fs.read_at(&fh, 5000, 10000).unwrap(), data[5000..15000]);
+    assert_eq!(
+        fs.read_at(&fh, 3 * MB - 10, 100).unwrap(),
+        data[3 * MB - 10..]
+    );
+    assert!(fs.read_at(&fh, 3 * MB, 100).unwrap().is_empty());
+
+    assert_eq!(
+        fs.seek(&mut fh, io::SeekFrom::Start(MB as u64 + 7))
+            .unwrap(),
+        MB as u64 + 7
+    );
+    assert_eq!(
+        fs.read_from_file(&mut fh).unwrap(),
+        data[MB + 7..2 * MB + 7]
+    );
+
+    let end = fs.seek(&mut fh, io::SeekFrom::End(-100)).unwrap();
+    assert_eq!(end, 3 * MB as u64 - 100);
+    assert_eq!(fs.read_from_file(&mut fh).unwrap(), data[3 * MB - 100..]);
+    assert!(fs.read_from_file(&mut fh).unwrap().is_empty());
+
+    let result = fs.seek(&mut fh, io::SeekFrom::Current(-(4 * MB as i64)));
+    assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+}
+
+#[rstest]
+#[case(DiskDatabase::init_on_regular_file(generate_unique_filename(), FILE_SIZE, true).unwrap())]
+#[case(DiskDatabase::init_on_regular_file(generate_unique_filename(), FILE_SIZE, false).unwrap())]
+#[case(HashMap::default())]
+fn io_adapters_can_be_used_with_std_io(#[case] db: impl Database<Vec<u8>, DataContainer<()>>) {
+    let mut fs = create_cdc_filesystem(db, SimpleHasher);
+
+    let data = (0..2 * MB + 100)
+        .map(|i| (i % 251) as u8)
+        .collect::<Vec<_>>();
+    let mut fh = fs.create_file("file", FSChunker::new(4096)).unwrap();
+    {
+        let mut writer = fs.writer(&mut fh);
+        let mut reader = io::BufReader::with_capacity(8192, &data[..]);
+        assert_eq!(
+            io::copy(&mut reader, &mut writer).unwrap(),
+            data.len() as u64
+        );
+        writer.flush().unwrap();
+    }
+    fs.close_file(fh).unwrap();
+
+    let fh = fs.open_file_readonly("file").unwrap();
+    let mut read = vec![];
+    fs.reader(&fh).read_to_end(&mut read).unwrap();
+    assert_eq!(read, data);
+
+    let mut reader = fs.reader(&fh);
+    let mut buffer = [0; 10];
+    reader.read_exact(&mut buffer).unwrap();
+    reader.seek(io::SeekFrom::Current(MB as i64)).unwrap();
+    assert_eq!(reader.fill_buf().unwrap()[..10], data[MB + 10..MB + 20]);
+    assert_eq!(
+        reader.seek(io::SeekFrom::End(-50)).unwrap(),
+        2 * MB as u64 + 50
+    );
+    let mut tail = vec![];
+    reader.read_to_end(&mut tail).unwrap();
+    assert_eq!(tail, data[2 * MB + 50..]);
+}
+
+#[rstest]
+#[case(DiskDatabase::init_on_regular_file(generate_unique_filename(), FILE_SIZE, true).unwrap())]
+#[case(DiskDatabase::init_on_regular_file(generate_unique_filename(), FILE_SIZE, false).unwrap())]
+#[case(HashMap::default())]
+fn write_at_overwrites_and_extends_file(
+    #[case] db: impl IterableDatabase<Vec<u8>, DataContainer<()>>,
+) {
+    let mut fs = create_cdc_filesystem(db, SimpleHasher);
+
+    let mut expected = (0..2 * MB).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+    let mut fh = fs.create_file("file", SuperChunker::default()).unwrap();
+    fs.write_to_file(&mut fh, &expected).unwrap();
+
+    let patch = vec![7; 10000];
+    fs.write_at(&mut fh, 5000, &patch).unwrap();
+    expected[5000..15000].copy_from_slice(&patch);
+    assert_eq!(fh.offset(), 15000);
+
+    let tail = vec![9; 3000];
+    fs.write_at(&mut fh, 2 * MB - 1000, &tail).unwrap();
+    expected.truncate(2 * MB - 1000);
+    expected.extend_from_slice(&tail);
+
+    fs.write_at(&mut fh, 2 * MB + 2000, &[5; 10]).unwrap();
+    expected.extend_from_slice(&[5; 10]);
+
+    fs.seek(&mut fh, io::SeekFrom::Start(MB as u64)).unwrap();
+    fs.writer(&mut fh).write_all(&[3; 100]).unwrap();
+    expected[MB..MB + 100].copy_from_slice(&[3; 100]);
+    fs.close_file(fh).unwrap();
+
+    let fh = fs.open_file_readonly("file").unwrap();
+    assert_eq!(fs.read_file_complete(&fh).unwrap(), expected);
+    assert_eq!(
+        fs.read_at(&fh, MB - 50, 200).unwrap(),
+        expected[MB - 50..MB + 150]
+    );
+    assert!(fs.collect_garbage().unwrap() > 0);
+    assert_eq!(fs.read_file_complete(&fh).unwrap(), expected);
+}
+
+#[rstest]
+#[case(DiskDatabase::init_on_regular_file(generate_unique_filename(), FILE_SIZE, true).unwrap())]
+#[case(DiskDatabase::init_on_regular_file(generate_unique_filename(), FILE_SIZE, false).unwrap())]
+#[case(HashMap::default())]
+fn truncate_and_holes_are_read_as_zeros(
+    #[case] db: impl IterableDatabase<Vec<u8>, DataContainer<()>>,
+) {
+    let mut fs = create_cdc_filesystem(db, SimpleHasher);
+    fs.set_zero_chunk_threshold(Some(4096));
+
+    let data = [vec![1; MB], vec![0; MB], vec![2; MB]].concat();
+    let mut fh = fs.create_file("file", FSChunker::new(4096)).unwrap();
+    fs.write_to_file(&mut fh, &data).unwrap();
+
+    // zero chunks are neither stored nor counted as written data
+    assert_relative_eq!(
+        fs.cdc_dedup_ratio().unwrap(),
+        (2 * MB) as f64 / (2 * 4096) as f64
+    );
+
+    fs.write_at(&mut fh, 4 * MB, &[3; 100]).unwrap();
+    fs.close_file(fh).unwrap();
+
+    let fh = fs.open_file_readonly("file").unwrap();
+    let mut expected = [data, vec![0; MB], vec![3; 100]].concat();
+    assert_eq!(fs.read_file_complete(&fh).unwrap(), expected);
+
+    fs.truncate("file", 3 * MB - 10).unwrap();
+    expected.truncate(3 * MB - 10);
+    assert_eq!(fs.read_file_complete(&fh).unwrap(), expected);
+
+    fs.truncate("file", 3 * MB + 50).unwrap();
+    expected.resize(3 * MB + 50, 0);
+    assert_eq!(fs.read_file_complete(&fh).unwrap(), expected);
+    assert_eq!(
+        fs.read_at(&fh, 3 * MB - 20, 30).unwrap(),
+        expected[3 * MB - 20..3 * MB + 10]
+    );
+
+    fs.truncate("file", 0).unwrap();
+    assert!(fs.read_file_complete(&fh).unwrap().is_empty());
+    assert_eq!(fs.collect_garbage().unwrap(), 4);
+}
+
+#[test]
+fn saved_file_system_can_be_loaded() {
+    let dir = tempfile::tempdir().unwrap();
+
+    let first = (0..2 * MB).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+    let second = vec![4; MB];
+
+    let mut fs = create_cdc_filesystem(HashMap::default(), Sha256Hasher::default());
+    fs.create_dir("empty").unwrap();
+    let mut fh = fs.create_file("first", SuperChunker::default()).unwrap();
+    fs.write_to_file(&mut fh, &first).unwrap();
+    fs.close_file(fh).unwrap();
+    let mut fh = fs.create_file("second", FSChunker::default()).unwrap();
+    fs.write_to_file(&mut fh, &second).unwrap();
+    fs.close_file(fh).unwrap();
+    fs.save(dir.path()).unwrap();
+
+    let chunkers = ChunkerRegistry::new()
+        .register(SuperChunker::default)
+        .register(FSChunker::default);
+    let mut loaded: FileSystem<HashMap<_, _>, _, (), HashMap<_, _>> =
+        FileSystem::load(dir.path(), Sha256Hasher::default(), chunkers).unwrap();
+
+    let mut files = loaded.list_files();
+    files.sort();
+    assert_eq!(files, ["first", "second"]);
+    assert!(loaded.dir_exists("empty"));
+    assert_relative_eq!(
+        loaded.cdc_dedup_ratio().unwrap(),
+        fs.cdc_dedup_ratio().unwrap()
+    );
+
+    let fh = loaded.open_file_readonly("first").unwrap();
+    assert_eq!(loaded.read_file_complete(&fh).unwrap(), first);
+
+    // files are appended to with the chunkers they were written with
+    let mut fh = loaded.reopen_file("second").unwrap();
+    loaded.write_to_file(&mut fh, &second).unwrap();
+    loaded.close_file(fh).unwrap();
+    let metadata = loaded.metadata("second").unwrap();
+    assert_eq!(metadata.chunker, fs.metadata("second").unwrap().chunker);
+    assert_eq!(metadata.size, 2 * second.len());
+
+    // reference counts are restored too
+    loaded.delete_file("first").unwrap();
+    assert!(loaded.collect_garbage().unwrap() > 0);
+    let fh = loaded.open_file_readonly("second").unwrap();
+    assert_eq!(
+        loaded.read_file_complete(&fh).unwrap(),
+        [second.clone(), second].concat()
+    );
+
+    let unregistered = FileSystem::<HashMap<_, _>, _, (), HashMap<_, _>>::load(
+        dir.path(),
+        Sha256Hasher::default(),
+        ChunkerRegistry::new(),
+    )
+    .unwrap();
+    let result = unregistered.reopen_file("first");
+    assert_eq!(result.err().unwrap().kind(), io::ErrorKind::NotFound);
+}
+
+#[test]
+fn file_system_on_disk_database_can_be_loaded() {
+    let dir = tempfile::tempdir().unwrap();
+    let device = tempfile::tempdir().unwrap();
+    let data = (0..2 * MB).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+
+    let db = DiskDatabase::create(device.path().join("device"), FILE_SIZE, false).unwrap();
+    let mut fs = create_cdc_filesystem(db, Sha256Hasher::default());
+    let mut fh = fs.create_file("file", SuperChunker::default()).unwrap();
+    fs.write_to_file(&mut fh, &data).unwrap();
+    fs.close_file(fh).unwrap();
+    // the database itself stays on its device, only the path to it is saved
+    fs.save(dir.path()).unwrap();
+    let dedup_ratio = fs.cdc_dedup_ratio().unwrap();
+    // only one database may write to the device at a time
+    drop(fs);
+
+    let mut loaded: FileSystem<DiskDatabase<_, _>, _, (), HashMap<_, _>> =
+        FileSystem::load(dir.path(), Sha256Hasher::default(), ChunkerRegistry::new()).unwrap();
+    let fh = loaded.open_file_readonly("file").unwrap();
+    assert_eq!(loaded.read_file_complete(&fh).unwrap(), data);
+    assert_relative_eq!(loaded.cdc_dedup_ratio().unwrap(), dedup_ratio);
+
+    let mut fh = loaded.create_file("copy", SuperChunker::default()).unwrap();
+    loaded.write_to_file(&mut fh, &data).unwrap();
+    loaded.close_file(fh).unwrap();
+    let fh = loaded.open_file_readonly("copy").unwrap();
+    assert_eq!(loaded.read_file_complete(&fh).unwrap(), data);
+}
+
+#[test]
+fn directories_can_be_created_listed_and_removed() {
+    let mut fs = create_cdc_filesystem(HashMap::default(), SimpleHasher);
+    let result = fs.create_file("src/main.rs", FSChunker::default());
+    assert_eq!(result.err().unwrap().kind(), io::ErrorKind::NotFound);
+
+    fs.create_dir_all("src/system").unwrap();
+    let data = vec![3; MB];
+    for name in ["src/lib.rs", "src/system/mod.rs"] {
+        let mut fh = fs.create_file(name, FSChunker::new(4096)).unwrap();
+        fs.write_to_file(&mut fh, &data).unwrap();
+        fs.close_file(fh).unwrap();
+    }
+
+    let entries = fs.read_dir("/src/").unwrap();
+    let names = entries.iter().map(|e| (e.name.as_str(), e.is_dir));
+    assert!(names.eq([("lib.rs", false), ("system", true)]));
+    assert_eq!(fs.read_dir("").unwrap().len(), 1);
+    assert_eq!(
+        fs.read_dir("src/lib.rs").unwrap_err().kind(),
+        io::ErrorKind::NotADirectory
+    );
+
+    let fh = fs.open_file_readonly("src/system/mod.rs").unwrap();
+    assert_eq!(fs.read_file_complete(&fh).unwrap(), data);
+    assert_relative_eq!(fs.cdc_dedup_ratio().unwrap(), 2.0 * MB as f64 / 4096.0);
+
+    assert_eq!(
+        fs.remove_dir("src").unwrap_err().kind(),
+        io::ErrorKind::DirectoryNotEmpty
+    );
+    fs.delete_file("src/system/mod.rs").unwrap();
+    fs.remove_dir("src/system").unwrap();
+    assert!(!fs.dir_exists("src/system"));
+    assert!(fs.dir_exists("src"));
+}
+
+#[test]
+fn metadata_describes_file_contents() {
+    let mut fs = create_cdc_filesystem(HashMap::default(), SimpleHasher);
+    let mut fh = fs.create_file("file", FSChunker::new(4096)).unwrap();
+    let metadata = fs.metadata("file").unwrap();
+    assert_eq!(metadata.size, 0);
+    assert_eq!(metadata.chunker, None);
+
+    let mut data = vec![1; MB];
+    data.extend(vec![2; MB]);
+    fs.write_to_file(&mut fh, &data).unwrap();
+    fs.close_file(fh).unwrap();
+
+    let metadata = fs.metadata("file").unwrap();
+    assert_eq!(metadata.size, 2 * MB);
+    assert_eq!(metadata.span_count, 2 * MB / 4096);
+    assert_eq!(metadata.unique_chunks, 2);
+    assert_eq!(
+        metadata.chunker,
+        Some(format!("{:?}", FSChunker::new(4096)))
+    );
+    assert!(metadata.modified >= metadata.created);
+
+    fs.truncate("file", MB + 4096 + 1).unwrap();
+    let truncated = fs.metadata("file").unwrap();
+    assert_eq!(truncated.size, MB + 4096 + 1);
+    assert_eq!(truncated.unique_chunks, 3);
+    assert_eq!(truncated.chunker, metadata.chunker);
+    assert!(truncated.modified >= metadata.modified);
+
+    assert_eq!(
+        fs.metadata("missing").unwrap_err().kind(),
+        io::ErrorKind::NotFound
+    );
+}
+
+#[rstest]
+#[case(DiskDatabase::init_on_regular_file(generate_unique_filename(), FILE_SIZE, true).unwrap())]
+#[case(DiskDatabase::init_on_regular_file(generate_unique_filename(), FILE_SIZE, false).unwrap())]
+#[case(HashMap::default())]
+fn cloned_files_share_chunks(#[case] db: impl IterableDatabase<Vec<u8>, DataContainer<()>>) {
+    let mut fs = create_cdc_filesystem(db, SimpleHasher);
+
+    // every chunk is unique
+    let data = (0..MB).map(|i| (i / 4096) as u8).collect::<Vec<_>>();
+    let patch = (0..4096).map(|i| i as u8).collect::<Vec<_>>();
+    let mut fh = fs.create_file("original", FSChunker::new(4096)).unwrap();
+    fs.write_to_file(&mut fh, &data).unwrap();
+    fs.close_file(fh).unwrap();
+
+    let ratio = fs.cdc_dedup_ratio().unwrap();
+    fs.clone_file("original", "copy").unwrap();
+    assert_eq!(
+        fs.clone_file("original", "copy").unwrap_err().kind(),
+        io::ErrorKind::AlreadyExists
+    );
+    assert_relative_eq!(fs.cdc_dedup_ratio().unwrap(), 2.0 * ratio);
+
+    let mut fh = fs.open_file("copy", FSChunker::new(4096)).unwrap();
+    fs.write_at(&mut fh, 0, &patch).unwrap();
+    fs.close_file(fh).unwrap();
+
+    fs.delete_file("original").unwrap();
+    // only the first chunk of the original is not referenced by the copy
+    assert_eq!(fs.collect_garbage().unwrap(), 1);
+
+    let fh = fs.open_file_readonly("copy").unwrap();
+    assert_eq!(fs.read_at(&fh, 0, 4096).unwrap(), patch);
+    assert_eq!(fs.read_at(&fh, 4096, MB).unwrap(), data[4096..]);
+}
+
+#[rstest]
+#[case(DiskDatabase::init_on_regular_file(generate_unique_filename(), FILE_SIZE, true).unwrap())]
+#[case(DiskDatabase::init_on_regular_file(generate_unique_filename(), FILE_SIZE, false).unwrap())]
+#[case(HashMap::default())]
+fn snapshots_can_be_restored(#[case] db: impl IterableDatabase<Vec<u8>, DataContainer<()>>) {
+    let mut fs = create_cdc_filesystem(db, SimpleHasher);
+
+    let first = vec![1; MB];
+    let second = vec![2; MB];
+
+    fs.create_dir("dir").unwrap();
+    let mut fh = fs.create_file("dir/file", FSChunker::new(4096)).unwrap();
+    fs.write_to_file(&mut fh, &first).unwrap();
+    fs.close_file(fh).unwrap();
+    fs.snapshot("v1").unwrap();
+    assert_eq!(
+        fs.snapshot("v1").unwrap_err().kind(),
+        io::ErrorKind::AlreadyExists
+    );
+
+    let mut fh = fs.create_file("dir/file", FSChunker::new(4096)).unwrap();
+    fs.write_to_file(&mut fh, &second).unwrap();
+    fs.close_file(fh).unwrap();
+    fs.create_file("other", FSChunker::new(4096)).unwrap();
+
+    // the chunk of `first` is still referenced by the snapshot
+    assert_eq!(fs.collect_garbage().unwrap(), 0);
+
+    fs.restore_snapshot("v1").unwrap();
+    assert!(!fs.file_exists("other"));
+    let fh = fs.open_file_readonly("dir/file").unwrap();
+    assert_eq!(fs.read_file_complete(&fh).unwrap(), first);
+    assert_eq!(fs.collect_garbage().unwrap(), 1);
+
+    fs.delete_snapshot("v1").unwrap();
+    assert!(fs.list_snapshots().is_empty());
+    assert_eq!(fs.collect_garbage().unwrap(), 0);
+    fs.delete_file("dir/file").unwrap();
+    assert_eq!(fs.collect_garbage().unwrap(), 1);
+    assert_eq!(
+        fs.restore_snapshot("v1").unwrap_err().kind(),
+        io::ErrorKind::NotFound
+    );
+}
+
+#[test]
+fn renamed_files_keep_their_handles() {
+    let mut fs = create_cdc_filesystem(HashMap::default(), SimpleHasher);
+    fs.create_dir("dir").unwrap();
+
+    let first = vec![1; MB];
+    let second = vec![2; MB];
+    let mut fh = fs.create_file("first", FSChunker::new(4096)).unwrap();
+    fs.write_to_file(&mut fh, &first).unwrap();
+    let mut replaced = fs.create_file("dir/second", FSChunker::new(4096)).unwrap();
+    fs.write_to_file(&mut replaced, &second).unwrap();
+
+    assert_eq!(
+        fs.rename("first", "missing/second").unwrap_err().kind(),
+        io::ErrorKind::NotFound
+    );
+    assert_eq!(
+        fs.rename("first", "dir").unwrap_err().kind(),
+        io::ErrorKind::IsADirectory
+    );
+    assert!(fs.file_exists("first"));
+
+    fs.rename("first", "dir/second").unwrap();
+    assert!(!fs.file_exists("first"));
+    assert_eq!(fs.file_name(&fh).unwrap(), "dir/second");
+
+    fs.write_to_file(&mut fh, &first).unwrap();
+    let fh = fs.open_file_readonly("dir/second").unwrap();
+    assert_eq!(
+        fs.read_file_complete(&fh).unwrap(),
+        [first.clone(), first].concat()
+    );
+
+    assert_eq!(
+        fs.write_to_file(&mut replaced, &second).unwrap_err().kind(),
+        io::ErrorKind::NotFound
+    );
+    // the chunk of the replaced file is not referenced anymore
+    assert_eq!(fs.collect_garbage().unwrap(), 1);
+}
+
+#[rstest]
+#[case(ShardedDatabase::new(vec![HashMap::default(); 8]))]
+#[case(RwLock::new(HashMap::default()))]
+fn shared_file_system_accepts_concurrent_writers(
+    #[case] db: impl ConcurrentDatabase<[u8; 32], DataContainer<()>>,
+) {
+    let fs = SharedFileSystem::new(db, Sha256Hasher::default);
+    fs.create_dir_all("backup").unwrap();
+
+    let shared = (0..2 * MB).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+    thread::scope(|s| {
+        for i in 0..4u8 {
+            let (fs, shared) = (&fs, &shared);
+            s.spawn(move || {
+                let name = format!("backup/{i}");
+                let mut fh = fs.create_file(&name, SuperChunker::default()).unwrap();
+                fs.write_to_file(&mut fh, shared).unwrap();
+                fs.write_from_stream(&mut fh, &vec![i; MB][..]).unwrap();
+                fs.close_file(fh).unwrap();
+            });
+        }
+    });
+
+    assert_eq!(fs.list_files().len(), 4);
+    assert_eq!(fs.size_written(), 4 * 3 * MB);
+    for i in 0..4u8 {
+        let fh = fs.open_file_readonly(format!("backup/{i}")).unwrap();
+        let data = fs.read_file_complete(&fh).unwrap();
+        assert_eq!(data[..2 * MB], shared);
+        assert!(data[2 * MB..].iter().all(|&b| b == i));
+        assert_eq!(
+            fs.read_at(&fh, 2 * MB - 1, 2).unwrap(),
+            [shared[2 * MB - 1], i]
+        );
+    }
+
+    let mut readonly = fs.open_file_readonly("backup/0").unwrap();
+    assert_eq!(
+        fs.write_to_file(&mut readonly, &shared).unwrap_err().kind(),
+        io::ErrorKind::PermissionDenied
+    );
+    fs.delete_file("backup/0").unwrap();
+    assert_eq!(fs.size_written(), 3 * 3 * MB);
+}
+
+#[rstest]
+#[case(ShardedDatabase::new(vec![HashMap::default(); 8]))]
+#[case(RwLock::new(HashMap::default()))]
+fn shared_file_system_replaces_files_and_collects_garbage(
+    #[case] db: impl ConcurrentDatabase<[u8; 32], DataContainer<()>>,
+) {
+    let fs = SharedFileSystem::new(db, Sha256Hasher::default);
+    let first = (0..3 * MB).map(|i| (i / MB) as u8).collect::<Vec<_>>();
+    let second = vec![7; MB];
+
+    let mut replaced = fs.create_file("file", FSChunker::new(MB)).unwrap();
+    fs.write_to_file(&mut replaced, &first).unwrap();
+    let mut fh = fs.create_file("file", FSChunker::new(MB)).unwrap();
+    assert_eq!(fs.size_written(), 0);
+    assert_eq!(
+        fs.write_to_file(&mut replaced, &second).unwrap_err().kind(),
+        io::ErrorKind::NotFound
+    );
+
+    fs.write_to_file(&mut fh, &second).unwrap();
+    assert_eq!(fs.size_written(), MB);
+    // the three chunks of the replaced file are not referenced anymore
+    assert_eq!(fs.collect_garbage().unwrap(), 3);
+    assert_eq!(fs.collect_garbage().unwrap(), 0);
+    assert_eq!(fs.read_file_complete(&fh).unwrap(), second);
+}
+
+#[rstest]
+#[case(DiskDatabase::init_on_regular_file(generate_unique_filename(), FILE_SIZE, true).unwrap())]
+#[case(DiskDatabase::init_on_regular_file(generate_unique_filename(), FILE_SIZE, false).unwrap())]
+#[case(HashMap::default())]
+fn pipelined_writes_match_serial_writes(
+    #[case] db: impl Database<[u8; 32], DataContainer<()>> + Send,
+) {
+    let mut fs = create_cdc_filesystem(db, Sha256Hasher::default());
+
+    let mut state = 1u64;
+    let random = (0..3 * MB + 12345)
+        .map(|_| {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1);
+            (state >> 56) as u8
+        })
+        .collect::<Vec<_>>();
+    let data = [random.clone(), vec![0; MB], random].concat();
+
+    for (name, pipeline) in [
+        ("serial", None),
+        ("pipelined", Some(Pipeline::new(4, Sha256Hasher::default))),
+    ] {
+        fs.set_pipeline(pipeline);
+        let mut fh = fs.create_file(name, SuperChunker::default()).unwrap();
+        fs.write_to_file(&mut fh, &data).unwrap();
+        fs.write_from_stream(&mut fh, &data[..MB]).unwrap();
+        let measurements = fs.close_file(fh).unwrap();
+        assert!(measurements.chunk_time() > Duration::default());
+        assert!(measurements.hash_time() > Duration::default());
+    }
+
+    let serial = fs.metadata("serial").unwrap();
+    let pipelined = fs.metadata("pipelined").unwrap();
+    assert_eq!(pipelined.size, serial.size);
+    assert_eq!(pipelined.span_count, serial.span_count);
+    assert_eq!(pipelined.unique_chunks, serial.unique_chunks);
+
+    let fh = fs.open_file_readonly("pipelined").unwrap();
+    assert_eq!(
+        fs.read_file_complete(&fh).unwrap(),
+        [&data[..], &data[..MB]].concat()
+    );
+}
+
+#[rstest]
+#[case(DiskDatabase::init_on_regular_file(generate_unique_filename(), FILE_SIZE, true).unwrap())]
+#[case(DiskDatabase::init_on_regular_file(generate_unique_filename(), FILE_SIZE, false).unwrap())]
+#[case(HashMap::default())]
+fn file_contents_can_be_streamed(#[case] db: impl Database<Vec<u8>, DataContainer<()>>) {
+    let mut fs = create_cdc_filesystem(db, SimpleHasher);
+    fs.set_zero_chunk_threshold(Some(4096));
+
+    let data = [
+        (0..MB).map(|i| (i / 4096) as u8).collect(),
+        vec![0; MB],
+        vec![2; MB + 10],
+    ]
+    .concat();
+    let mut fh = fs.create_file("file", FSChunker::new(4096)).unwrap();
+    fs.write_to_file(&mut fh, &data).unwrap();
+    fs.close_file(fh).unwrap();
+
+    let fh = fs.open_file_readonly("file").unwrap();
+    let mut streamed = vec![];
+    assert_eq!(
+        fs.read_into(&fh, 100, 2 * MB, &mut streamed).unwrap(),
+        2 * MB
+    );
+    assert_eq!(streamed, data[100..2 * MB + 100]);
+
+    streamed.clear();
+    let tail = fs.read_into(&fh, 3 * MB, MB, &mut streamed).unwrap();
+    assert_eq!(tail, 10);
+    assert_eq!(streamed, data[3 * MB..]);
+    assert_eq!(fs.read_into(&fh, 4 * MB, MB, &mut streamed).unwrap(), 0);
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("file");
+    fs.write_file_to_disk("file", &path).unwrap();
+    assert_eq!(std::fs::read(&path).unwrap(), data);
+    assert_eq!(
+        fs.write_file_to_disk("file", &path).unwrap_err().kind(),
+        io::ErrorKind::AlreadyExists
+    );
+}
+
+/// Weak hasher that uses the first byte of the chunk as its hash.
+struct FirstByteHasher;
+
+impl Hasher for FirstByteHasher {
+    type Hash = u8;
+
+    fn hash(&mut self, data: &[u8]) -> Self::Hash {
+        data[0]
+    }
+
+    fn len(&self, _: &Self::Hash) -> usize {
+        1
+    }
+}
+
+#[test]
+fn collisions_are_detected_in_paranoid_mode() {
+    let mut fs = create_cdc_filesystem(HashMap::default(), FirstByteHasher);
+
+    // all chunks start with the same byte, but only the first two are equal
+    let data = (0..4 * 4096)
+        .map(|i| {
+            if i % 4096 == 0 {
+                0
+            } else {
+                (i / 4096 / 2) as u8
+            }
+        })
+        .collect::<Vec<_>>();
+    let mut fh = fs.create_file("unchecked", FSChunker::new(4096)).unwrap();
+    fs.write_to_file(&mut fh, &data).unwrap();
+    assert_eq!(fs.close_file(fh).unwrap().collisions(), 0);
+
+    fs.set_collision_policy(Some(CollisionPolicy::Count));
+    let mut fh = fs.create_file("counted", FSChunker::new(4096)).unwrap();
+    fs.write_to_file(&mut fh, &data[..2 * 4096]).unwrap();
+    fs.write_to_file(&mut fh, &data[2 * 4096..]).unwrap();
+    assert_eq!(fs.close_file(fh).unwrap().collisions(), 2);
+
+    fs.set_collision_policy(Some(CollisionPolicy::Fail));
+    let mut fh = fs.create_file("failed", FSChunker::new(4096)).unwrap();
+    fs.write_to_file(&mut fh, &data[..2 * 4096]).unwrap();
+    assert_eq!(
+        fs.write_to_file(&mut fh, &data).unwrap_err().kind(),
+        io::ErrorKind::InvalidData
+    );
+
+    fs.set_pipeline(Some(Pipeline::new(2, || FirstByteHasher)));
+    let mut fh = fs.create_file("pipelined", FSChunker::new(4096)).unwrap();
+    assert_eq!(
+        fs.write_to_file(&mut fh, &data).unwrap_err().kind(),
+        io::ErrorKind::InvalidData
+    );
+}
+
+#[rstest]
+#[case(Lz4Compressor)]
+#[case(ZstdCompressor::default())]
+fn compressed_chunks_are_read_back(#[case] compressor: impl Compressor + 'static) {
+    // compressible data with repeating chunks
+    let data = (0..8 * MB)
+        .map(|i| ((i / 4096) % 17 + (i % 64) / 16) as u8)
+        .collect::<Vec<_>>();
+
+    let mut plain = create_cdc_filesystem(HashMap::default(), SimpleHasher);
+    let mut fh = plain.create_file("file", FSChunker::new(4096)).unwrap();
+    plain.write_to_file(&mut fh, &data).unwrap();
+    plain.close_file(fh).unwrap();
+
+    let mut fs = create_cdc_filesystem(HashMap::default(), SimpleHasher);
+    fs.set_compressor(Some(compressor.into()));
+    fs.set_collision_policy(Some(CollisionPolicy::Fail));
+    let mut fh = fs.create_file("file", FSChunker::new(4096)).unwrap();
+    fs.write_to_file(&mut fh, &data).unwrap();
+    // already stored chunks must not be mistaken for collisions
+    fs.write_to_file(&mut fh, &data).unwrap();
+    let measurements = fs.close_file(fh).unwrap();
+    assert!(measurements.compress_time() > Duration::ZERO);
+
+    assert!(fs.compression_ratio().unwrap() > 1.0);
+    assert_relative_eq!(plain.compression_ratio().unwrap(), 1.0);
+    assert_relative_eq!(
+        fs.cdc_dedup_ratio().unwrap() / 2.0,
+        plain.cdc_dedup_ratio().unwrap()
+    );
+    assert_eq!(
+        fs.average_chunk_size().unwrap(),
+        plain.average_chunk_size().unwrap()
+    );
+
+    let fh = fs.open_file_readonly("file").unwrap();
+    let read = fs.read_file_complete(&fh).unwrap();
+    assert_eq!(read.len(), 2 * data.len());
+    assert_eq!(read[..data.len()], data);
+    assert_eq!(read[data.len()..], data);
+    assert!(fs.decompress_time() > Duration::ZERO);
+}
+
+#[test]
+fn encrypted_chunks_are_deduplicated() {
+    let dir = tempfile::tempdir().unwrap();
+    let data = (0..4 * MB)
+        .map(|i| ((i / 8192) % 13) as u8)
+        .collect::<Vec<_>>();
+
+    let mut plain = create_cdc_filesystem(HashMap::default(), Sha256Hasher::default());
+    let mut fh = plain.create_file("file", FSChunker::new(8192)).unwrap();
+    plain.write_to_file(&mut fh, &data).unwrap();
+    plain.close_file(fh).unwrap();
+
+    let mut fs = create_cdc_filesystem(HashMap::default(), Sha256Hasher::default());
+    fs.set_cipher(Some(Sha256Cipher.into()));
+    let mut fh = fs.create_file("file", FSChunker::new(8192)).unwrap();
+    fs.write_to_file(&mut fh, &data).unwrap();
+    let measurements = fs.close_file(fh).unwrap();
+    assert!(measurements.encrypt_time() > Duration::ZERO);
+
+    fs.set_pipeline(Some(Pipeline::new(2, Sha256Hasher::default)));
+    let mut fh = fs.create_file("pipelined", FSChunker::new(8192)).unwrap();
+    fs.write_to_file(&mut fh, &data).unwrap();
+    fs.close_file(fh).unwrap();
+
+    // same chunks are encrypted the same way, so they are still deduplicated
+    assert_relative_eq!(
+        fs.cdc_dedup_ratio().unwrap(),
+        2.0 * plain.cdc_dedup_ratio().unwrap()
+    );
+    // but none of them is stored as plaintext
+    let plaintexts = data.chunks(8192).collect::<Vec<_>>();
+    assert!(fs.storage_iterator().all(|pair| {
+        let (_, container) = pair.unwrap();
+        !plaintexts.contains(&container.unwrap_chunk().as_slice())
+    }));
+
+    for name in ["file", "pipelined"] {
+        let fh = fs.open_file_readonly(name).unwrap();
+        assert_eq!(fs.read_file_complete(&fh).unwrap(), data);
+    }
+    assert!(fs.decrypt_time() > Duration::ZERO);
+
+    // keys are saved with the file system, the cipher has to be set again
+    fs.save(dir.path()).unwrap();
+    let mut loaded: FileSystem<HashMap<_, _>, _, (), HashMap<_, _>> =
+        FileSystem::load(dir.path(), Sha256Hasher::default(), ChunkerRegistry::new()).unwrap();
+    let fh = loaded.open_file_readonly("file").unwrap();
+    assert_eq!(
+        loaded.read_file_complete(&fh).unwrap_err().kind(),
+        io::ErrorKind::InvalidInput
+    );
+    loaded.set_cipher(Some(Sha256Cipher.into()));
+    assert_eq!(loaded.read_file_complete(&fh).unwrap(), data);
+}
+
+#[rstest]
+#[case(Lz4Compressor)]
+#[case(ZstdCompressor::default())]
+fn encrypted_chunks_are_compressed_before_encryption(
+    #[case] compressor: impl Compressor + 'static,
+) {
+    // compressible data with repeating chunks
+    let data = (0..4 * MB)
+        .map(|i| ((i / 8192) % 13 + (i % 64) / 16) as u8)
+        .collect::<Vec<_>>();
+
+    let mut fs = create_cdc_filesystem(HashMap::default(), Sha256Hasher::default());
+    fs.set_cipher(Some(Sha256Cipher.into()));
+    fs.set_compressor(Some(compressor.into()));
+    fs.set_collision_policy(Some(CollisionPolicy::Fail));
+    let mut fh = fs.create_file("file", FSChunker::new(8192)).unwrap();
+    fs.write_to_file(&mut fh, &data).unwrap();
+    let measurements = fs.close_file(fh).unwrap();
+    assert!(measurements.compress_time() > Duration::ZERO);
+    assert!(measurements.encrypt_time() > Duration::ZERO);
+
+    fs.set_pipeline(Some(Pipeline::new(2, Sha256Hasher::default)));
+    let mut fh = fs.create_file("pipelined", FSChunker::new(8192)).unwrap();
+    fs.write_to_file(&mut fh, &data).unwrap();
+    fs.close_file(fh).unwrap();
+
+    // the plaintext is compressed, so the ciphertext is smaller than the chunk
+    assert!(fs.compression_ratio().unwrap() > 2.0);
+    assert_eq!(fs.average_chunk_size().unwrap(), 8192);
+
+    for name in ["file", "pipelined"] {
+        let fh = fs.open_file_readonly(name).unwrap();
+        assert_eq!(fs.read_file_complete(&fh).unwrap(), data);
+    }
+    assert!(fs.decrypt_time() > Duration::ZERO);
+    assert!(fs.decompress_time() > Duration::ZERO);
+}
+
+#[rstest]
+#[case(DiskDatabase::init_on_regular_file(generate_unique_filename(), FILE_SIZE, true).unwrap())]
+#[case(DiskDatabase::init_on_regular_file(generate_unique_filename(), FILE_SIZE, false).unwrap())]
+#[case(HashMap::default())]
+fn filtered_database_keeps_dedup_exact(
+    #[case] db: impl IterableDatabase<[u8; 32], DataContainer<()>>,
+) {
+    let data = (0..4 * MB)
+        .map(|i| ((i / 8192) % 13) as u8)
+        .collect::<Vec<_>>();
+
+    let mut fs = create_cdc_filesystem(
+        FilteredDatabase::new(db, 1024, 0.01),
+        Sha256Hasher::default(),
+    );
+    // collision checks look every chunk up in the database
+    fs.set_collision_policy(Some(CollisionPolicy::Count));
+
+    let mut fh = fs.create_file("file", FSChunker::new(8192)).unwrap();
+    fs.write_to_file(&mut fh, &data).unwrap();
+    fs.write_to_file(&mut fh, &data).unwrap();
+    fs.close_file(fh).unwrap();
+
+    assert_relative_eq!(fs.cdc_dedup_ratio().unwrap(), 1024.0 / 13.0);
+    let fh = fs.open_file_readonly("file").unwrap();
+    assert_eq!(
+        fs.read_file_complete(&fh).unwrap(),
+        [&data[..], &data[..]].concat()
+    );
+}
+
+#[rstest]
+#[case(DiskDatabase::init_on_regular_file(generate_unique_filename(), FILE_SIZE, true).unwrap())]
+#[case(DiskDatabase::init_on_regular_file(generate_unique_filename(), FILE_SIZE, false).unwrap())]
+#[case(HashMap::default())]
+fn repeated_chunks_are_read_from_cache(
+    #[case] db: impl IterableDatabase<[u8; 32], DataContainer<()>>,
+) {
+    let data = (0..4 * MB)
+        .map(|i| ((i / 8192) % 13) as u8)
+        .collect::<Vec<_>>();
+
+    let mut fs = create_cdc_filesystem(CachedDatabase::new(db, MB), Sha256Hasher::default());
+    let mut fh = fs.create_file("file", FSChunker::new(8192)).unwrap();
+    fs.write_to_file(&mut fh, &data).unwrap();
+    fs.close_file(fh).unwrap();
+
+    let fh = fs.open_file_readonly("file").unwrap();
+    assert_eq!(fs.read_file_complete(&fh).unwrap(), data);
+    assert_eq!(fs.read_file_complete(&fh).unwrap(), data);
+
+    // each of 13 unique chunks is fetched once
+    let stats = fs.database().stats();
+    assert_eq!(stats.misses, 13);
+    assert_eq!(stats.hits, 2 * 512 - 13);
+    assert_eq!(stats.evictions, 0);
+}
+
+#[test]
+fn cache_stats_are_reported_by_bench() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("dataset");
+    let data = (0..4 * MB)
+        .map(|i| ((i / 8192) % 13) as u8)
+        .collect::<Vec<_>>();
+    std::fs::write(&path, &data).unwrap();
+    let dataset = Dataset::new(path.to_str().unwrap(), "repeated").unwrap();
+
+    let db = CachedDatabase::new(HashMap::default(), MB);
+    let mut fixture = CDCFixture::new(db, Sha256Hasher::default());
+    let result = fixture.measure(&dataset, FSChunker::new(8192)).unwrap();
+    let stats = result.cache_stats.unwrap();
+    assert_eq!(stats.misses, 13);
+    assert_eq!(stats.hits, 512 - 13);
+
+    let report = dir.path().join("report.csv");
+    result.write_to_csv(&report).unwrap();
+    let mut reader = csv::Reader::from_path(&report).unwrap();
+    let header = reader.headers().unwrap().clone();
+    let row = reader.records().next().unwrap().unwrap();
+    let columns = header.iter().zip(&row).collect::<HashMap<_, _>>();
+    assert_eq!(columns["cache_hits"], "499");
+    assert_eq!(columns["cache_misses"], "13");
+
+    let plain = CDCFixture::new(HashMap::default(), Sha256Hasher::default())
+        .measure(&dataset, FSChunker::new(8192))
+        .unwrap();
+    assert!(plain.cache_stats.is_none());
+}
+
+#[rstest]
+#[case(true)]
+#[case(false)]
+fn containers_are_read_whole(#[case] o_direct: bool) {
+    let data = (0..2 * MB)
+        .map(|i| (i % 251) as u8 ^ (i / 8192) as u8)
+        .collect::<Vec<_>>();
+
+    let db = DiskDatabase::init_on_regular_file(generate_unique_filename(), FILE_SIZE, o_direct)
+        .unwrap()
+        .with_containers(MB as u64, 1)
+        .unwrap();
+    let mut fs = create_cdc_filesystem(db, Sha256Hasher::default());
+    let mut fh = fs.create_file("file", FSChunker::new(8192)).unwrap();
+    fs.write_to_file(&mut fh, &data).unwrap();
+    fs.close_file(fh).unwrap();
+
+    let fh = fs.open_file_readonly("file").unwrap();
+    assert_eq!(fs.read_file_complete(&fh).unwrap(), data);
+    // 256 chunks take 3 containers, the last one is still in memory
+    assert_eq!(fs.database().container_reads(), 2);
+}